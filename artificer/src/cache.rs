@@ -0,0 +1,353 @@
+//! Content-addressed cache of downloaded artifacts, backed by [`cacache`].
+//!
+//! Artifacts are stored by their integrity, and indexed by [`Source::cache_key`]
+//! so that artifacts without a known hash can still be found.
+
+use std::{
+    collections::HashSet,
+    fs::File,
+    path::{Path, PathBuf},
+};
+
+use cacache::{Algorithm, Integrity, Metadata};
+use color_eyre::{
+    eyre::{eyre, WrapErr},
+    Result,
+};
+use fs4::FileExt;
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncRead;
+
+use crate::config::{sources::Source, ArtifactName, Hash};
+
+#[derive(Debug, Clone)]
+pub struct Cache {
+    dir: PathBuf,
+}
+
+impl Cache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// `$XDG_CACHE_HOME/artificer/store`, falling back to `$HOME/.cache`.
+    pub fn default_dir() -> Result<PathBuf> {
+        let cache_home = match std::env::var_os("XDG_CACHE_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => std::env::var_os("HOME")
+                .map(|home| PathBuf::from(home).join(".cache"))
+                .ok_or_else(|| eyre!("neither XDG_CACHE_HOME nor HOME are set"))?,
+        };
+        Ok(cache_home.join("artificer").join("store"))
+    }
+
+    /// Finds the cached integrity of the artifact at `source`, if it is cached.
+    ///
+    /// Artifacts with a known hash are looked up by content. Otherwise they are
+    /// looked up by source, unless `refresh` is set or the artifact has
+    /// `hash = false`, in which case they are always redownloaded. Local artifacts
+    /// are cheap to reread and may change, so they are never looked up by source.
+    /// When `offline`, any cached version is accepted.
+    pub async fn lookup(
+        &self,
+        source: &Source,
+        hash: Option<&Hash>,
+        refresh: bool,
+        offline: bool,
+    ) -> Result<Option<Integrity>> {
+        if let Some(Hash::Hash(integrity)) = hash {
+            return Ok(cacache::exists(&self.dir, integrity)
+                .await
+                .then(|| integrity.clone()));
+        }
+        let by_source = offline
+            || match hash {
+                Some(Hash::False) => false,
+                _ => !refresh && !source.is_local(),
+            };
+        if !by_source {
+            return Ok(None);
+        }
+
+        let metadata = cacache::metadata(&self.dir, source.cache_key())
+            .await
+            .wrap_err("failed to read cache index")?;
+        Ok(metadata.map(|m| m.integrity))
+    }
+
+    /// Streams `reader` into the cache, indexed by `source`. If `size` is given,
+    /// errors if the number of bytes read doesn't match it.
+    pub async fn insert(
+        &self,
+        source: &Source,
+        algorithm: Algorithm,
+        size: Option<u64>,
+        mut reader: impl AsyncRead + Unpin,
+    ) -> Result<Integrity> {
+        let opts = cacache::WriteOpts::new().algorithm(algorithm);
+        let opts = match size {
+            Some(size) => opts.size(size.try_into().wrap_err("artifact too big")?),
+            None => opts,
+        };
+        let mut writer = opts
+            .open(&self.dir, source.cache_key())
+            .await
+            .wrap_err("failed to open cache writer")?;
+        tokio::io::copy(&mut reader, &mut writer)
+            .await
+            .wrap_err("failed to write body to cache")?;
+        writer.commit().await.wrap_err("failed to commit to cache")
+    }
+
+    /// Opens a cached artifact for reading. Call [`cacache::Reader::check`] after
+    /// reading to detect corrupted entries.
+    pub async fn open(&self, integrity: &Integrity) -> Result<cacache::Reader> {
+        cacache::Reader::open_hash(&self.dir, integrity.clone())
+            .await
+            .wrap_err("failed to open cached artifact")
+    }
+
+    /// Reads a small cached entry into memory, verifying it.
+    pub async fn read(&self, integrity: &Integrity) -> Result<Vec<u8>> {
+        cacache::read_hash(&self.dir, integrity)
+            .await
+            .wrap_err("failed to read cached entry")
+    }
+
+    /// Removes the index entry for `source`, so that it is no longer found by
+    /// [`Self::lookup`] unless its hash is known.
+    pub async fn forget(&self, source: &Source) -> Result<()> {
+        cacache::remove(&self.dir, source.cache_key())
+            .await
+            .wrap_err("failed to remove cache entry")
+    }
+
+    /// Where an incomplete download of the artifact `name` from `source` is kept,
+    /// so that it can be resumed instead of starting over. Hold
+    /// [`Self::lock_partial`] while using it.
+    pub fn partial_path(&self, name: &ArtifactName, source: &Source) -> PathBuf {
+        let key = hex::encode(Sha256::digest(format!(
+            "{}\0{}",
+            name.0,
+            source.cache_key()
+        )));
+        self.dir.join("partial").join(key)
+    }
+
+    /// Locks the partial download at `partial_path` against other artificer runs
+    /// sharing this cache, waiting for them to finish with it if needed. The lock
+    /// is held until the returned file is dropped.
+    pub async fn lock_partial(&self, partial_path: &Path) -> Result<File> {
+        let lock_path = partial_path.with_extension("lock");
+        let lock = std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&lock_path)
+            .wrap_err("failed to open partial download lock")?;
+        if lock.try_lock_exclusive().is_ok() {
+            return Ok(lock);
+        }
+        tracing::info!(
+            "waiting for another download into {} to finish",
+            partial_path.display()
+        );
+        tokio::task::spawn_blocking(move || lock.lock_exclusive().map(|()| lock))
+            .await
+            .wrap_err("lock task panicked")?
+            .wrap_err("failed to lock partial download")
+    }
+
+    /// Removes cached content.
+    pub async fn remove(&self, integrity: &Integrity) -> Result<()> {
+        cacache::remove_hash(&self.dir, integrity)
+            .await
+            .wrap_err("failed to remove cached content")
+    }
+
+    fn is_empty(&self) -> Result<bool> {
+        match std::fs::read_dir(&self.dir) {
+            Ok(mut entries) => Ok(entries.next().is_none()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(true),
+            Err(err) => Err(err).wrap_err("failed to read cache dir"),
+        }
+    }
+
+    /// Lists all index entries in the cache.
+    pub fn list(&self) -> Result<Vec<Metadata>> {
+        if self.is_empty()? {
+            return Ok(Vec::new());
+        }
+        cacache::list_sync(&self.dir)
+            .collect::<Result<_, _>>()
+            .wrap_err("failed to list cache entries")
+    }
+
+    /// Removes every entry whose content is not in `keep`. Returns the removed
+    /// entries.
+    pub async fn gc(&self, keep: &HashSet<Integrity>) -> Result<Vec<Metadata>> {
+        let mut removed = Vec::new();
+        for entry in self.list()? {
+            if keep.contains(&entry.integrity) {
+                continue;
+            }
+            cacache::remove(&self.dir, &entry.key)
+                .await
+                .wrap_err("failed to remove cache entry")?;
+            // Several keys may share the same content.
+            if cacache::exists(&self.dir, &entry.integrity).await {
+                self.remove(&entry.integrity).await?;
+            }
+            removed.push(entry);
+        }
+        Ok(removed)
+    }
+
+    /// Deletes the entire cache.
+    pub async fn clear(&self) -> Result<()> {
+        if self.is_empty()? {
+            return Ok(());
+        }
+        cacache::clear(&self.dir)
+            .await
+            .wrap_err("failed to clear cache")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashSet, time::Duration};
+
+    use cacache::{Algorithm, Integrity};
+    use color_eyre::Result;
+    use tokio::io::AsyncReadExt;
+
+    use super::Cache;
+    use crate::config::{
+        sources::{Github, Source},
+        ArtifactName, Hash,
+    };
+
+    fn github(tag: &str) -> Source {
+        Source::Github(Github {
+            repo: "worldcoin/orb-software".to_owned(),
+            tag: Some(tag.to_owned()),
+            version: None,
+            artifact: "foo".to_owned(),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_partial_download_is_locked() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let cache = Cache::new(tmp.path());
+        let source = github("v1");
+        let foo = cache.partial_path(&ArtifactName("foo".to_owned()), &source);
+        let bar = cache.partial_path(&ArtifactName("bar".to_owned()), &source);
+        assert_ne!(
+            foo, bar,
+            "artifacts with the same source share a partial file"
+        );
+        std::fs::create_dir_all(foo.parent().unwrap())?;
+
+        let lock = cache.lock_partial(&foo).await?;
+        let _other = cache.lock_partial(&bar).await?;
+        let waiting = tokio::spawn({
+            let cache = cache.clone();
+            async move { cache.lock_partial(&foo).await }
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!waiting.is_finished(), "lock was taken twice");
+        drop(lock);
+        waiting.await??;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_insert_and_lookup() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let cache = Cache::new(tmp.path());
+        let source = github("v1");
+        let data = b"hello world";
+
+        assert_eq!(cache.lookup(&source, None, false, false).await?, None);
+        let sri = cache
+            .insert(&source, Algorithm::Sha256, Some(11), &data[..])
+            .await?;
+        assert_eq!(sri, Integrity::from(data));
+
+        let by_hash = Hash::Hash(sri.clone());
+        let lookup = |hash, refresh, offline| {
+            let cache = &cache;
+            let source = &source;
+            async move { cache.lookup(source, hash, refresh, offline).await }
+        };
+        assert_eq!(lookup(None, false, false).await?, Some(sri.clone()));
+        assert_eq!(
+            lookup(Some(&by_hash), true, false).await?,
+            Some(sri.clone())
+        );
+        assert_eq!(lookup(None, true, false).await?, None);
+        assert_eq!(lookup(None, true, true).await?, Some(sri.clone()));
+        assert_eq!(lookup(Some(&Hash::False), false, false).await?, None);
+        assert_eq!(
+            lookup(Some(&Hash::False), false, true).await?,
+            Some(sri.clone())
+        );
+        assert_eq!(
+            cache.lookup(&github("v2"), None, false, false).await?,
+            None,
+            "different source should not be found"
+        );
+
+        let mut contents = Vec::new();
+        let mut reader = cache.open(&sri).await?;
+        reader.read_to_end(&mut contents).await?;
+        reader.check()?;
+        assert_eq!(contents, data);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_insert_size_mismatch() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let cache = Cache::new(tmp.path());
+        let source = github("v1");
+
+        assert!(cache
+            .insert(&source, Algorithm::Sha256, Some(3), &b"hello"[..])
+            .await
+            .is_err());
+        assert_eq!(cache.lookup(&source, None, false, false).await?, None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_gc_and_clear() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let cache = Cache::new(tmp.path().join("store"));
+        assert!(cache.list()?.is_empty());
+
+        let keep = cache
+            .insert(&github("v1"), Algorithm::Sha256, None, &b"keep"[..])
+            .await?;
+        let drop = cache
+            .insert(&github("v2"), Algorithm::Sha256, None, &b"drop"[..])
+            .await?;
+        assert_eq!(cache.list()?.len(), 2);
+
+        let removed = cache.gc(&HashSet::from([keep.clone()])).await?;
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0].integrity, drop);
+        assert!(cacache::exists(tmp.path().join("store"), &keep).await);
+        assert!(!cacache::exists(tmp.path().join("store"), &drop).await);
+
+        cache.clear().await?;
+        assert!(cache.list()?.is_empty());
+
+        Ok(())
+    }
+}
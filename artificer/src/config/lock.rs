@@ -1,36 +1,161 @@
 //! Schema for artificer.lock and out.lock
 
-use std::collections::HashMap;
+use std::{collections::BTreeMap, str::FromStr};
 
+use color_eyre::{
+    eyre::{bail, WrapErr},
+    Result,
+};
 use serde::{Deserialize, Serialize};
 
 use super::sources::Source;
-use super::spec::Artifact;
+use super::spec::{Hash, Spec};
 use super::ArtifactName;
 
+/// The current version of the lockfile syntax.
+pub const LOCKFILE_VERSION: u8 = 0;
+
+const LOCKFILE_HEADER: &str = "# NOTE: This file is autogenerated\n";
+
 /// The locks for a [`Spec`]. Stored in the lockfile, aka `artificer.lock`. Specifies
 /// last observed hashes for every artifact.
 #[derive(Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct LockedSpec {
     /// The version of the overall lockfile syntax
     pub version: u8,
-    pub artifacts: HashMap<ArtifactName, Artifact>,
+    pub artifacts: BTreeMap<ArtifactName, LockedArtifact>,
+}
+
+impl FromStr for LockedSpec {
+    type Err = color_eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        toml::from_str(s).wrap_err("failed to parse lockfile toml")
+    }
+}
+
+impl LockedSpec {
+    pub fn new() -> Self {
+        Self {
+            version: LOCKFILE_VERSION,
+            artifacts: BTreeMap::new(),
+        }
+    }
+
+    /// Serializes the lockfile, including its header.
+    pub fn to_toml(&self) -> Result<String> {
+        let body = toml::to_string(self).wrap_err("failed to serialize lockfile")?;
+        Ok(format!("{LOCKFILE_HEADER}{body}"))
+    }
+
+    /// Errors if the lockfile doesn't describe exactly the artifacts in `spec`,
+    /// similar to `cargo --locked`.
+    pub fn check_up_to_date(&self, spec: &Spec) -> Result<()> {
+        if self.version != LOCKFILE_VERSION {
+            bail!(
+                "unsupported lockfile version {}, expected {LOCKFILE_VERSION}",
+                self.version
+            );
+        }
+
+        let mut problems = Vec::new();
+        for (name, artifact) in &spec.artifacts {
+            let Some(locked) = self.artifacts.get(name) else {
+                problems.push(format!("`{}` is missing from the lockfile", name.0));
+                continue;
+            };
+            if locked.source != artifact.source {
+                problems.push(format!(
+                    "`{}` has a different source in the lockfile",
+                    name.0
+                ));
+            } else if artifact.source.needs_resolving() && locked.resolved_tag.is_none()
+            {
+                problems
+                    .push(format!("`{}` has no resolved tag in the lockfile", name.0));
+            }
+            if let Some(Hash::Hash(expected)) = &artifact.hash {
+                if expected.matches(&locked.hash).is_none() {
+                    problems.push(format!(
+                        "`{}` has hash {expected} in the spec but {} in the lockfile",
+                        name.0, locked.hash
+                    ));
+                }
+            }
+        }
+        for name in self.artifacts.keys() {
+            if !spec.artifacts.contains_key(name) {
+                problems
+                    .push(format!("`{}` is in the lockfile but not the spec", name.0));
+            }
+        }
+
+        if !problems.is_empty() {
+            problems.sort();
+            bail!(
+                "the lockfile is out of date:\n  - {}\nrun `artificer lock` or \
+                `artificer update <name>...` to update it",
+                problems.join("\n  - ")
+            );
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for LockedSpec {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 /// An artifact in the lock file.
-#[derive(Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Eq, PartialEq, Serialize, Deserialize, Clone)]
 pub struct LockedArtifact {
+    #[serde(flatten)]
     pub source: Source,
+    /// The tag that a github source's `version` resolved to.
+    #[serde(
+        rename = "resolved-tag",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub resolved_tag: Option<String>,
     pub hash: cacache::Integrity,
 }
 
+impl LockedArtifact {
+    /// Locks `source` at `hash`. `resolved` is `source` with its version
+    /// requirements resolved, see [`Source::needs_resolving`].
+    pub fn new(source: Source, resolved: &Source, hash: cacache::Integrity) -> Self {
+        let resolved_tag = match resolved {
+            Source::Github(gh) if source.needs_resolving() => gh.tag.clone(),
+            _ => None,
+        };
+        Self {
+            source,
+            resolved_tag,
+            hash,
+        }
+    }
+
+    /// The concrete source to download the locked artifact from.
+    pub fn resolved_source(&self) -> Source {
+        match (&self.source, &self.resolved_tag) {
+            (Source::Github(gh), Some(tag)) => Source::Github(gh.with_tag(tag.clone())),
+            (source, _) => source.clone(),
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use std::path::Path;
 
     use color_eyre::{eyre::WrapErr, Result};
 
-    use super::LockedSpec;
+    use super::{LockedArtifact, LockedSpec};
+    use crate::config::{sources::Source, Hash, Spec};
 
     fn deserialize_example_lockfile() -> Result<LockedSpec> {
         let path = Path::new(concat!(
@@ -53,4 +178,109 @@ mod test {
 
         Ok(())
     }
+
+    fn deserialize_example_spec() -> Result<Spec> {
+        let path = Path::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/src/config/example.toml"
+        ));
+        let file_contents = std::fs::read_to_string(path)?;
+        toml::from_str(&file_contents).wrap_err("failed to deserialize example spec")
+    }
+
+    #[test]
+    fn test_to_toml_roundtrip() -> Result<()> {
+        let locked = deserialize_example_lockfile()?;
+        let serialized = locked.to_toml()?;
+        assert!(serialized.starts_with("# NOTE: This file is autogenerated"));
+        assert_eq!(locked, toml::from_str(&serialized)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_up_to_date() -> Result<()> {
+        let spec = deserialize_example_spec()?;
+        let mut locked = LockedSpec::new();
+        for (name, artifact) in &spec.artifacts {
+            let hash = match &artifact.hash {
+                Some(Hash::Hash(h)) => h.clone(),
+                _ => cacache::Integrity::from(name.0.as_bytes()),
+            };
+            locked.artifacts.insert(
+                name.clone(),
+                super::LockedArtifact {
+                    source: artifact.source.clone(),
+                    resolved_tag: artifact
+                        .source
+                        .needs_resolving()
+                        .then(|| "v1.0.0".to_owned()),
+                    hash,
+                },
+            );
+        }
+        locked.check_up_to_date(&spec)?;
+
+        // Extra artifacts in the lockfile are an error
+        let mut extra = LockedSpec::new();
+        extra.artifacts.clone_from(&locked.artifacts);
+        let (_, any) = locked.artifacts.iter().next().unwrap();
+        extra
+            .artifacts
+            .insert(crate::config::ArtifactName("extra".to_owned()), any.clone());
+        assert!(extra.check_up_to_date(&spec).is_err());
+
+        // Missing artifacts in the lockfile are an error
+        let mut missing = LockedSpec::new();
+        missing.artifacts.clone_from(&locked.artifacts);
+        missing.artifacts.pop_first();
+        assert!(missing.check_up_to_date(&spec).is_err());
+
+        // Hashes that disagree with the spec are an error
+        let mut wrong_hash = LockedSpec::new();
+        wrong_hash.artifacts.clone_from(&locked.artifacts);
+        for artifact in wrong_hash.artifacts.values_mut() {
+            artifact.hash = cacache::Integrity::from(b"wrong");
+        }
+        assert!(wrong_hash.check_up_to_date(&spec).is_err());
+
+        // Versioned github sources must have been resolved
+        let mut unresolved = LockedSpec::new();
+        unresolved.artifacts.clone_from(&locked.artifacts);
+        for artifact in unresolved.artifacts.values_mut() {
+            artifact.resolved_tag = None;
+        }
+        assert!(unresolved.check_up_to_date(&spec).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolved_source() -> Result<()> {
+        let locked: LockedArtifact = toml::from_str(
+            r#"
+            source = "github"
+            repo = "worldcoin/orb-mcu-firmware"
+            version = "^1.4"
+            artifact = "main-board.bin"
+            resolved-tag = "v1.4.2"
+            hash = "sha256-uU0nuZNNPgilLlLX2n2r+sSE7+N6U4DukIj3rOLvzek="
+            "#,
+        )?;
+        let Source::Github(resolved) = locked.resolved_source() else {
+            panic!("expected a github source");
+        };
+        assert_eq!(resolved.tag.as_deref(), Some("v1.4.2"));
+        assert_eq!(resolved.version, None);
+        assert_eq!(
+            LockedArtifact::new(
+                locked.source.clone(),
+                &Source::Github(resolved),
+                locked.hash.clone()
+            ),
+            locked
+        );
+
+        Ok(())
+    }
 }
@@ -4,9 +4,11 @@ mod lock;
 pub mod sources;
 mod spec;
 
-pub use self::lock::LockedSpec;
-pub use self::spec::Spec;
+pub use self::lock::{LockedArtifact, LockedSpec};
+pub use self::spec::{Artifact, CustomExtractor, ExtractorName, Hash, Signature, Spec};
 
 /// `[artifacts.<artifact-name>]`. See also, [`Artifact`].
-#[derive(Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[derive(
+    Debug, Eq, PartialEq, Hash, Ord, PartialOrd, Serialize, Deserialize, Clone,
+)]
 pub struct ArtifactName(pub String);
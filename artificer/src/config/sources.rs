@@ -1,17 +1,184 @@
 //! The different supported artifact sources
 
+use std::{fmt, path::PathBuf, str::FromStr};
+
+use semver::{Version, VersionReq};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Eq, PartialEq, Serialize, Deserialize, Hash, Clone)]
-#[serde(rename_all = "lowercase")]
+#[serde(rename_all = "kebab-case")]
 #[serde(tag = "source")]
 pub enum Source {
     Github(Github),
+    GithubActions(GithubActions),
+    Url(Url),
+    S3(S3),
+    Path(LocalPath),
+}
+
+impl Source {
+    /// Uniquely identifies the artifact at this source. Used to find the artifact
+    /// in the cache when its hash is not known.
+    pub fn cache_key(&self) -> String {
+        match self {
+            Source::Github(gh) => {
+                let tag = match (&gh.tag, &gh.version) {
+                    (Some(tag), _) => tag.clone(),
+                    (None, Some(version)) => version.to_string(),
+                    (None, None) => String::new(),
+                };
+                format!("github:{}@{tag}/{}", gh.repo, gh.artifact)
+            }
+            Source::GithubActions(gha) => format!(
+                "github-actions:{}@{}/{}",
+                gha.repo, gha.run_id, gha.artifact
+            ),
+            Source::Url(url) => format!("url:{}", url.url),
+            Source::S3(s3) => format!(
+                "s3:{}/{}/{}",
+                s3.endpoint.as_deref().unwrap_or(&s3.region),
+                s3.bucket,
+                s3.key
+            ),
+            Source::Path(path) => format!("path:{}", path.path.display()),
+        }
+    }
+
+    /// Whether the artifact is on the local filesystem, in which case it is cheap
+    /// to read and never looked up in the cache by its source.
+    pub fn is_local(&self) -> bool {
+        matches!(self, Source::Path(_))
+    }
+
+    /// Whether the source refers to a version requirement rather than a concrete
+    /// release, and must be resolved before downloading.
+    pub fn needs_resolving(&self) -> bool {
+        matches!(
+            self,
+            Source::Github(Github {
+                version: Some(_),
+                ..
+            })
+        )
+    }
 }
 
+/// A github release asset. `source = "github"`.
+///
+/// The release is given by exactly one of `tag` and `version`.
 #[derive(Debug, Eq, PartialEq, Serialize, Deserialize, Hash, Clone)]
 pub struct Github {
     pub repo: String,
-    pub tag: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    /// Use the release with the highest semver tag matching this.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<VersionSpec>,
     pub artifact: String,
 }
+
+impl Github {
+    /// This source, pinned to the release with `tag`.
+    pub fn with_tag(&self, tag: String) -> Self {
+        Self {
+            tag: Some(tag),
+            version: None,
+            ..self.clone()
+        }
+    }
+}
+
+/// A semver requirement on release tags, like `^1.4`, or `latest` for the
+/// highest non-prerelease version. Tags may be prefixed with `v`.
+#[derive(Debug, Eq, PartialEq, Hash, Clone, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum VersionSpec {
+    Latest,
+    Req(VersionReq),
+}
+
+impl VersionSpec {
+    pub fn matches(&self, version: &Version) -> bool {
+        match self {
+            Self::Latest => version.pre.is_empty(),
+            Self::Req(req) => req.matches(version),
+        }
+    }
+}
+
+impl FromStr for VersionSpec {
+    type Err = semver::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "latest" {
+            Ok(Self::Latest)
+        } else {
+            s.parse().map(Self::Req)
+        }
+    }
+}
+
+impl TryFrom<String> for VersionSpec {
+    type Error = semver::Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<VersionSpec> for String {
+    fn from(v: VersionSpec) -> Self {
+        v.to_string()
+    }
+}
+
+impl fmt::Display for VersionSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Latest => f.write_str("latest"),
+            Self::Req(req) => req.fmt(f),
+        }
+    }
+}
+
+/// An artifact uploaded by a github actions workflow run.
+/// `source = "github-actions"`. These are always zip files.
+#[derive(Debug, Eq, PartialEq, Serialize, Deserialize, Hash, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct GithubActions {
+    pub repo: String,
+    pub run_id: u64,
+    pub artifact: String,
+}
+
+/// A plain http(s) url. `source = "url"`.
+#[derive(Debug, Eq, PartialEq, Serialize, Deserialize, Hash, Clone)]
+pub struct Url {
+    pub url: String,
+}
+
+/// An object in an S3-compatible object store. `source = "s3"`.
+///
+/// Requests are signed if `AWS_ACCESS_KEY_ID` and `AWS_SECRET_ACCESS_KEY` are set,
+/// and anonymous otherwise.
+#[derive(Debug, Eq, PartialEq, Serialize, Deserialize, Hash, Clone)]
+pub struct S3 {
+    pub bucket: String,
+    pub key: String,
+    #[serde(default = "default_s3_region")]
+    pub region: String,
+    /// Base url of the object store. Defaults to AWS for `region`. Objects are
+    /// addressed as `<endpoint>/<bucket>/<key>`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub endpoint: Option<String>,
+}
+
+fn default_s3_region() -> String {
+    "us-east-1".to_owned()
+}
+
+/// A file on the local filesystem, relative to the spec. `source = "path"`.
+#[derive(Debug, Eq, PartialEq, Serialize, Deserialize, Hash, Clone)]
+pub struct LocalPath {
+    pub path: PathBuf,
+}
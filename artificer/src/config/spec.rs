@@ -2,10 +2,17 @@
 
 use std::{collections::HashMap, path::PathBuf, str::FromStr};
 
+use color_eyre::{
+    eyre::{bail, WrapErr},
+    Result,
+};
 use semver::Version;
 use serde::{Deserialize, Deserializer, Serialize};
 
-use super::{sources::Source, ArtifactName};
+use super::{
+    sources::{Github, Source, Url},
+    ArtifactName,
+};
 
 /// The spec file, aka `artificer.toml`. Describes the full set of artifacts
 /// to download.
@@ -16,6 +23,14 @@ pub struct Spec {
     pub extractors: HashMap<ExtractorName, CustomExtractor>,
 }
 
+impl FromStr for Spec {
+    type Err = color_eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        toml::from_str(s).wrap_err("failed to parse spec toml")
+    }
+}
+
 /// `[artificer]` toplevel table
 #[derive(Debug, Eq, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
@@ -31,10 +46,48 @@ pub struct Artifact {
     pub source: Source,
     pub hash: Option<Hash>,
     pub extractor: Option<ExtractorName>,
+    pub signature: Option<Signature>,
+}
+
+/// A detached [minisign](https://jedisct1.github.io/minisign/) signature of an
+/// artifact, which must be valid for `public-key` before the artifact is written
+/// to the out dir.
+///
+/// The signature is downloaded from exactly one of `asset`, an asset of the same
+/// github release as the artifact, and `url`.
+#[derive(Debug, Eq, PartialEq, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct Signature {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub asset: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// The trusted key, as in the last line of a minisign `.pub` file.
+    pub public_key: String,
+}
+
+impl Signature {
+    /// Where to download the signature of the artifact at `artifact`, which should
+    /// already be resolved.
+    pub fn source(&self, artifact: &Source) -> Result<Source> {
+        match (&self.asset, &self.url, artifact) {
+            (Some(_), Some(_), _) | (None, None, _) => {
+                bail!("signatures must have exactly one of `asset` and `url`")
+            }
+            (Some(asset), None, Source::Github(gh)) => Ok(Source::Github(Github {
+                artifact: asset.clone(),
+                ..gh.clone()
+            })),
+            (Some(_), None, _) => {
+                bail!("`asset` signatures are only supported for github sources")
+            }
+            (None, Some(url), _) => Ok(Source::Url(Url { url: url.clone() })),
+        }
+    }
 }
 
 /// `[extractors.<extractor-name>]`. See [`CustomExtractor`] for custom extractors.
-/// Note that there also will be built-in extractors in the future.
+/// The built-in extractors are `tar.gz`, `tar.zst`, and `zip`.
 #[derive(Debug, Eq, PartialEq, Hash, Serialize, Deserialize, Clone)]
 pub struct ExtractorName(pub String);
 
@@ -122,7 +175,8 @@ mod test {
 
     use color_eyre::{eyre::WrapErr, Result};
 
-    use super::Spec;
+    use super::{Signature, Spec};
+    use crate::config::sources::{Github, LocalPath, Source, Url};
 
     fn deserialize_example_spec() -> Result<Spec> {
         let path = Path::new(concat!(
@@ -144,4 +198,44 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn test_signature_source() -> Result<()> {
+        let artifact = Source::Github(Github {
+            repo: "worldcoin/orb-mcu-firmware".to_owned(),
+            tag: Some("v1.4.2".to_owned()),
+            version: None,
+            artifact: "main-board.signed.bin".to_owned(),
+        });
+        let signature = |asset: Option<&str>, url: Option<&str>| Signature {
+            asset: asset.map(str::to_owned),
+            url: url.map(str::to_owned),
+            public_key: String::new(),
+        };
+
+        let Source::Github(asset) =
+            signature(Some("main-board.signed.bin.minisig"), None).source(&artifact)?
+        else {
+            panic!("asset signatures should be github sources");
+        };
+        assert_eq!(asset.tag.as_deref(), Some("v1.4.2"));
+        assert_eq!(asset.artifact, "main-board.signed.bin.minisig");
+
+        let url = "https://example.com/main-board.signed.bin.minisig";
+        assert_eq!(
+            signature(None, Some(url)).source(&artifact)?,
+            Source::Url(Url {
+                url: url.to_owned()
+            })
+        );
+
+        assert!(signature(None, None).source(&artifact).is_err());
+        assert!(signature(Some("a"), Some(url)).source(&artifact).is_err());
+        let path = Source::Path(LocalPath {
+            path: "main-board.signed.bin".into(),
+        });
+        assert!(signature(Some("a"), None).source(&path).is_err());
+
+        Ok(())
+    }
 }
@@ -1,26 +1,25 @@
 use color_eyre::{
-    eyre::{bail, WrapErr},
+    eyre::{bail, eyre, WrapErr},
     Result,
 };
-use futures::{StreamExt, TryStreamExt};
-use tokio::io::AsyncRead;
-use tokio_util::compat::FuturesAsyncReadCompatExt;
 
-use crate::config::sources;
+use crate::config::sources::{self, VersionSpec};
 
-use super::Client;
+use super::{Client, Download};
 
 pub async fn download_artifact(
     client: &Client,
     source: sources::Github,
-) -> Result<(Box<dyn AsyncRead + Send>, u64)> {
-    let Some((owner, repo)) = source.repo.split_once('/') else {
-        bail!("invalid repo url: expected `/` but was not present");
+    offset: u64,
+) -> Result<Download> {
+    let Some(tag) = &source.tag else {
+        bail!("github source has no tag, it must be resolved first");
     };
+    let (owner, repo) = split_repo(&source.repo)?;
     let repos = client.octo.repos(owner, repo);
     let release = repos
         .releases()
-        .get_by_tag(&source.tag)
+        .get_by_tag(tag)
         .await
         .wrap_err("could not get release")?;
     let Some(ass) = release.assets.iter().find(|a| a.name == source.artifact) else {
@@ -39,23 +38,91 @@ pub async fn download_artifact(
         .header("X-GitHub-Api-Version", "2022-11-28")
         .header("Accept", "application/octet-stream");
 
-    tracing::debug!("sending request: {req:?}");
-    let response = req
+    let response = super::send(req, offset)
+        .await
+        .wrap_err("failed to send download request")?;
+
+    let mut download = Download::from_response(response);
+    download.size = Some(total_bytes.try_into().expect("should have converted"));
+    Ok(download)
+}
+
+/// Finds the tag of the newest release matching `version`.
+pub async fn resolve_tag(
+    client: &Client,
+    repo: &str,
+    version: &VersionSpec,
+) -> Result<String> {
+    let (owner, repo) = split_repo(repo)?;
+    let page = client
+        .octo
+        .repos(owner, repo)
+        .releases()
+        .list()
+        .per_page(100)
         .send()
         .await
-        .wrap_err("failed to send download request")?
-        .error_for_status()?;
-
-    // convert from stream to tokio reader via `futures` and `tokio_util`
-    let reader = response
-        .bytes_stream()
-        .map(|result| {
-            result.map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err))
+        .wrap_err("could not list releases")?;
+    let releases = client
+        .octo
+        .all_pages(page)
+        .await
+        .wrap_err("could not list releases")?;
+    let tags = releases
+        .iter()
+        .filter(|r| !r.draft && !r.prerelease)
+        .map(|r| r.tag_name.as_str());
+
+    match pick_tag(tags, version) {
+        Some(tag) => Ok(tag.to_owned()),
+        None => bail!("no release of {owner}/{repo} matches version `{version}`"),
+    }
+}
+
+/// Picks the tag with the highest semver version matching `version`. Tags may be
+/// prefixed with `v`, tags that aren't semver versions are ignored.
+fn pick_tag<'a>(
+    tags: impl IntoIterator<Item = &'a str>,
+    version: &VersionSpec,
+) -> Option<&'a str> {
+    tags.into_iter()
+        .filter_map(|tag| {
+            let parsed = semver::Version::parse(tag.strip_prefix('v').unwrap_or(tag));
+            parsed.ok().map(|v| (v, tag))
         })
-        .into_async_read()
-        .compat();
-    Ok((
-        Box::new(reader),
-        total_bytes.try_into().expect("should have converted"),
-    ))
+        .filter(|(v, _)| version.matches(v))
+        .max_by(|(a, _), (b, _)| a.cmp(b))
+        .map(|(_, tag)| tag)
+}
+
+fn split_repo(repo: &str) -> Result<(&str, &str)> {
+    repo.split_once('/')
+        .ok_or_else(|| eyre!("invalid repo url: expected `/` but was not present"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::pick_tag;
+    use crate::config::sources::VersionSpec;
+
+    #[test]
+    fn test_pick_tag() {
+        let tags = [
+            "v1.3.9",
+            "v1.4.0",
+            "1.4.2",
+            "v1.5.0-rc.1",
+            "v2.0.0",
+            "nightly",
+            "v1.4.1",
+        ];
+        let pick = |version: &str| pick_tag(tags, &version.parse().unwrap());
+
+        assert_eq!(pick("~1.4"), Some("1.4.2"));
+        assert_eq!(pick("^1.4"), Some("1.4.2"), "prereleases are skipped");
+        assert_eq!(pick("=1.4.0"), Some("v1.4.0"));
+        assert_eq!(pick("latest"), Some("v2.0.0"));
+        assert_eq!(pick("^3"), None);
+        assert_eq!(pick_tag(["nightly"], &VersionSpec::Latest), None);
+    }
 }
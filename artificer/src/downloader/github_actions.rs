@@ -0,0 +1,124 @@
+use color_eyre::{
+    eyre::{bail, eyre, WrapErr},
+    Result,
+};
+use serde::Deserialize;
+
+use crate::config::sources;
+
+use super::{Client, Download};
+
+/// Response of the "list workflow run artifacts" github api.
+#[derive(Debug, Deserialize)]
+struct ArtifactList {
+    artifacts: Vec<WorkflowArtifact>,
+}
+
+#[derive(Debug, Deserialize)]
+struct WorkflowArtifact {
+    name: String,
+    archive_download_url: String,
+    expired: bool,
+}
+
+pub async fn download_artifact(
+    client: &Client,
+    source: &sources::GithubActions,
+    offset: u64,
+) -> Result<Download> {
+    let Some(token) = client.gh_token.as_deref() else {
+        bail!("downloading github actions artifacts requires a github token");
+    };
+    if !source.repo.contains('/') {
+        bail!("invalid repo url: expected `/` but was not present");
+    }
+
+    let list_url = format!(
+        "https://api.github.com/repos/{}/actions/runs/{}/artifacts",
+        source.repo, source.run_id
+    );
+    let req = client
+        .reqwest
+        .get(list_url)
+        .query(&[("name", &source.artifact)])
+        .bearer_auth(token)
+        .header("X-GitHub-Api-Version", "2022-11-28")
+        .header("Accept", "application/vnd.github+json");
+    let response = super::send(req, 0)
+        .await
+        .wrap_err("failed to list workflow run artifacts")?;
+    let list: ArtifactList = serde_json::from_slice(
+        &response
+            .bytes()
+            .await
+            .wrap_err("failed to read artifact list")?,
+    )
+    .wrap_err("failed to parse artifact list")?;
+    let artifact = find_artifact(list, &source.artifact)?;
+
+    // Github redirects to a short lived blob storage url.
+    let req = client
+        .reqwest
+        .get(artifact.archive_download_url)
+        .bearer_auth(token)
+        .header("X-GitHub-Api-Version", "2022-11-28");
+    let response = super::send(req, offset)
+        .await
+        .wrap_err("failed to send download request")?;
+
+    Ok(Download::from_response(response))
+}
+
+fn find_artifact(list: ArtifactList, name: &str) -> Result<WorkflowArtifact> {
+    let mut matching = list.artifacts.into_iter().filter(|a| a.name == name);
+    let artifact = matching
+        .next()
+        .ok_or_else(|| eyre!("no workflow artifact named {name} found"))?;
+    if artifact.expired {
+        bail!("workflow artifact {name} has expired");
+    }
+
+    Ok(artifact)
+}
+
+#[cfg(test)]
+mod test {
+    use color_eyre::Result;
+
+    use super::{find_artifact, ArtifactList};
+
+    const RESPONSE: &str = r#"{
+        "total_count": 2,
+        "artifacts": [
+            {
+                "id": 1,
+                "name": "old-artifact",
+                "size_in_bytes": 556,
+                "archive_download_url": "https://api.github.com/repos/worldcoin/orb-software/actions/artifacts/1/zip",
+                "expired": true
+            },
+            {
+                "id": 2,
+                "name": "artifact",
+                "size_in_bytes": 1024,
+                "archive_download_url": "https://api.github.com/repos/worldcoin/orb-software/actions/artifacts/2/zip",
+                "expired": false
+            }
+        ]
+    }"#;
+
+    fn list() -> ArtifactList {
+        serde_json::from_str(RESPONSE).unwrap()
+    }
+
+    #[test]
+    fn test_find_artifact() -> Result<()> {
+        let artifact = find_artifact(list(), "artifact")?;
+        assert!(artifact.archive_download_url.ends_with("/artifacts/2/zip"));
+
+        assert!(find_artifact(list(), "old-artifact").is_err());
+        assert!(find_artifact(list(), "nonexistent").is_err());
+
+        Ok(())
+    }
+}
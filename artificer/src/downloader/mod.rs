@@ -1,19 +1,185 @@
 //! Download functionality for various sources of artifacts.
 
-use color_eyre::{eyre::WrapErr, Result};
+use std::{fmt, path::PathBuf, time::Duration};
+
+use async_trait::async_trait;
+use color_eyre::{
+    eyre::{bail, WrapErr},
+    Result,
+};
+use futures::{StreamExt, TryStreamExt};
 use octocrab::Octocrab;
+use reqwest::{header, StatusCode};
+use tokio::io::AsyncRead;
+use tokio_util::compat::FuturesAsyncReadCompatExt;
+
+use crate::config::sources::{Github, Source};
 
 pub mod github;
+pub mod github_actions;
+pub mod path;
+pub mod s3;
+pub mod url;
+
+/// An in-progress download of an artifact.
+pub struct Download {
+    pub reader: Box<dyn AsyncRead + Send + Unpin>,
+    /// The size of the whole artifact in bytes, if known ahead of time.
+    pub size: Option<u64>,
+    /// The byte offset that `reader` starts at. Zero unless a resume was requested
+    /// and the source honoured it.
+    pub offset: u64,
+}
+
+impl Download {
+    /// Streams the body of `response`, which may be a partial response to a
+    /// request made with [`send`].
+    pub fn from_response(response: reqwest::Response) -> Self {
+        let content_length = response.content_length();
+        let range = (response.status() == StatusCode::PARTIAL_CONTENT)
+            .then(|| response.headers().get(header::CONTENT_RANGE))
+            .flatten()
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_content_range);
+        let (offset, size) = match range {
+            Some((offset, total)) => {
+                (offset, total.or(content_length.map(|len| len + offset)))
+            }
+            None => (0, content_length),
+        };
+        // convert from stream to tokio reader via `futures` and `tokio_util`
+        let reader = response
+            .bytes_stream()
+            .map(|result| {
+                result
+                    .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err))
+            })
+            .into_async_read()
+            .compat();
+
+        Self {
+            reader: Box::new(reader),
+            size,
+            offset,
+        }
+    }
+}
+
+/// Parses `bytes <start>-<end>/<total>` into the start and, if known, the total.
+fn parse_content_range(value: &str) -> Option<(u64, Option<u64>)> {
+    let (range, total) = value.strip_prefix("bytes ")?.split_once('/')?;
+    let (start, _end) = range.split_once('-')?;
+    Some((start.parse().ok()?, total.parse().ok()))
+}
+
+/// Sends `req`, asking for the response body to start at byte `offset`. Error
+/// statuses are turned into an [`HttpError`].
+pub async fn send(
+    req: reqwest::RequestBuilder,
+    offset: u64,
+) -> Result<reqwest::Response> {
+    let req = if offset > 0 {
+        req.header(header::RANGE, format!("bytes={offset}-"))
+    } else {
+        req
+    };
+    tracing::debug!("sending request: {req:?}");
+    let response = req.send().await.wrap_err("failed to send request")?;
+    if !response.status().is_success() {
+        return Err(HttpError::from_response(&response).into());
+    }
+
+    Ok(response)
+}
+
+/// An http request that completed with an error status.
+#[derive(Debug)]
+pub struct HttpError {
+    pub url: reqwest::Url,
+    pub status: StatusCode,
+    /// How long the server asked us to wait before trying again, from either
+    /// `Retry-After` or github's `X-RateLimit-Reset`.
+    pub retry_after: Option<Duration>,
+}
+
+impl HttpError {
+    fn from_response(response: &reqwest::Response) -> Self {
+        let headers = response.headers();
+        let get = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+        let retry_after =
+            get("retry-after").and_then(parse_retry_after).or_else(|| {
+                if get("x-ratelimit-remaining") != Some("0") {
+                    return None;
+                }
+                let reset: i64 = get("x-ratelimit-reset")?.parse().ok()?;
+                let wait = reset - chrono::Utc::now().timestamp();
+                Some(Duration::from_secs(wait.max(0).unsigned_abs()))
+            });
+
+        Self {
+            url: response.url().clone(),
+            status: response.status(),
+            retry_after,
+        }
+    }
+
+    /// Whether retrying the request later may succeed.
+    pub fn is_transient(&self) -> bool {
+        self.status.is_server_error()
+            || self.status == StatusCode::TOO_MANY_REQUESTS
+            || self.status == StatusCode::REQUEST_TIMEOUT
+            // github signals exhausted rate limits with a 403.
+            || (self.status == StatusCode::FORBIDDEN && self.retry_after.is_some())
+    }
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "http status {} for url ({})", self.status, self.url)?;
+        if let Some(wait) = self.retry_after {
+            write!(f, ", retry after {}s", wait.as_secs())?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for HttpError {}
+
+/// Parses `Retry-After`, which is either a number of seconds or an http date.
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    if let Ok(secs) = value.trim().parse() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    let wait = date.timestamp() - chrono::Utc::now().timestamp();
+    Some(Duration::from_secs(wait.max(0).unsigned_abs()))
+}
+
+/// Something that can download artifacts from a [`Source`].
+#[async_trait]
+pub trait Downloader: Send + Sync {
+    /// Downloads the artifact at `source`, starting at byte `offset` if the source
+    /// supports resuming. See [`Download::offset`] for where it actually starts.
+    async fn download(&self, source: &Source, offset: u64) -> Result<Download>;
+
+    /// Resolves version requirements in `source` to a concrete release, see
+    /// [`Source::needs_resolving`]. Other sources are returned unchanged.
+    async fn resolve(&self, source: &Source) -> Result<Source> {
+        Ok(source.clone())
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct Client {
     pub octo: Octocrab,
     pub reqwest: reqwest::Client,
     pub gh_token: Option<String>,
+    /// The directory that [`Source::Path`] sources are relative to.
+    pub local_root: PathBuf,
 }
 
 impl Client {
-    pub fn new(gh_token: Option<String>) -> Result<Self> {
+    pub fn new(gh_token: Option<String>, local_root: PathBuf) -> Result<Self> {
         let b = Octocrab::builder();
         let b = if let Some(token) = gh_token.clone() {
             b.personal_token(token)
@@ -32,6 +198,168 @@ impl Client {
             octo,
             reqwest,
             gh_token,
+            local_root,
         })
     }
 }
+
+#[async_trait]
+impl Downloader for Client {
+    async fn download(&self, source: &Source, offset: u64) -> Result<Download> {
+        match source {
+            Source::Github(s) => github::download_artifact(self, s.clone(), offset)
+                .await
+                .wrap_err_with(|| format!("failed to download github source: {s:?}")),
+            Source::GithubActions(s) => {
+                github_actions::download_artifact(self, s, offset)
+                    .await
+                    .wrap_err_with(|| {
+                        format!("failed to download github actions source: {s:?}")
+                    })
+            }
+            Source::Url(s) => url::download_artifact(self, s, offset)
+                .await
+                .wrap_err_with(|| format!("failed to download url source: {s:?}")),
+            Source::S3(s) => s3::download_artifact(self, s, offset)
+                .await
+                .wrap_err_with(|| format!("failed to download s3 source: {s:?}")),
+            Source::Path(s) => path::read_artifact(self, s, offset)
+                .await
+                .wrap_err_with(|| format!("failed to read path source: {s:?}")),
+        }
+    }
+
+    async fn resolve(&self, source: &Source) -> Result<Source> {
+        match source {
+            Source::Github(Github {
+                tag: Some(_),
+                version: Some(_),
+                ..
+            }) => bail!("github sources must not have both a `tag` and a `version`"),
+            Source::Github(
+                s @ Github {
+                    version: Some(version),
+                    ..
+                },
+            ) => {
+                let tag = github::resolve_tag(self, &s.repo, version)
+                    .await
+                    .wrap_err_with(|| {
+                        format!("failed to resolve version `{version}` of {}", s.repo)
+                    })?;
+                tracing::info!("resolved {} `{version}` to tag {tag}", s.repo);
+                Ok(Source::Github(s.with_tag(tag)))
+            }
+            _ => Ok(source.clone()),
+        }
+    }
+}
+
+/// A minimal http server, standing in for artifact hosts in tests.
+#[cfg(test)]
+pub mod test_server {
+    use std::net::SocketAddr;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Serves `body` for every request, until the returned task is aborted.
+    /// Returns the address of the server.
+    pub async fn serve(body: Vec<u8>) -> (SocketAddr, tokio::task::JoinHandle<()>) {
+        serve_flaky(body, 0).await
+    }
+
+    /// Like [`serve`], but the first `interrupted` responses are cut off halfway
+    /// through the body. Honours `Range: bytes=<start>-` requests.
+    pub async fn serve_flaky(
+        body: Vec<u8>,
+        interrupted: usize,
+    ) -> (SocketAddr, tokio::task::JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let remaining = Arc::new(AtomicUsize::new(interrupted));
+        let task = tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let body = body.clone();
+                let remaining = remaining.clone();
+                tokio::spawn(async move {
+                    let mut request = Vec::new();
+                    let mut buf = [0; 1024];
+                    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                        let n = stream.read(&mut buf).await.unwrap();
+                        if n == 0 {
+                            return;
+                        }
+                        request.extend_from_slice(&buf[..n]);
+                    }
+                    let request = String::from_utf8_lossy(&request).to_lowercase();
+                    let start: Option<usize> = request
+                        .lines()
+                        .find_map(|l| l.strip_prefix("range: bytes="))
+                        .and_then(|r| r.trim_end_matches('-').parse().ok());
+                    let header = match start {
+                        Some(start) => format!(
+                            "HTTP/1.1 206 Partial Content\r\nContent-Length: {}\r\n\
+                            Content-Range: bytes {start}-{}/{}\r\n\
+                            Connection: close\r\n\r\n",
+                            body.len() - start,
+                            body.len() - 1,
+                            body.len()
+                        ),
+                        None => format!(
+                            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\
+                            Connection: close\r\n\r\n",
+                            body.len()
+                        ),
+                    };
+                    let body = &body[start.unwrap_or(0)..];
+                    let cut_off = remaining
+                        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                            n.checked_sub(1)
+                        })
+                        .is_ok();
+                    let body = if cut_off {
+                        &body[..body.len() / 2]
+                    } else {
+                        body
+                    };
+                    stream.write_all(header.as_bytes()).await.unwrap();
+                    stream.write_all(body).await.unwrap();
+                });
+            }
+        });
+
+        (addr, task)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{parse_content_range, parse_retry_after};
+
+    #[test]
+    fn test_parse_content_range() {
+        assert_eq!(parse_content_range("bytes 10-19/20"), Some((10, Some(20))));
+        assert_eq!(parse_content_range("bytes 10-19/*"), Some((10, None)));
+        assert_eq!(parse_content_range("bytes */20"), None);
+        assert_eq!(parse_content_range("10-19/20"), None);
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(Duration::ZERO),
+            "dates in the past mean no wait"
+        );
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+}
@@ -0,0 +1,67 @@
+use color_eyre::{eyre::WrapErr, Result};
+use tokio::io::AsyncSeekExt;
+
+use crate::config::sources;
+
+use super::{Client, Download};
+
+pub async fn read_artifact(
+    client: &Client,
+    source: &sources::LocalPath,
+    offset: u64,
+) -> Result<Download> {
+    let path = client.local_root.join(&source.path);
+    let mut file = tokio::fs::File::open(&path)
+        .await
+        .wrap_err_with(|| format!("failed to open {}", path.display()))?;
+    let size = file
+        .metadata()
+        .await
+        .wrap_err("failed to read file metadata")?
+        .len();
+    let offset = offset.min(size);
+    file.seek(std::io::SeekFrom::Start(offset))
+        .await
+        .wrap_err("failed to seek file")?;
+
+    Ok(Download {
+        reader: Box::new(file),
+        size: Some(size),
+        offset,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use color_eyre::Result;
+    use tokio::io::AsyncReadExt;
+
+    use super::read_artifact;
+    use crate::config::sources;
+    use crate::downloader::Client;
+
+    #[tokio::test]
+    async fn test_relative_to_root() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        std::fs::create_dir(tmp.path().join("blobs"))?;
+        std::fs::write(tmp.path().join("blobs").join("foo.bin"), b"foo")?;
+        let client = Client::new(None, tmp.path().to_owned())?;
+        let source = sources::LocalPath {
+            path: "blobs/foo.bin".into(),
+        };
+
+        let mut download = read_artifact(&client, &source, 0).await?;
+        assert_eq!(download.size, Some(3));
+        let mut contents = Vec::new();
+        download.reader.read_to_end(&mut contents).await?;
+        assert_eq!(contents, b"foo");
+
+        let mut resumed = read_artifact(&client, &source, 1).await?;
+        assert_eq!(resumed.offset, 1);
+        let mut contents = Vec::new();
+        resumed.reader.read_to_end(&mut contents).await?;
+        assert_eq!(contents, b"oo");
+
+        Ok(())
+    }
+}
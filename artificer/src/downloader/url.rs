@@ -0,0 +1,54 @@
+use color_eyre::{eyre::WrapErr, Result};
+
+use crate::config::sources;
+
+use super::{Client, Download};
+
+pub async fn download_artifact(
+    client: &Client,
+    source: &sources::Url,
+    offset: u64,
+) -> Result<Download> {
+    let req = client.reqwest.get(&source.url);
+    let response = super::send(req, offset)
+        .await
+        .wrap_err("failed to send download request")?;
+
+    Ok(Download::from_response(response))
+}
+
+#[cfg(test)]
+mod test {
+    use color_eyre::Result;
+    use tokio::io::AsyncReadExt;
+
+    use super::download_artifact;
+    use crate::config::sources;
+    use crate::downloader::{test_server, Client};
+
+    #[tokio::test]
+    async fn test_download() -> Result<()> {
+        let body = b"artifact served over http".to_vec();
+        let (addr, server) = test_server::serve(body.clone()).await;
+        let client = Client::new(None, ".".into())?;
+        let source = sources::Url {
+            url: format!("http://{addr}/artifact"),
+        };
+
+        let mut download = download_artifact(&client, &source, 0).await?;
+        assert_eq!(download.size, Some(body.len() as u64));
+        let mut contents = Vec::new();
+        download.reader.read_to_end(&mut contents).await?;
+        assert_eq!(contents, body);
+
+        let mut resumed = download_artifact(&client, &source, 9).await?;
+        assert_eq!(resumed.offset, 9);
+        assert_eq!(resumed.size, Some(body.len() as u64));
+        let mut contents = Vec::new();
+        resumed.reader.read_to_end(&mut contents).await?;
+        assert_eq!(contents, body[9..]);
+
+        server.abort();
+        Ok(())
+    }
+}
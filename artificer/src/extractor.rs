@@ -0,0 +1,293 @@
+//! Extractors post-process a downloaded artifact into its out dir.
+
+use std::{collections::HashMap, fmt, fs::File, io::BufReader, path::Path};
+
+use color_eyre::{
+    eyre::{bail, eyre, WrapErr},
+    Result,
+};
+
+use crate::config::{ArtifactName, CustomExtractor, ExtractorName};
+
+/// Env var holding the path to the artifact being extracted.
+pub const ENV_INPUT: &str = "ARTIFICER_INPUT";
+/// Env var holding the dir that the extractor should write into. This is also the
+/// working directory of the extractor.
+pub const ENV_OUTPUT_DIR: &str = "ARTIFICER_OUTPUT_DIR";
+/// Env var holding the name of the artifact being extracted.
+pub const ENV_ARTIFACT_NAME: &str = "ARTIFICER_ARTIFACT_NAME";
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Extractor {
+    /// `tar.gz`: a gzipped tarball.
+    TarGz,
+    /// `tar.zst`: a zstd compressed tarball.
+    TarZst,
+    /// `zip`: a zip archive.
+    Zip,
+    /// A command from the `[extractors]` table of the spec.
+    Custom { name: ExtractorName, run: String },
+}
+
+impl Extractor {
+    fn builtin(name: &ExtractorName) -> Option<Self> {
+        match name.0.as_str() {
+            "tar.gz" => Some(Self::TarGz),
+            "tar.zst" => Some(Self::TarZst),
+            "zip" => Some(Self::Zip),
+            _ => None,
+        }
+    }
+
+    /// Finds the extractor called `name`. Custom extractors take precedence over
+    /// built-in ones, so that adding a new built-in extractor doesn't break
+    /// existing specs.
+    pub fn resolve(
+        name: &ExtractorName,
+        custom: &HashMap<ExtractorName, CustomExtractor>,
+        allow_custom: bool,
+    ) -> Result<Self> {
+        let builtin = Self::builtin(name);
+        let Some(custom) = custom.get(name) else {
+            return builtin.ok_or_else(|| eyre!("no extractor named `{}`", name.0));
+        };
+        if builtin.is_some() {
+            tracing::warn!(
+                "custom extractor `{}` shadows the built-in extractor of the same \
+                name",
+                name.0
+            );
+        }
+        if !allow_custom {
+            bail!(
+                "extractor `{}` is a custom extractor, which requires passing \
+                `--allow-custom-extractors`",
+                name.0
+            );
+        }
+
+        Ok(Self::Custom {
+            name: name.clone(),
+            run: custom.run.clone(),
+        })
+    }
+
+    /// Extracts the artifact at `input` into the existing directory `output`.
+    pub async fn extract(
+        &self,
+        input: &Path,
+        output: &Path,
+        artifact: &ArtifactName,
+    ) -> Result<()> {
+        tracing::debug!("extracting `{}` with {self:?}", artifact.0);
+        match self {
+            Self::TarGz | Self::TarZst | Self::Zip => {
+                let this = self.clone();
+                let input = input.to_owned();
+                let output = output.to_owned();
+                tokio::task::spawn_blocking(move || {
+                    this.extract_builtin(&input, &output)
+                })
+                .await
+                .wrap_err("extractor task panicked")?
+            }
+            Self::Custom { name, run } => {
+                run_custom(name, run, input, output, artifact).await
+            }
+        }
+        .wrap_err_with(|| format!("failed to extract artifact `{}`", artifact.0))
+    }
+
+    fn extract_builtin(&self, input: &Path, output: &Path) -> Result<()> {
+        let file =
+            BufReader::new(File::open(input).wrap_err("failed to open artifact")?);
+        match self {
+            Self::TarGz => tar::Archive::new(flate2::read::GzDecoder::new(file))
+                .unpack(output)
+                .wrap_err("failed to unpack tar.gz"),
+            Self::TarZst => {
+                let decoder =
+                    zstd::Decoder::with_buffer(file).wrap_err("failed to read zstd")?;
+                tar::Archive::new(decoder)
+                    .unpack(output)
+                    .wrap_err("failed to unpack tar.zst")
+            }
+            Self::Zip => zip::ZipArchive::new(file)
+                .wrap_err("failed to read zip")?
+                .extract(output)
+                .wrap_err("failed to unpack zip"),
+            Self::Custom { .. } => unreachable!("not a built-in extractor"),
+        }
+    }
+}
+
+impl fmt::Display for Extractor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TarGz => f.write_str("tar.gz"),
+            Self::TarZst => f.write_str("tar.zst"),
+            Self::Zip => f.write_str("zip"),
+            Self::Custom { name, .. } => write!(f, "{} (custom)", name.0),
+        }
+    }
+}
+
+/// Runs `run` verbatim with `sh -c`. The artifact is passed in the environment and
+/// as `$1`, `$0` is the name of the extractor.
+async fn run_custom(
+    name: &ExtractorName,
+    run: &str,
+    input: &Path,
+    output: &Path,
+    artifact: &ArtifactName,
+) -> Result<()> {
+    let status = tokio::process::Command::new("sh")
+        .arg("-c")
+        .arg(run)
+        .arg(&name.0)
+        .arg(input)
+        .current_dir(output)
+        .env(ENV_INPUT, input)
+        .env(ENV_OUTPUT_DIR, output)
+        .env(ENV_ARTIFACT_NAME, &artifact.0)
+        .kill_on_drop(true)
+        .status()
+        .await
+        .wrap_err_with(|| format!("failed to run extractor `{}`", name.0))?;
+    if !status.success() {
+        bail!("extractor `{}` failed with {status}", name.0);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashMap, io::Write, path::Path};
+
+    use color_eyre::Result;
+
+    use super::Extractor;
+    use crate::config::{ArtifactName, CustomExtractor, ExtractorName};
+
+    fn make_tar() -> Result<Vec<u8>> {
+        let mut builder = tar::Builder::new(Vec::new());
+        let contents = b"hello from the tarball";
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder.append_data(&mut header, "dir/hello.txt", &contents[..])?;
+        Ok(builder.into_inner()?)
+    }
+
+    async fn extract(
+        extractor: &Extractor,
+        artifact: &[u8],
+    ) -> Result<tempfile::TempDir> {
+        let input = tempfile::NamedTempFile::new()?;
+        std::fs::write(input.path(), artifact)?;
+        let output = tempfile::tempdir()?;
+        extractor
+            .extract(input.path(), output.path(), &ArtifactName("foo".to_owned()))
+            .await?;
+        Ok(output)
+    }
+
+    fn assert_hello(output: &Path, expected: &str) -> Result<()> {
+        let contents = std::fs::read_to_string(output.join("dir").join("hello.txt"))?;
+        assert_eq!(contents, expected);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_tar_gz() -> Result<()> {
+        let mut encoder =
+            flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        encoder.write_all(&make_tar()?)?;
+        let output = extract(&Extractor::TarGz, &encoder.finish()?).await?;
+        assert_hello(output.path(), "hello from the tarball")
+    }
+
+    #[tokio::test]
+    async fn test_tar_zst() -> Result<()> {
+        let compressed = zstd::encode_all(make_tar()?.as_slice(), 0)?;
+        let output = extract(&Extractor::TarZst, &compressed).await?;
+        assert_hello(output.path(), "hello from the tarball")
+    }
+
+    #[tokio::test]
+    async fn test_zip() -> Result<()> {
+        let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        writer.start_file("dir/hello.txt", zip::write::FileOptions::default())?;
+        writer.write_all(b"hello from the zip")?;
+        let zipped = writer.finish()?.into_inner();
+
+        let output = extract(&Extractor::Zip, &zipped).await?;
+        assert_hello(output.path(), "hello from the zip")
+    }
+
+    #[tokio::test]
+    async fn test_custom() -> Result<()> {
+        let extractor = Extractor::Custom {
+            name: ExtractorName("copy".to_owned()),
+            run: "mkdir dir && echo \"$ARTIFICER_ARTIFACT_NAME\" > name.txt && \
+                cp -- \"$ARTIFICER_INPUT\" dir/hello.txt && test -f \"$1\""
+                .to_owned(),
+        };
+        let output = extract(&extractor, b"hello from sh").await?;
+        assert_hello(output.path(), "hello from sh")?;
+        assert_eq!(
+            std::fs::read_to_string(output.path().join("name.txt"))?,
+            "foo\n"
+        );
+
+        let failing = Extractor::Custom {
+            name: ExtractorName("fail".to_owned()),
+            run: "false".to_owned(),
+        };
+        assert!(extract(&failing, b"").await.is_err());
+
+        // Arguments are not appended to the command
+        let verbatim = Extractor::Custom {
+            name: ExtractorName("verbatim".to_owned()),
+            run: "test $# -eq 1 && test \"$0\" = verbatim && echo".to_owned(),
+        };
+        extract(&verbatim, b"").await?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve() -> Result<()> {
+        let custom = HashMap::from([
+            (
+                ExtractorName("tar".to_owned()),
+                CustomExtractor {
+                    run: "tar -xvf \"$1\"".to_owned(),
+                },
+            ),
+            (
+                ExtractorName("zip".to_owned()),
+                CustomExtractor {
+                    run: "unzip".to_owned(),
+                },
+            ),
+        ]);
+        let resolve = |name: &str, allow_custom| {
+            Extractor::resolve(&ExtractorName(name.to_owned()), &custom, allow_custom)
+        };
+
+        assert_eq!(resolve("tar.gz", false)?, Extractor::TarGz);
+        assert_eq!(resolve("tar.zst", false)?, Extractor::TarZst);
+        assert!(matches!(resolve("tar", true)?, Extractor::Custom { .. }));
+        assert!(resolve("tar", false).is_err());
+        assert!(
+            matches!(resolve("zip", true)?, Extractor::Custom { .. }),
+            "custom extractors should shadow built-in ones"
+        );
+        assert!(resolve("nonexistent", true).is_err());
+
+        Ok(())
+    }
+}
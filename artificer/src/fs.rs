@@ -0,0 +1,465 @@
+//! Filesystem access for artificer.
+//!
+//! All reads of the spec and lockfile, and all writes into the out dir, go through
+//! the [`Filesystem`] trait so that the download logic can be exercised without
+//! touching the disk.
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use async_trait::async_trait;
+use cacache::Integrity;
+use color_eyre::{eyre::WrapErr, Result};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use crate::config::ArtifactName;
+use crate::extractor::Extractor;
+use crate::verify::{self, Manifest};
+
+/// Name of the lockfile. It always lives next to the spec file.
+pub const LOCKFILE_NAME: &str = "artificer.lock";
+/// Directory inside the out dir that is reserved for artificer's own use.
+pub const METADATA_DIR: &str = ".artificer-metadata";
+
+#[async_trait]
+pub trait Filesystem: Send + Sync {
+    /// Handle used to write a single artifact before it is committed.
+    type Writer: AsyncWrite + Unpin + Send + 'static;
+
+    /// Reads the contents of the spec file.
+    async fn read_spec(&self) -> Result<String>;
+
+    /// Reads the contents of the lockfile, or `None` if there isn't one yet.
+    async fn read_lockfile(&self) -> Result<Option<String>>;
+
+    /// Atomically replaces the contents of the lockfile.
+    async fn write_lockfile(&self, contents: &str) -> Result<()>;
+
+    /// Begins writing the artifact `name`. Nothing becomes visible in `out_dir`
+    /// until the writer is passed to [`Self::commit_artifact`].
+    ///
+    /// `out_dir` is interpreted relative to the spec file.
+    async fn artifact_writer(
+        &self,
+        out_dir: &Path,
+        name: &ArtifactName,
+    ) -> Result<Self::Writer>;
+
+    /// Atomically moves a fully written artifact into `out_dir`, replacing any
+    /// previous version of it. If an `extractor` is given, the artifact is
+    /// extracted in a scratch dir and only the extracted contents are moved,
+    /// along with a manifest recording the artifact's `hash`.
+    async fn commit_artifact(
+        &self,
+        out_dir: &Path,
+        name: &ArtifactName,
+        writer: Self::Writer,
+        hash: &Integrity,
+        extractor: Option<&Extractor>,
+    ) -> Result<()>;
+}
+
+/// The real filesystem. All paths are resolved relative to the spec file.
+#[derive(Debug, Clone)]
+pub struct LocalFs {
+    spec_path: PathBuf,
+}
+
+impl LocalFs {
+    pub fn new(spec_path: impl Into<PathBuf>) -> Self {
+        Self {
+            spec_path: spec_path.into(),
+        }
+    }
+
+    /// The directory containing the spec file.
+    pub fn spec_dir(&self) -> &Path {
+        self.spec_path
+            .parent()
+            .filter(|p| !p.as_os_str().is_empty())
+            .unwrap_or(Path::new("."))
+    }
+
+    pub fn lockfile_path(&self) -> PathBuf {
+        self.spec_dir().join(LOCKFILE_NAME)
+    }
+
+    pub fn out_dir(&self, out_dir: &Path) -> PathBuf {
+        self.spec_dir().join(out_dir)
+    }
+
+    fn staging_dir(&self, out_dir: &Path) -> PathBuf {
+        self.out_dir(out_dir).join(METADATA_DIR).join("staging")
+    }
+
+    /// Atomically replaces the manifest of the artifact `name`, or removes it if
+    /// `manifest` is `None`.
+    async fn write_manifest(
+        &self,
+        out_dir: &Path,
+        name: &ArtifactName,
+        manifest: Option<&Manifest>,
+    ) -> Result<()> {
+        let path = self.out_dir(out_dir).join(verify::manifest_path(name));
+        let Some(manifest) = manifest else {
+            return match tokio::fs::remove_file(&path).await {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err)
+                    .wrap_err_with(|| {
+                        format!("failed to remove manifest {}", path.display())
+                    }),
+                _ => Ok(()),
+            };
+        };
+        let dir = path.parent().expect("manifests are in a dir");
+        tokio::fs::create_dir_all(dir)
+            .await
+            .wrap_err("failed to create manifests dir")?;
+        let contents =
+            toml::to_string(manifest).wrap_err("failed to serialize manifest")?;
+        let tmp = tempfile::NamedTempFile::new_in(dir)
+            .wrap_err("failed to create temporary manifest")?;
+        tokio::fs::write(tmp.path(), contents)
+            .await
+            .wrap_err("failed to write manifest")?;
+        tmp.persist(&path).wrap_err_with(|| {
+            format!("failed to replace manifest {}", path.display())
+        })?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Filesystem for LocalFs {
+    type Writer = StagedArtifact;
+
+    async fn read_spec(&self) -> Result<String> {
+        tokio::fs::read_to_string(&self.spec_path)
+            .await
+            .wrap_err_with(|| {
+                format!("failed to read spec file {}", self.spec_path.display())
+            })
+    }
+
+    async fn read_lockfile(&self) -> Result<Option<String>> {
+        let path = self.lockfile_path();
+        match tokio::fs::read_to_string(&path).await {
+            Ok(contents) => Ok(Some(contents)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err).wrap_err_with(|| {
+                format!("failed to read lockfile {}", path.display())
+            }),
+        }
+    }
+
+    async fn write_lockfile(&self, contents: &str) -> Result<()> {
+        let path = self.lockfile_path();
+        let tmp = tempfile::NamedTempFile::new_in(self.spec_dir())
+            .wrap_err("failed to create temporary lockfile")?;
+        tokio::fs::write(tmp.path(), contents)
+            .await
+            .wrap_err("failed to write temporary lockfile")?;
+        tmp.persist(&path)
+            .wrap_err_with(|| format!("failed to write lockfile {}", path.display()))?;
+
+        Ok(())
+    }
+
+    async fn artifact_writer(
+        &self,
+        out_dir: &Path,
+        name: &ArtifactName,
+    ) -> Result<Self::Writer> {
+        let staging = self.staging_dir(out_dir);
+        tokio::fs::create_dir_all(&staging)
+            .await
+            .wrap_err_with(|| {
+                format!("failed to create staging dir {}", staging.display())
+            })?;
+        let dir = tempfile::Builder::new()
+            .prefix(&format!("{}-", name.0))
+            .tempdir_in(&staging)
+            .wrap_err("failed to create temporary artifact dir")?;
+        let file = tokio::fs::File::create(dir.path().join(&name.0))
+            .await
+            .wrap_err("failed to create temporary artifact file")?;
+
+        Ok(StagedArtifact { dir, file })
+    }
+
+    async fn commit_artifact(
+        &self,
+        out_dir: &Path,
+        name: &ArtifactName,
+        writer: Self::Writer,
+        hash: &Integrity,
+        extractor: Option<&Extractor>,
+    ) -> Result<()> {
+        let StagedArtifact { dir, mut file } = writer;
+        file.flush().await.wrap_err("failed to flush artifact")?;
+        file.sync_all().await.wrap_err("failed to sync artifact")?;
+        drop(file);
+
+        let (dir, manifest) = if let Some(extractor) = extractor {
+            let extracted = tempfile::Builder::new()
+                .prefix(&format!("{}-extracted-", name.0))
+                .tempdir_in(self.staging_dir(out_dir))
+                .wrap_err("failed to create temporary extraction dir")?;
+            extractor
+                .extract(&dir.path().join(&name.0), extracted.path(), name)
+                .await?;
+            let path = extracted.path().to_owned();
+            let hash = hash.clone();
+            let manifest =
+                tokio::task::spawn_blocking(move || Manifest::compute(&path, hash))
+                    .await
+                    .wrap_err("manifest task panicked")??;
+            (extracted, Some(manifest))
+        } else {
+            (dir, None)
+        };
+
+        let dest = self.out_dir(out_dir).join(&name.0);
+        // Move any previous version out of the way first. `dest` is briefly missing
+        // until the new version is renamed into place, but it never contains a mix
+        // of both versions.
+        let old = if tokio::fs::try_exists(&dest).await? {
+            let old = tempfile::Builder::new()
+                .prefix(&format!("{}-old-", name.0))
+                .tempdir_in(self.staging_dir(out_dir))
+                .wrap_err("failed to create temporary dir for old artifact")?;
+            let old_path = old.path().join(&name.0);
+            tokio::fs::rename(&dest, &old_path)
+                .await
+                .wrap_err_with(|| {
+                    format!("failed to move old artifact {}", dest.display())
+                })?;
+            Some((old, old_path))
+        } else {
+            None
+        };
+
+        if let Err(err) = tokio::fs::rename(dir.path(), &dest).await {
+            // Put the previous version back, so that a failed commit leaves the out
+            // dir as it was.
+            if let Some((old, old_path)) = old {
+                if let Err(restore_err) = tokio::fs::rename(&old_path, &dest).await {
+                    // Keep it around rather than deleting the only copy.
+                    let _ = old.into_path();
+                    tracing::error!(
+                        "failed to restore old artifact {} from {}: {restore_err}",
+                        dest.display(),
+                        old_path.display()
+                    );
+                }
+            }
+            return Err(err).wrap_err_with(|| {
+                format!("failed to move artifact into {}", dest.display())
+            });
+        }
+        // The staged dir has become `dest`, so it must not be deleted with `dir`.
+        let _ = dir.into_path();
+        drop(old);
+
+        self.write_manifest(out_dir, name, manifest.as_ref()).await
+    }
+}
+
+/// An artifact that is being written into a temporary directory inside the out
+/// dir. Dropping it without committing deletes the temporary directory.
+#[derive(Debug)]
+pub struct StagedArtifact {
+    dir: tempfile::TempDir,
+    file: tokio::fs::File,
+}
+
+impl AsyncWrite for StagedArtifact {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.file).poll_write(cx, buf)
+    }
+
+    fn poll_flush(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.file).poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.file).poll_shutdown(cx)
+    }
+}
+
+/// An in-memory filesystem for tests. Committed artifacts are stored by their
+/// path relative to the spec, i.e. `<out_dir>/<name>/<name>`.
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct InMemoryFs {
+    pub spec: String,
+    pub lockfile: std::sync::Mutex<Option<String>>,
+    pub artifacts: std::sync::Mutex<std::collections::HashMap<PathBuf, Vec<u8>>>,
+}
+
+#[cfg(test)]
+#[async_trait]
+impl Filesystem for InMemoryFs {
+    type Writer = Vec<u8>;
+
+    async fn read_spec(&self) -> Result<String> {
+        Ok(self.spec.clone())
+    }
+
+    async fn read_lockfile(&self) -> Result<Option<String>> {
+        Ok(self.lockfile.lock().unwrap().clone())
+    }
+
+    async fn write_lockfile(&self, contents: &str) -> Result<()> {
+        *self.lockfile.lock().unwrap() = Some(contents.to_owned());
+        Ok(())
+    }
+
+    async fn artifact_writer(
+        &self,
+        _out_dir: &Path,
+        _name: &ArtifactName,
+    ) -> Result<Self::Writer> {
+        Ok(Vec::new())
+    }
+
+    async fn commit_artifact(
+        &self,
+        out_dir: &Path,
+        name: &ArtifactName,
+        writer: Self::Writer,
+        _hash: &Integrity,
+        extractor: Option<&Extractor>,
+    ) -> Result<()> {
+        if extractor.is_some() {
+            color_eyre::eyre::bail!("InMemoryFs does not support extractors");
+        }
+        let path = out_dir.join(&name.0).join(&name.0);
+        self.artifacts.lock().unwrap().insert(path, writer);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use color_eyre::Result;
+    use tokio::io::AsyncWriteExt;
+
+    use cacache::Integrity;
+
+    use super::{Filesystem, LocalFs};
+    use crate::config::{ArtifactName, ExtractorName};
+    use crate::extractor::Extractor;
+    use crate::verify::Manifest;
+
+    #[tokio::test]
+    async fn test_local_fs_commit_is_atomic() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let fs = LocalFs::new(tmp.path().join("artificer.toml"));
+        let out_dir = Path::new("out");
+        let name = ArtifactName("foo".to_owned());
+        let dest = tmp.path().join("out").join("foo");
+        let hash = Integrity::from(b"contents");
+
+        let mut writer = fs.artifact_writer(out_dir, &name).await?;
+        writer.write_all(b"first").await?;
+        assert!(!dest.exists(), "artifact visible before commit");
+        fs.commit_artifact(out_dir, &name, writer, &hash, None)
+            .await?;
+        assert_eq!(std::fs::read(dest.join("foo"))?, b"first");
+
+        // Replacing an existing artifact
+        let mut writer = fs.artifact_writer(out_dir, &name).await?;
+        writer.write_all(b"second").await?;
+        assert_eq!(std::fs::read(dest.join("foo"))?, b"first");
+        fs.commit_artifact(out_dir, &name, writer, &hash, None)
+            .await?;
+        assert_eq!(std::fs::read(dest.join("foo"))?, b"second");
+
+        // An abandoned write leaves the old artifact intact
+        let mut writer = fs.artifact_writer(out_dir, &name).await?;
+        writer.write_all(b"third").await?;
+        drop(writer);
+        assert_eq!(std::fs::read(dest.join("foo"))?, b"second");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_local_fs_commit_extracted() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let fs = LocalFs::new(tmp.path().join("artificer.toml"));
+        let out_dir = Path::new("out");
+        let name = ArtifactName("foo".to_owned());
+        let dest = tmp.path().join("out").join("foo");
+        let hash = Integrity::from(b"contents");
+        let extractor = Extractor::Custom {
+            name: ExtractorName("copy".to_owned()),
+            run: "cp -- \"$ARTIFICER_INPUT\" extracted.txt && test -f".to_owned(),
+        };
+
+        let mut writer = fs.artifact_writer(out_dir, &name).await?;
+        writer.write_all(b"contents").await?;
+        fs.commit_artifact(out_dir, &name, writer, &hash, Some(&extractor))
+            .await?;
+        assert_eq!(std::fs::read(dest.join("extracted.txt"))?, b"contents");
+        assert!(
+            !dest.join("foo").exists(),
+            "raw artifact should not be kept"
+        );
+        let manifest = tmp
+            .path()
+            .join("out")
+            .join(crate::verify::manifest_path(&name));
+        let manifest: Manifest = toml::from_str(&std::fs::read_to_string(manifest)?)?;
+        assert_eq!(manifest.hash, hash);
+        assert_eq!(manifest.files.keys().collect::<Vec<_>>(), ["extracted.txt"]);
+
+        // A failing extractor leaves the previous artifact intact
+        let failing = Extractor::Custom {
+            name: ExtractorName("fail".to_owned()),
+            run: "touch partial.txt && false".to_owned(),
+        };
+        let writer = fs.artifact_writer(out_dir, &name).await?;
+        assert!(fs
+            .commit_artifact(out_dir, &name, writer, &hash, Some(&failing))
+            .await
+            .is_err());
+        assert_eq!(std::fs::read(dest.join("extracted.txt"))?, b"contents");
+        assert!(!dest.join("partial.txt").exists());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_local_fs_lockfile() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let fs = LocalFs::new(tmp.path().join("artificer.toml"));
+        assert_eq!(fs.read_lockfile().await?, None);
+
+        fs.write_lockfile("version = 0").await?;
+        assert_eq!(fs.read_lockfile().await?.as_deref(), Some("version = 0"));
+        assert_eq!(
+            std::fs::read_to_string(tmp.path().join("artificer.lock"))?,
+            "version = 0"
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,156 @@
+//! Integrity verification of artifacts, using [subresource integrity][ssri] hashes.
+//!
+//! [ssri]: https://w3c.github.io/webappsec-subresource-integrity/
+
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use cacache::{Algorithm, Integrity};
+use color_eyre::{eyre::bail, Result};
+use ssri::IntegrityOpts;
+use tokio::io::AsyncWrite;
+
+use crate::config::{ArtifactName, Hash};
+
+/// The algorithm used when there is no existing hash to match against.
+pub const DEFAULT_ALGORITHM: Algorithm = Algorithm::Sha256;
+
+/// Picks the algorithm to hash an artifact with, so that the result is comparable
+/// with `expected`.
+pub fn algorithm_for(expected: Option<&Hash>) -> Algorithm {
+    match expected {
+        Some(Hash::Hash(integrity)) => integrity.pick_algorithm(),
+        Some(Hash::Dummy) | Some(Hash::False) | None => DEFAULT_ALGORITHM,
+    }
+}
+
+/// Checks the `actual` integrity of an artifact against the `expected` [`Hash`].
+///
+/// - [`Hash::Hash`] must match `actual`.
+/// - [`Hash::Dummy`] always fails, and reports `actual` so that it can be copied
+///   into the spec.
+/// - [`Hash::False`] and `None` skip verification.
+pub fn verify(
+    name: &ArtifactName,
+    expected: Option<&Hash>,
+    actual: &Integrity,
+) -> Result<()> {
+    match expected {
+        Some(Hash::Hash(expected)) if expected.matches(actual).is_none() => bail!(
+            "hash mismatch for artifact `{}`:\n  expected: {expected}\n  \
+            actual:   {actual}",
+            name.0
+        ),
+        Some(Hash::Hash(_)) => (),
+        Some(Hash::Dummy) => bail!(
+            "artifact `{}` has an empty hash. If you trust the downloaded \
+            artifact, set its hash to:\n  hash = \"{actual}\"",
+            name.0
+        ),
+        Some(Hash::False) => {
+            tracing::debug!("skipping hash verification for `{}`", name.0);
+        }
+        None => (),
+    }
+
+    Ok(())
+}
+
+/// Wraps an [`AsyncWrite`], computing the [`Integrity`] of everything written
+/// through it.
+pub struct HashingWriter<W> {
+    inner: W,
+    opts: IntegrityOpts,
+}
+
+impl<W> HashingWriter<W> {
+    pub fn new(inner: W, algorithm: Algorithm) -> Self {
+        Self {
+            inner,
+            opts: IntegrityOpts::new().algorithm(algorithm),
+        }
+    }
+
+    /// Returns the inner writer and the integrity of all bytes written so far.
+    pub fn finish(self) -> (W, Integrity) {
+        (self.inner, self.opts.result())
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for HashingWriter<W> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        let poll = Pin::new(&mut this.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = poll {
+            this.opts.input(&buf[..n]);
+        }
+        poll
+    }
+
+    fn poll_flush(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use cacache::{Algorithm, Integrity};
+    use color_eyre::Result;
+    use tokio::io::AsyncWriteExt;
+
+    use super::{verify, HashingWriter};
+    use crate::config::{ArtifactName, Hash};
+
+    #[tokio::test]
+    async fn test_hashing_writer_matches_oneshot() -> Result<()> {
+        let data = b"some artifact contents".repeat(1000);
+        let mut writer = HashingWriter::new(Vec::new(), Algorithm::Sha256);
+        for chunk in data.chunks(7) {
+            writer.write_all(chunk).await?;
+        }
+        let (written, integrity) = writer.finish();
+
+        assert_eq!(written, data);
+        assert_eq!(integrity, Integrity::from(&data));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify() {
+        let name = ArtifactName("foo".to_owned());
+        let actual = Integrity::from(b"foo");
+        let other = Integrity::from(b"bar");
+
+        assert!(verify(&name, None, &actual).is_ok());
+        assert!(verify(&name, Some(&Hash::False), &actual).is_ok());
+        assert!(verify(&name, Some(&Hash::Hash(actual.clone())), &actual).is_ok());
+
+        let err = verify(&name, Some(&Hash::Hash(other.clone())), &actual)
+            .expect_err("mismatched hash should fail");
+        let msg = err.to_string();
+        assert!(msg.contains(&other.to_string()), "{msg}");
+        assert!(msg.contains(&actual.to_string()), "{msg}");
+
+        let err = verify(&name, Some(&Hash::Dummy), &actual)
+            .expect_err("dummy hash should always fail");
+        assert!(err.to_string().contains(&actual.to_string()));
+    }
+}
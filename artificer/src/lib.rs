@@ -1,137 +1,1199 @@
+//! Reproducibly downloads artifacts described by an `artificer.toml` spec.
+//!
+//! Besides the `artificer` CLI, the download logic can be embedded in other
+//! tools: parse a [`Spec`] and its [`LockedSpec`], build a [`DownloadPlan`] from
+//! them, and [`DownloadPlan::run`] it with your own [`Filesystem`] and
+//! [`ProgressSink`] to get a [`PlanReport`].
+
 #![forbid(unsafe_code)]
 
-mod config;
-mod downloader;
+mod cache;
+pub mod config;
+pub mod downloader;
+pub mod extractor;
+pub mod fs;
+mod integrity;
+pub mod progress;
+mod retry;
+mod signature;
+mod verify;
 
 use std::{
-    collections::HashMap,
-    fmt::Write,
+    collections::{HashMap, HashSet},
+    future::Future,
+    io::SeekFrom,
+    num::NonZeroUsize,
     path::{Path, PathBuf},
-    pin::pin,
-    time::Duration,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crate::cache::Cache;
+use crate::downloader::{Client, Downloader, HttpError};
+use crate::extractor::Extractor;
+use crate::fs::LocalFs;
+use crate::integrity::HashingWriter;
+use crate::progress::{Bars, Event, JsonLines};
+use crate::retry::Interrupted;
+use crate::signature::PlannedSignature;
+use cacache::Integrity;
+use color_eyre::{
+    eyre::{bail, eyre, WrapErr},
+    Result,
 };
+use config::{sources::Source, Artifact, ArtifactName, Hash, LockedArtifact};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+pub use crate::config::{LockedSpec, Spec};
+pub use crate::fs::Filesystem;
+pub use crate::progress::{ArtifactSummary, ProgressSink};
+pub use crate::retry::RetryPolicy;
+pub use crate::verify::Problem;
+
+/// Options shared by all commands.
+#[derive(Debug, Clone)]
+pub struct Settings {
+    /// Path to `artificer.toml`. The lockfile is expected to be next to it.
+    pub spec_path: PathBuf,
+    pub plan: PlanOptions,
+    pub output: OutputMode,
+}
+
+/// Options for fetching the artifacts of a [`DownloadPlan`].
+#[derive(Debug, Clone)]
+pub struct PlanOptions {
+    /// Location of the download cache. See [`default_cache_dir`].
+    pub cache_dir: PathBuf,
+    /// Error instead of accessing the network when an artifact isn't cached.
+    pub offline: bool,
+    /// Allow running the custom extractors declared in the spec.
+    pub allow_custom_extractors: bool,
+    /// How to retry downloads that failed for transient reasons.
+    pub retry: RetryPolicy,
+    /// Time limit for downloading a single artifact, including retries.
+    pub timeout: Option<Duration>,
+    /// Maximum number of artifacts fetched at once.
+    pub jobs: NonZeroUsize,
+}
+
+impl PlanOptions {
+    /// The defaults of the CLI, caching in `cache_dir`.
+    pub fn new(cache_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            cache_dir: cache_dir.into(),
+            offline: false,
+            allow_custom_extractors: false,
+            retry: RetryPolicy::default(),
+            timeout: None,
+            jobs: NonZeroUsize::new(4).expect("not zero"),
+        }
+    }
+}
+
+/// How progress is reported.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum OutputMode {
+    /// Progress bars and a summary table on stderr.
+    Bars,
+    /// One JSON object per event on stdout, for scripts and CI logs.
+    JsonLines,
+}
+
+/// `$XDG_CACHE_HOME/artificer/store`, falling back to `$HOME/.cache`.
+pub fn default_cache_dir() -> Result<PathBuf> {
+    Cache::default_dir()
+}
+
+/// Downloads every artifact in the spec into the out dir, verifying them against
+/// the lockfile. Errors if the lockfile is missing or out of date.
+pub async fn run(settings: &Settings) -> Result<()> {
+    let fs = LocalFs::new(&settings.spec_path);
+    let spec = read_spec(&fs).await?;
+    let Some(locked) = read_lockfile(&fs).await? else {
+        bail!(
+            "no lockfile found at {}, run `artificer lock` to create one",
+            fs.lockfile_path().display()
+        );
+    };
+
+    DownloadPlan::new(&spec, &locked, &settings.plan)?
+        .run(make_client(fs.spec_dir())?, &fs, make_progress(settings))
+        .await?
+        .into_result()?;
+
+    Ok(())
+}
+
+/// Downloads every artifact in the spec and writes a fresh lockfile. Artifacts
+/// that are already cached are not downloaded again.
+pub async fn lock(settings: &Settings) -> Result<()> {
+    let fs = LocalFs::new(&settings.spec_path);
+    let spec = read_spec(&fs).await?;
+    let selected = spec.artifacts.keys().cloned().collect();
+
+    relock(settings, &fs, spec, LockedSpec::new(), selected, false).await
+}
+
+/// Re-downloads the artifacts in `names`, bypassing the cache unless their hash
+/// is known, and updates their lockfile entries. If `names` is empty, all
+/// artifacts are updated.
+pub async fn update(settings: &Settings, names: &[String]) -> Result<()> {
+    let fs = LocalFs::new(&settings.spec_path);
+    let spec = read_spec(&fs).await?;
+    let locked = read_lockfile(&fs).await?.unwrap_or_default();
+    let selected = if names.is_empty() {
+        spec.artifacts.keys().cloned().collect()
+    } else {
+        let mut selected = Vec::new();
+        for name in names {
+            let name = ArtifactName(name.to_owned());
+            if !spec.artifacts.contains_key(&name) {
+                bail!("no artifact named `{}` in the spec", name.0);
+            }
+            selected.push(name);
+        }
+        selected
+    };
+
+    relock(settings, &fs, spec, locked, selected, true).await
+}
+
+/// Downloads the `selected` artifacts and records their hashes in `locked`, which
+/// is then written to the lockfile. Version requirements of the selected artifacts
+/// are resolved anew. Entries for artifacts that are no longer in the spec are
+/// removed.
+async fn relock(
+    settings: &Settings,
+    fs: &LocalFs,
+    spec: Spec,
+    mut locked: LockedSpec,
+    selected: Vec<ArtifactName>,
+    refresh: bool,
+) -> Result<()> {
+    locked
+        .artifacts
+        .retain(|name, _| spec.artifacts.contains_key(name));
+
+    let downloader = make_client(fs.spec_dir())?;
+    let mut resolved = HashMap::new();
+    for name in &selected {
+        let source = &spec.artifacts[name].source;
+        if settings.plan.offline && source.needs_resolving() {
+            bail!(
+                "artifact `{}` has a version requirement, which can't be resolved \
+                offline",
+                name.0
+            );
+        }
+        let source = downloader.resolve(source).await.wrap_err_with(|| {
+            format!("failed to resolve the source of `{}`", name.0)
+        })?;
+        resolved.insert(name.clone(), source);
+    }
+
+    let artifacts = selected
+        .into_iter()
+        .map(|name| {
+            let art = &spec.artifacts[&name];
+            let source = resolved[&name].clone();
+            let planned = PlannedArtifact {
+                signature: plan_signature(&name, art, &source)?,
+                source,
+                hash: art.hash.clone(),
+                locked: locked.artifacts.get(&name).map(|l| l.hash.clone()),
+                extractor: resolve_extractor(&settings.plan, &spec, art)?,
+            };
+            Ok((name, planned))
+        })
+        .collect::<Result<_>>()?;
+    let dp = DownloadPlan::with_artifacts(&spec, artifacts, &settings.plan, refresh);
+    let summaries = dp
+        .run(downloader, fs, make_progress(settings))
+        .await?
+        .into_result()?;
+
+    for ArtifactSummary { artifact, hash, .. } in summaries {
+        let source = spec.artifacts[&artifact].source.clone();
+        tracing::info!("locked `{}` at {hash}", artifact.0);
+        let entry = LockedArtifact::new(source, &resolved[&artifact], hash);
+        locked.artifacts.insert(artifact, entry);
+    }
+    fs.write_lockfile(&locked.to_toml()?).await
+}
+
+/// Checks without network access that the out dir contains exactly the artifacts
+/// in the lockfile, unmodified. Returns every problem found, none if the out dir is
+/// intact.
+pub async fn verify(settings: &Settings) -> Result<Vec<Problem>> {
+    let fs = LocalFs::new(&settings.spec_path);
+    let spec = read_spec(&fs).await?;
+    let Some(locked) = read_lockfile(&fs).await? else {
+        bail!("no lockfile found at {}", fs.lockfile_path().display());
+    };
+    locked.check_up_to_date(&spec)?;
+
+    let out_dir = fs.out_dir(&spec.artificer.out_dir);
+    let count = locked.artifacts.len();
+    let problems = {
+        let out_dir = out_dir.clone();
+        tokio::task::spawn_blocking(move || {
+            verify::verify_out_dir(&out_dir, &spec, &locked)
+        })
+        .await
+        .wrap_err("verify task panicked")??
+    };
+    if problems.is_empty() {
+        tracing::info!("verified {count} artifacts in {}", out_dir.display());
+    }
+
+    Ok(problems)
+}
+
+/// Lists every entry in the cache, sorted by key.
+pub fn cache_ls(settings: &Settings) -> Result<Vec<cacache::Metadata>> {
+    let mut entries = Cache::new(&settings.plan.cache_dir).list()?;
+    entries.sort_by(|a, b| a.key.cmp(&b.key));
+
+    Ok(entries)
+}
+
+/// Removes every cache entry that isn't referenced by the lockfile.
+pub async fn cache_gc(settings: &Settings) -> Result<()> {
+    let fs = LocalFs::new(&settings.spec_path);
+    let keep: HashSet<Integrity> = read_lockfile(&fs)
+        .await?
+        .map(|locked| locked.artifacts.into_values().map(|a| a.hash).collect())
+        .unwrap_or_default();
+    let removed = Cache::new(&settings.plan.cache_dir).gc(&keep).await?;
+    let bytes: usize = removed.iter().map(|e| e.size).sum();
+    tracing::info!("removed {} cache entries ({bytes} bytes)", removed.len());
+
+    Ok(())
+}
+
+/// Deletes the entire cache.
+pub async fn cache_clear(settings: &Settings) -> Result<()> {
+    Cache::new(&settings.plan.cache_dir).clear().await
+}
+
+fn resolve_extractor(
+    options: &PlanOptions,
+    spec: &Spec,
+    artifact: &Artifact,
+) -> Result<Option<Extractor>> {
+    artifact
+        .extractor
+        .as_ref()
+        .map(|name| {
+            Extractor::resolve(name, &spec.extractors, options.allow_custom_extractors)
+        })
+        .transpose()
+}
 
-use crate::downloader::Client;
-use color_eyre::{eyre::WrapErr, Result};
-use config::{sources::Source, ArtifactName};
-use indicatif::{ProgressState, ProgressStyle};
-use tokio::io::AsyncWrite;
+/// The signature `artifact` must have, if any, given its resolved `source`.
+fn plan_signature(
+    name: &ArtifactName,
+    artifact: &Artifact,
+    source: &Source,
+) -> Result<Option<PlannedSignature>> {
+    artifact
+        .signature
+        .as_ref()
+        .map(|signature| PlannedSignature::new(signature, source))
+        .transpose()
+        .wrap_err_with(|| format!("invalid signature for `{}`", name.0))
+}
 
-use crate::config::{LockedSpec, Spec};
+/// Reads and parses the spec from `fs`.
+pub async fn read_spec(fs: &impl Filesystem) -> Result<Spec> {
+    fs.read_spec().await?.parse()
+}
 
-pub async fn run() -> Result<()> {
-    let mut fs_io = MockedFs;
-    let spec: Spec = toml::from_str(&fs_io.spec_contents().await?)
-        .wrap_err("failed to parse spec toml")?;
-    let _locked: LockedSpec = toml::from_str(&fs_io.lockfile_contents().await?)
-        .wrap_err("failed to parse lockfile toml")?;
+/// Reads and parses the lockfile from `fs`, if there is one.
+pub async fn read_lockfile(fs: &impl Filesystem) -> Result<Option<LockedSpec>> {
+    let Some(contents) = fs.read_lockfile().await? else {
+        return Ok(None);
+    };
+    contents.parse().map(Some)
+}
 
-    let gh_token = fs_io.github_token_env();
+/// `local_root` is the directory that `path` sources are relative to.
+fn make_client(local_root: &Path) -> Result<Arc<dyn Downloader>> {
+    let gh_token = std::env::var("GITHUB_TOKEN").ok();
     if gh_token.is_some() {
         tracing::info!("Using provided github token");
     } else {
         tracing::warn!("No github token provided");
     }
-    let client = Client::new(gh_token)?;
+    Ok(Arc::new(Client::new(gh_token, local_root.to_owned())?))
+}
 
-    let sources = spec
-        .artifacts
-        .into_iter()
-        .map(|(name, art)| (name, art.source.clone()))
-        .collect();
-    let dp = DownloadPlan { sources };
-    dp.run(client, &mut fs_io).await
+fn make_progress(settings: &Settings) -> Arc<dyn ProgressSink> {
+    match settings.output {
+        OutputMode::Bars => Arc::new(Bars::default()),
+        OutputMode::JsonLines => Arc::new(JsonLines::new(std::io::stdout())),
+    }
+}
+
+/// The artifacts to fetch into an out dir, and how. Build one with
+/// [`DownloadPlan::new`].
+#[derive(Debug)]
+pub struct DownloadPlan {
+    out_dir: PathBuf,
+    artifacts: HashMap<ArtifactName, PlannedArtifact>,
+    cache: Cache,
+    /// Error instead of downloading artifacts that aren't cached.
+    offline: bool,
+    /// Redownload artifacts whose hash is not known, even if they are cached.
+    refresh: bool,
+    retry: RetryPolicy,
+    /// Time limit for downloading a single artifact, including retries.
+    timeout: Option<Duration>,
+    /// Maximum number of artifacts fetched at once.
+    jobs: NonZeroUsize,
 }
 
-struct DownloadPlan {
-    sources: HashMap<ArtifactName, Source>,
+/// A single artifact in a [`DownloadPlan`].
+#[derive(Debug)]
+struct PlannedArtifact {
+    source: Source,
+    /// The hash the downloaded artifact is verified against.
+    hash: Option<Hash>,
+    /// The hash recorded in the lockfile, if any. Only used to warn about changes
+    /// to artifacts with `hash = false`.
+    locked: Option<Integrity>,
+    extractor: Option<Extractor>,
+    /// Checked before the artifact is written to the out dir.
+    signature: Option<PlannedSignature>,
 }
 
 impl DownloadPlan {
-    async fn run(self, client: Client, fs_io: &mut MockedFs) -> Result<()> {
+    /// Plans fetching every artifact of `spec` as it was locked in `locked`, like
+    /// `artificer` without a subcommand does. Errors if `locked` is out of date.
+    pub fn new(
+        spec: &Spec,
+        locked: &LockedSpec,
+        options: &PlanOptions,
+    ) -> Result<Self> {
+        locked.check_up_to_date(spec)?;
+        let artifacts = spec
+            .artifacts
+            .iter()
+            .map(|(name, art)| {
+                let locked = &locked.artifacts[name];
+                let hash = match &art.hash {
+                    None => Hash::Hash(locked.hash.clone()),
+                    Some(hash) => hash.clone(),
+                };
+                let source = locked.resolved_source();
+                let planned = PlannedArtifact {
+                    signature: plan_signature(name, art, &source)?,
+                    source,
+                    hash: Some(hash),
+                    locked: Some(locked.hash.clone()),
+                    extractor: resolve_extractor(options, spec, art)?,
+                };
+                Ok((name.clone(), planned))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self::with_artifacts(spec, artifacts, options, false))
+    }
+
+    fn with_artifacts(
+        spec: &Spec,
+        artifacts: HashMap<ArtifactName, PlannedArtifact>,
+        options: &PlanOptions,
+        refresh: bool,
+    ) -> Self {
+        Self {
+            out_dir: spec.artificer.out_dir.clone(),
+            artifacts,
+            cache: Cache::new(&options.cache_dir),
+            offline: options.offline,
+            refresh,
+            retry: options.retry.clone(),
+            timeout: options.timeout,
+            jobs: options.jobs,
+        }
+    }
+
+    /// The artifacts that will be fetched, in no particular order.
+    pub fn artifacts(&self) -> impl Iterator<Item = &ArtifactName> {
+        self.artifacts.keys()
+    }
+
+    /// Fetches all artifacts into the out dir of `fs`, reporting progress to
+    /// `progress`. Artifacts are downloaded into the cache first, unless they are
+    /// already present.
+    ///
+    /// A failing artifact doesn't stop the others, and is recorded in the returned
+    /// report. Errors only if the plan as a whole couldn't run.
+    pub async fn run<F: Filesystem>(
+        self,
+        downloader: Arc<dyn Downloader>,
+        fs: &F,
+        progress: Arc<dyn ProgressSink>,
+    ) -> Result<PlanReport> {
         tracing::debug!("starting download plan");
-        let multi_progress = indicatif::MultiProgress::new();
-        let mut download_tasks: tokio::task::JoinSet<Result<u64>> =
-            tokio::task::JoinSet::new();
-        for (s_name, s) in self.sources {
-            let writer = fs_io.writer_from_artifact(&s_name, &s).await?;
-            let (reader, total_bytes) = match s {
-                Source::Github(s) => {
-                    crate::downloader::github::download_artifact(&client, s)
-                        .await
-                        .wrap_err("failed to download github source: {s:?}")?
-                }
+        let jobs = Arc::new(tokio::sync::Semaphore::new(self.jobs.get()));
+        let mut download_tasks: tokio::task::JoinSet<(
+            ArtifactName,
+            Result<Fetched<F::Writer>>,
+        )> = tokio::task::JoinSet::new();
+        let mut extractors = HashMap::new();
+        for (s_name, mut planned) in self.artifacts {
+            if let Some(extractor) = planned.extractor.take() {
+                extractors.insert(s_name.clone(), extractor);
+            }
+            let writer = fs.artifact_writer(&self.out_dir, &s_name).await?;
+            let fetch = FetchArtifact {
+                name: s_name.clone(),
+                planned,
+                cache: self.cache.clone(),
+                downloader: downloader.clone(),
+                progress: progress.clone(),
+                offline: self.offline,
+                refresh: self.refresh,
+                retry: self.retry.clone(),
+                timeout: self.timeout,
             };
-            let style = ProgressStyle::with_template("{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} ({msg})")
-        .unwrap()
-        .with_key("eta", |state: &ProgressState, w: &mut dyn Write| write!(w, "{:.1}s", state.eta().as_secs_f64()).unwrap())
-        .progress_chars("#>-");
-            let progress = multi_progress
-                .add(
-                    indicatif::ProgressBar::new(total_bytes)
-                        .with_message(s_name.0.clone())
-                        .with_style(style.clone()),
-                )
-                .wrap_async_read(Box::into_pin(reader));
+            let jobs = jobs.clone();
             download_tasks.spawn(async move {
-                tokio::time::sleep(Duration::from_millis(1000)).await;
-                let nbytes =
-                    tokio::io::copy(&mut pin!(progress), &mut Box::into_pin(writer))
-                        .await
-                        .wrap_err("failed to write body to writer")?;
-                assert_eq!(nbytes, total_bytes, "nbytes and total bytes didn't match");
-                Ok(nbytes)
+                let _permit = jobs.acquire_owned().await.expect("never closed");
+                (s_name, fetch.run(writer).await)
             });
         }
 
+        let mut succeeded = Vec::new();
+        let mut failed = Vec::new();
         while let Some(result) = download_tasks.join_next().await {
-            let _nbytes = result.wrap_err("task panicked")?.wrap_err("task errored")?;
+            let (name, fetched) = result.wrap_err("task panicked")?;
+            let extractor = extractors.get(&name);
+            let committed = match fetched {
+                Ok(fetched) => fs
+                    .commit_artifact(
+                        &self.out_dir,
+                        &name,
+                        fetched.writer,
+                        &fetched.hash,
+                        extractor,
+                    )
+                    .await
+                    .wrap_err_with(|| format!("failed to commit artifact {}", name.0))
+                    .map(|()| ArtifactSummary {
+                        artifact: name.clone(),
+                        hash: fetched.hash,
+                        bytes: fetched.bytes,
+                        cached: fetched.cached,
+                        duration: fetched.started.elapsed(),
+                    }),
+                Err(err) => Err(err),
+            };
+            match committed {
+                Ok(summary) => {
+                    if let Some(extractor) = extractor {
+                        progress.event(Event::Extracted {
+                            artifact: &name.0,
+                            extractor: extractor.to_string(),
+                        });
+                    }
+                    succeeded.push(summary);
+                }
+                Err(error) => {
+                    progress.event(Event::Failed {
+                        artifact: &name.0,
+                        error: format!("{error:#}"),
+                    });
+                    failed.push(ArtifactFailure {
+                        artifact: name,
+                        error,
+                    });
+                }
+            }
         }
-        Ok(())
+        succeeded.sort_by(|a, b| a.artifact.cmp(&b.artifact));
+        failed.sort_by(|a, b| a.artifact.cmp(&b.artifact));
+        progress.event(Event::Summary {
+            artifacts: &succeeded,
+        });
+
+        Ok(PlanReport { succeeded, failed })
     }
 }
 
-struct MockedFs;
+/// The outcome of [`DownloadPlan::run`].
+#[derive(Debug)]
+pub struct PlanReport {
+    /// Artifacts that were committed to the out dir, sorted by name.
+    pub succeeded: Vec<ArtifactSummary>,
+    /// Artifacts that couldn't be fetched, verified or committed, sorted by name.
+    pub failed: Vec<ArtifactFailure>,
+}
+
+/// An artifact of a [`DownloadPlan`] that failed.
+#[derive(Debug)]
+pub struct ArtifactFailure {
+    pub artifact: ArtifactName,
+    pub error: color_eyre::Report,
+}
+
+impl PlanReport {
+    /// The summaries of all artifacts, or an error if any of them failed. Errors
+    /// other than the first are logged.
+    pub fn into_result(self) -> Result<Vec<ArtifactSummary>> {
+        let count = self.failed.len();
+        let mut failed = self.failed.into_iter();
+        let Some(first) = failed.next() else {
+            return Ok(self.succeeded);
+        };
+        for ArtifactFailure { artifact, error } in failed {
+            tracing::error!("artifact `{}` failed: {error:?}", artifact.0);
+        }
+        Err(first.error).wrap_err_with(|| {
+            format!(
+                "{count} artifact(s) failed, including `{}`",
+                first.artifact.0
+            )
+        })
+    }
+}
+
+/// An artifact that was fetched and verified, but not yet committed.
+struct Fetched<W> {
+    writer: W,
+    hash: Integrity,
+    bytes: u64,
+    cached: bool,
+    started: Instant,
+}
+
+/// Fetches a single artifact of a [`DownloadPlan`].
+struct FetchArtifact {
+    name: ArtifactName,
+    planned: PlannedArtifact,
+    cache: Cache,
+    downloader: Arc<dyn Downloader>,
+    progress: Arc<dyn ProgressSink>,
+    offline: bool,
+    refresh: bool,
+    retry: RetryPolicy,
+    timeout: Option<Duration>,
+}
+
+impl FetchArtifact {
+    /// Writes the artifact to `writer`, downloading it into the cache first if
+    /// needed.
+    async fn run<W: tokio::io::AsyncWrite + Unpin>(
+        self,
+        writer: W,
+    ) -> Result<Fetched<W>> {
+        let started = Instant::now();
+        let Self { name, planned, .. } = &self;
+        let cached = self
+            .cache
+            .lookup(
+                &planned.source,
+                planned.hash.as_ref(),
+                self.refresh,
+                self.offline,
+            )
+            .await?;
+        self.progress.event(Event::Started {
+            artifact: &name.0,
+            cached: cached.is_some(),
+        });
+        let (cached_integrity, fresh) = match cached {
+            Some(integrity) => (integrity, false),
+            None if self.offline => {
+                bail!("artifact `{}` is not cached and offline mode is on", name.0)
+            }
+            None => {
+                let integrity = self
+                    .timed(self.download())
+                    .await
+                    .wrap_err_with(|| format!("failed to download `{}`", name.0))?;
+                (integrity, true)
+            }
+        };
 
-impl MockedFs {
-    async fn spec_contents(&self) -> Result<String> {
-        let path = Path::new(concat!(
-            env!("CARGO_MANIFEST_DIR"),
-            "/src/config/example.toml"
-        ));
-        tokio::fs::read_to_string(path)
+        let mut writer =
+            HashingWriter::new(writer, integrity::algorithm_for(planned.hash.as_ref()));
+        let mut reader = self.cache.open(&cached_integrity).await?;
+        let bytes = tokio::io::copy(&mut reader, &mut writer)
             .await
-            .wrap_err("failed to read spec file")
+            .wrap_err("failed to copy artifact from cache")?;
+        if let Err(err) = reader.check() {
+            self.cache.remove(&cached_integrity).await?;
+            return Err(err).wrap_err_with(|| {
+                format!(
+                    "cache entry for `{}` was corrupted and has been removed, \
+                    please try again",
+                    name.0
+                )
+            });
+        }
+
+        let (writer, actual) = writer.finish();
+        if let Err(err) = integrity::verify(name, planned.hash.as_ref(), &actual) {
+            if fresh {
+                // Don't let later runs pick up the bad artifact by its source.
+                self.cache.forget(&planned.source).await?;
+            }
+            return Err(err);
+        }
+        if let Some(signature) = &planned.signature {
+            if let Err(err) = self.verify_signature(signature, &cached_integrity).await
+            {
+                if fresh {
+                    self.cache.forget(&planned.source).await?;
+                }
+                return Err(err).wrap_err_with(|| {
+                    format!("failed to verify the signature of `{}`", name.0)
+                });
+            }
+            tracing::debug!("artifact `{}` has a valid signature", name.0);
+        }
+        tracing::debug!("artifact `{}` has hash {actual}", name.0);
+        if let Some(locked) = &planned.locked {
+            if locked.matches(&actual).is_none() {
+                tracing::warn!(
+                    "artifact `{}` changed since it was locked: {locked} -> {actual}",
+                    name.0
+                );
+            }
+        }
+        self.progress.event(Event::Verified {
+            artifact: &name.0,
+            hash: &actual,
+            bytes,
+        });
+
+        Ok(Fetched {
+            writer,
+            hash: actual,
+            bytes,
+            cached: !fresh,
+            started,
+        })
+    }
+
+    /// Runs `fut`, failing if it takes longer than the timeout.
+    async fn timed<T>(&self, fut: impl Future<Output = Result<T>>) -> Result<T> {
+        match self.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, fut)
+                .await
+                .map_err(|_| eyre!("timed out after {timeout:?}"))
+                .and_then(|result| result),
+            None => fut.await,
+        }
+    }
+
+    /// Checks the cached artifact with `integrity` against `expected`. The
+    /// signature file is cached by its source like artifacts without a known hash,
+    /// and forgotten if it doesn't verify.
+    async fn verify_signature(
+        &self,
+        expected: &PlannedSignature,
+        integrity: &Integrity,
+    ) -> Result<()> {
+        let source = &expected.source;
+        let cached = self
+            .cache
+            .lookup(source, None, self.refresh, self.offline)
+            .await?;
+        let sig_integrity = match cached {
+            Some(integrity) => integrity,
+            None if self.offline => bail!(
+                "signature at {} is not cached and offline mode is on",
+                source.cache_key()
+            ),
+            None => self
+                .timed(self.download_signature(source))
+                .await
+                .wrap_err("failed to download signature")?,
+        };
+
+        let result = async {
+            let contents = self.cache.read(&sig_integrity).await?;
+            let decoded = signature::decode(&contents)?;
+            let reader = self.cache.open(integrity).await?;
+            signature::verify(&expected.public_key, &decoded, reader).await
+        }
+        .await;
+        if result.is_err() {
+            self.cache.forget(source).await?;
+        }
+        result
     }
 
-    fn github_token_env(&self) -> Option<String> {
-        std::env::var("GITHUB_TOKEN").ok()
+    /// Downloads the signature file at `source` into the cache, retrying like
+    /// artifacts but without resuming, as signatures are tiny.
+    async fn download_signature(&self, source: &Source) -> Result<Integrity> {
+        let mut attempt = 0;
+        let contents = loop {
+            let result = async {
+                let download = self.downloader.download(source, 0).await?;
+                let mut contents = Vec::new();
+                download
+                    .reader
+                    .take(signature::MAX_SIGNATURE_SIZE + 1)
+                    .read_to_end(&mut contents)
+                    .await
+                    .map_err(Interrupted)?;
+                Ok::<_, color_eyre::Report>(contents)
+            }
+            .await;
+            match result {
+                Ok(contents) => break contents,
+                Err(err) => {
+                    attempt += 1;
+                    let Some(delay) = self.retry.delay(attempt, &err) else {
+                        return Err(err);
+                    };
+                    tracing::warn!(
+                        "downloading the signature of `{}` failed, retrying in \
+                        {delay:?} ({attempt}/{}): {err:#}",
+                        self.name.0,
+                        self.retry.retries
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+            }
+        };
+        if contents.len() as u64 > signature::MAX_SIGNATURE_SIZE {
+            bail!(
+                "signature is bigger than {} bytes",
+                signature::MAX_SIGNATURE_SIZE
+            );
+        }
+
+        self.cache
+            .insert(source, integrity::DEFAULT_ALGORITHM, None, &contents[..])
+            .await
     }
 
-    async fn lockfile_contents(&self) -> Result<String> {
-        let path = Path::new(concat!(
-            env!("CARGO_MANIFEST_DIR"),
-            "/src/config/example.lock"
-        ));
-        tokio::fs::read_to_string(path)
+    /// Downloads the artifact into the cache, returning the cached integrity.
+    ///
+    /// The download goes through a partial file, so that failed attempts can be
+    /// resumed where they left off. The partial file is kept across runs only if
+    /// the artifact's hash is known, as that catches a changed artifact being
+    /// spliced onto an old partial download. It is locked while in use, as other
+    /// artificer runs may share the cache.
+    async fn download(&self) -> Result<Integrity> {
+        let source = &self.planned.source;
+        let partial_path = self.cache.partial_path(&self.name, source);
+        if let Some(parent) = partial_path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .wrap_err("failed to create partial download dir")?;
+        }
+        let _lock = self.cache.lock_partial(&partial_path).await?;
+        let pinned = matches!(self.planned.hash, Some(Hash::Hash(_)));
+        let mut partial = tokio::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(!pinned)
+            .open(&partial_path)
+            .await
+            .wrap_err("failed to open partial download")?;
+
+        let mut attempt = 0;
+        let size = loop {
+            match self.download_attempt(&mut partial).await {
+                Ok(size) => break size,
+                Err(err) => {
+                    attempt += 1;
+                    let Some(delay) = self.retry.delay(attempt, &err) else {
+                        return Err(err);
+                    };
+                    tracing::warn!(
+                        "downloading `{}` failed, retrying in {delay:?} \
+                        ({attempt}/{}): {err:#}",
+                        self.name.0,
+                        self.retry.retries
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+            }
+        };
+
+        partial
+            .rewind()
             .await
-            .wrap_err("failed to read lock file")
+            .wrap_err("failed to seek partial download")?;
+        let inserted = self
+            .cache
+            .insert(
+                source,
+                integrity::algorithm_for(self.planned.hash.as_ref()),
+                size,
+                &mut partial,
+            )
+            .await;
+        drop(partial);
+        tokio::fs::remove_file(&partial_path)
+            .await
+            .wrap_err("failed to remove partial download")?;
+
+        inserted
     }
 
-    async fn writer_from_artifact(
+    /// Appends the rest of the artifact to `partial`, resuming at its current end
+    /// if the source supports it. Returns the size of the artifact, if known.
+    async fn download_attempt(
         &self,
-        name: &ArtifactName,
-        _src: &Source,
-    ) -> Result<Box<dyn AsyncWrite + Send>> {
-        // TODO: Use filesystem
-        let p = PathBuf::from(std::env::var("HOME").unwrap())
-            .join("Downloads")
-            .join(&name.0);
-        let f: Box<dyn AsyncWrite + Send> = Box::new(
-            tokio::fs::File::create(p)
+        partial: &mut tokio::fs::File,
+    ) -> Result<Option<u64>> {
+        let source = &self.planned.source;
+        let offset = partial
+            .seek(SeekFrom::End(0))
+            .await
+            .wrap_err("failed to seek partial download")?;
+        let mut download = match self.downloader.download(source, offset).await {
+            // The partial download is already complete or stale, start over.
+            Err(err) if offset > 0 && is_range_not_satisfiable(&err) => {
+                self.downloader.download(source, 0).await?
+            }
+            result => result?,
+        };
+        if download.offset > offset {
+            bail!(
+                "source resumed at byte {} but only {offset} bytes were downloaded",
+                download.offset
+            );
+        }
+        if download.offset < offset {
+            partial
+                .set_len(download.offset)
+                .await
+                .wrap_err("failed to truncate partial download")?;
+            partial
+                .seek(SeekFrom::Start(download.offset))
                 .await
-                .wrap_err("failed to create file")?,
+                .wrap_err("failed to seek partial download")?;
+        }
+        if download.offset > 0 {
+            tracing::info!(
+                "resuming download of `{}` at byte {}",
+                self.name.0,
+                download.offset
+            );
+        }
+        let mut position = download.offset;
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            let n = download.reader.read(&mut buf).await.map_err(Interrupted)?;
+            if n == 0 {
+                break;
+            }
+            partial
+                .write_all(&buf[..n])
+                .await
+                .wrap_err("failed to write partial download")?;
+            position += n as u64;
+            self.progress.event(Event::Progress {
+                artifact: &self.name.0,
+                bytes: position,
+                total: download.size,
+            });
+        }
+        partial
+            .flush()
+            .await
+            .wrap_err("failed to flush partial download")?;
+        if download.size.is_some_and(|size| position < size) {
+            return Err(Interrupted(std::io::ErrorKind::UnexpectedEof.into()).into());
+        }
+
+        Ok(download.size)
+    }
+}
+
+fn is_range_not_satisfiable(err: &color_eyre::eyre::Report) -> bool {
+    err.chain().any(|cause| {
+        cause
+            .downcast_ref::<HttpError>()
+            .is_some_and(|e| e.status == reqwest::StatusCode::RANGE_NOT_SATISFIABLE)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::HashMap, num::NonZeroUsize, path::Path, sync::Arc, time::Duration,
+    };
+
+    use cacache::Integrity;
+    use color_eyre::Result;
+
+    use super::{
+        read_lockfile, read_spec, DownloadPlan, PlanOptions, PlannedArtifact,
+        RetryPolicy,
+    };
+    use crate::cache::Cache;
+    use crate::config::{
+        sources::{self, Source},
+        ArtifactName, Hash, LockedArtifact, LockedSpec,
+    };
+    use crate::downloader::{test_server, Client};
+    use crate::fs::InMemoryFs;
+    use crate::progress::{JsonLines, ProgressSink};
+    use crate::signature::{test_vectors, PlannedSignature};
+
+    fn plan(cache: &Path, source: &Source, hash: Option<Hash>) -> DownloadPlan {
+        DownloadPlan {
+            out_dir: "out".into(),
+            artifacts: HashMap::from([(
+                ArtifactName("foo".to_owned()),
+                PlannedArtifact {
+                    source: source.clone(),
+                    hash,
+                    locked: None,
+                    extractor: None,
+                    signature: None,
+                },
+            )]),
+            cache: Cache::new(cache),
+            offline: false,
+            refresh: false,
+            retry: RetryPolicy {
+                retries: 2,
+                initial_backoff: Duration::ZERO,
+                max_backoff: Duration::ZERO,
+                max_retry_after: Duration::ZERO,
+            },
+            timeout: Some(Duration::from_secs(30)),
+            jobs: NonZeroUsize::new(1).unwrap(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_download_plan_url() -> Result<()> {
+        let body = b"hello from the server".to_vec();
+        let (addr, server) = test_server::serve(body.clone()).await;
+        let cache_dir = tempfile::tempdir()?;
+        let fs = InMemoryFs::default();
+        let source = Source::Url(sources::Url {
+            url: format!("http://{addr}/foo.bin"),
+        });
+        let downloader = Arc::new(Client::new(None, ".".into())?);
+        let events = Arc::new(JsonLines::new(Vec::new()));
+
+        let expected = Integrity::from(&body);
+        let summaries = plan(
+            cache_dir.path(),
+            &source,
+            Some(Hash::Hash(expected.clone())),
+        )
+        .run(downloader.clone(), &fs, events.clone())
+        .await?
+        .into_result()?;
+        assert_eq!(summaries.len(), 1);
+        assert_eq!(summaries[0].artifact, ArtifactName("foo".to_owned()));
+        assert_eq!(summaries[0].hash, expected);
+        assert_eq!(summaries[0].bytes, body.len() as u64);
+        assert!(!summaries[0].cached);
+        assert_eq!(fs.artifacts.lock().unwrap()[Path::new("out/foo/foo")], body);
+
+        let events = Arc::try_unwrap(events).ok().unwrap().into_inner();
+        let events: Vec<String> = String::from_utf8(events)?
+            .lines()
+            .map(|l| {
+                let event: serde_json::Value = serde_json::from_str(l).unwrap();
+                event["event"].as_str().unwrap().to_owned()
+            })
+            .collect();
+        assert_eq!(events, ["started", "progress", "verified", "summary"]);
+
+        let wrong = Some(Hash::Hash(Integrity::from(b"something else")));
+        let events = Arc::new(JsonLines::new(Vec::new()));
+        let report = plan(cache_dir.path(), &source, wrong)
+            .run(downloader, &fs, events.clone())
+            .await?;
+        assert!(report.succeeded.is_empty());
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(report.failed[0].artifact, ArtifactName("foo".to_owned()));
+        assert!(report.into_result().is_err());
+        let events = Arc::try_unwrap(events).ok().unwrap().into_inner();
+        assert!(String::from_utf8(events)?.contains(r#""event":"failed""#));
+
+        server.abort();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_download_plan_resumes() -> Result<()> {
+        let body: Vec<u8> = (0..100_000u32).flat_map(|i| i.to_le_bytes()).collect();
+        let downloader = Arc::new(Client::new(None, ".".into())?);
+        let fs = InMemoryFs::default();
+        let cache_dir = tempfile::tempdir()?;
+        let progress: Arc<dyn ProgressSink> = Arc::new(JsonLines::new(std::io::sink()));
+
+        // Two interrupted attempts are within the retry budget.
+        let (addr, server) = test_server::serve_flaky(body.clone(), 2).await;
+        let source = Source::Url(sources::Url {
+            url: format!("http://{addr}/foo.bin"),
+        });
+        plan(cache_dir.path(), &source, None)
+            .run(downloader.clone(), &fs, progress.clone())
+            .await?
+            .into_result()?;
+        assert_eq!(fs.artifacts.lock().unwrap()[Path::new("out/foo/foo")], body);
+        let cache = Cache::new(cache_dir.path());
+        assert!(!cache
+            .partial_path(&ArtifactName("foo".to_owned()), &source)
+            .exists());
+        server.abort();
+
+        // Three are not.
+        let (addr, server) = test_server::serve_flaky(body.clone(), 3).await;
+        let source = Source::Url(sources::Url {
+            url: format!("http://{addr}/foo.bin"),
+        });
+        assert!(plan(cache_dir.path(), &source, None)
+            .run(downloader, &fs, progress)
+            .await?
+            .into_result()
+            .is_err());
+        server.abort();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_download_plan_signature() -> Result<()> {
+        let downloader = Arc::new(Client::new(None, ".".into())?);
+        let cache_dir = tempfile::tempdir()?;
+        let progress: Arc<dyn ProgressSink> = Arc::new(JsonLines::new(std::io::sink()));
+        let (sig_addr, sig_server) =
+            test_server::serve(test_vectors::SIGNATURE.as_bytes().to_vec()).await;
+        let signature = PlannedSignature {
+            source: Source::Url(sources::Url {
+                url: format!("http://{sig_addr}/foo.bin.minisig"),
+            }),
+            public_key: minisign_verify::PublicKey::from_base64(
+                test_vectors::PUBLIC_KEY,
+            )?,
+        };
+        let signed_plan = |source: &Source| {
+            let mut plan = plan(cache_dir.path(), source, None);
+            let artifact = plan.artifacts.values_mut().next().unwrap();
+            artifact.signature = Some(signature.clone());
+            plan
+        };
+
+        let (addr, server) = test_server::serve(test_vectors::SIGNED.to_vec()).await;
+        let source = Source::Url(sources::Url {
+            url: format!("http://{addr}/foo.bin"),
+        });
+        let fs = InMemoryFs::default();
+        signed_plan(&source)
+            .run(downloader.clone(), &fs, progress.clone())
+            .await?
+            .into_result()?;
+        assert_eq!(
+            fs.artifacts.lock().unwrap()[Path::new("out/foo/foo")],
+            test_vectors::SIGNED
+        );
+        server.abort();
+
+        let (addr, server) = test_server::serve(b"tampered".to_vec()).await;
+        let source = Source::Url(sources::Url {
+            url: format!("http://{addr}/foo.bin"),
+        });
+        let fs = InMemoryFs::default();
+        let err = signed_plan(&source)
+            .run(downloader, &fs, progress)
+            .await?
+            .into_result()
+            .unwrap_err();
+        assert!(format!("{err:#}").contains("signature"), "{err:#}");
+        assert!(fs.artifacts.lock().unwrap().is_empty());
+        server.abort();
+
+        sig_server.abort();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_download_plan_from_spec() -> Result<()> {
+        let body = b"hello from the server".to_vec();
+        let (addr, server) = test_server::serve(body.clone()).await;
+        let downloader = Arc::new(Client::new(None, ".".into())?);
+        let cache_dir = tempfile::tempdir()?;
+        let options = PlanOptions::new(cache_dir.path());
+        let name = ArtifactName("foo".to_owned());
+        let fs = InMemoryFs {
+            spec: format!(
+                "[artificer]\nversion = \"0.0.0\"\nout-dir = \"out\"\n\
+                [artifacts.foo]\nsource = \"url\"\nurl = \"http://{addr}/foo.bin\"\n\
+                [extractors]\n"
+            ),
+            ..Default::default()
+        };
+        let spec = read_spec(&fs).await?;
+        assert!(
+            DownloadPlan::new(&spec, &LockedSpec::new(), &options).is_err(),
+            "lockfile is out of date"
         );
-        Ok(f)
+
+        let source = spec.artifacts[&name].source.clone();
+        let mut locked = LockedSpec::new();
+        locked.artifacts.insert(
+            name.clone(),
+            LockedArtifact::new(source.clone(), &source, Integrity::from(&body)),
+        );
+        *fs.lockfile.lock().unwrap() = Some(locked.to_toml()?);
+        let locked = read_lockfile(&fs).await?.unwrap();
+
+        let plan = DownloadPlan::new(&spec, &locked, &options)?;
+        assert_eq!(plan.artifacts().collect::<Vec<_>>(), [&name]);
+        let progress = Arc::new(JsonLines::new(std::io::sink()));
+        let report = plan.run(downloader, &fs, progress).await?;
+        assert!(report.failed.is_empty());
+        assert_eq!(report.succeeded.len(), 1);
+        assert_eq!(report.succeeded[0].hash, Integrity::from(&body));
+        assert_eq!(fs.artifacts.lock().unwrap()[Path::new("out/foo/foo")], body);
+
+        server.abort();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_download_plan_redownloads_mutable_artifact() -> Result<()> {
+        let body = b"changed upstream".to_vec();
+        let (addr, server) = test_server::serve(body.clone()).await;
+        let downloader = Arc::new(Client::new(None, ".".into())?);
+        let cache_dir = tempfile::tempdir()?;
+        let options = PlanOptions::new(cache_dir.path());
+        let name = ArtifactName("foo".to_owned());
+        let fs = InMemoryFs {
+            spec: format!(
+                "[artificer]\nversion = \"0.0.0\"\nout-dir = \"out\"\n\
+                [artifacts.foo]\nsource = \"url\"\nurl = \"http://{addr}/foo.bin\"\n\
+                hash = false\n[extractors]\n"
+            ),
+            ..Default::default()
+        };
+        let spec = read_spec(&fs).await?;
+        let source = spec.artifacts[&name].source.clone();
+        let mut locked = LockedSpec::new();
+        locked.artifacts.insert(
+            name.clone(),
+            LockedArtifact::new(source.clone(), &source, Integrity::from(b"locked")),
+        );
+
+        // Not verified against the lockfile, which it no longer matches
+        let plan = DownloadPlan::new(&spec, &locked, &options)?;
+        let progress = Arc::new(JsonLines::new(std::io::sink()));
+        let report = plan.run(downloader, &fs, progress).await?;
+        assert!(report.failed.is_empty());
+        assert!(!report.succeeded[0].cached);
+        assert_eq!(fs.artifacts.lock().unwrap()[Path::new("out/foo/foo")], body);
+
+        server.abort();
+        Ok(())
     }
 }
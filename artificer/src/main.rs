@@ -1,6 +1,9 @@
+use std::{io::IsTerminal, num::NonZeroUsize, path::PathBuf, time::Duration};
+
+use artificer::{OutputMode, PlanOptions, RetryPolicy, Settings};
 use build_info::{make_build_info, BuildInfo};
-use clap::Parser;
-use color_eyre::Result;
+use clap::{Parser, Subcommand};
+use color_eyre::{eyre::bail, Result};
 use tracing_subscriber::filter::LevelFilter;
 use tracing_subscriber::prelude::*;
 use tracing_subscriber::EnvFilter;
@@ -12,7 +15,7 @@ const BUILD_INFO: BuildInfo = make_build_info!();
 async fn main() -> Result<()> {
     color_eyre::install()?;
     tracing_subscriber::registry()
-        .with(tracing_subscriber::fmt::layer())
+        .with(tracing_subscriber::fmt::layer().with_writer(std::io::stderr))
         .with(
             EnvFilter::builder()
                 .with_default_directive(LevelFilter::INFO.into())
@@ -20,14 +23,123 @@ async fn main() -> Result<()> {
         )
         .init();
 
-    let _args = Cli::parse();
+    let args = Cli::parse();
+    let settings = Settings {
+        spec_path: args.spec,
+        plan: PlanOptions {
+            cache_dir: match args.cache_dir {
+                Some(dir) => dir,
+                None => artificer::default_cache_dir()?,
+            },
+            offline: args.offline,
+            allow_custom_extractors: args.allow_custom_extractors,
+            retry: RetryPolicy {
+                retries: args.retries,
+                ..Default::default()
+            },
+            timeout: args.timeout.map(Duration::from_secs),
+            jobs: args.jobs,
+        },
+        output: if args.quiet || !std::io::stderr().is_terminal() {
+            OutputMode::JsonLines
+        } else {
+            OutputMode::Bars
+        },
+    };
 
-    artificer::run().await
+    match args.command {
+        None => artificer::run(&settings).await,
+        Some(Commands::Lock) => artificer::lock(&settings).await,
+        Some(Commands::Update { names }) => artificer::update(&settings, &names).await,
+        Some(Commands::Verify) => {
+            let problems = artificer::verify(&settings).await?;
+            for problem in &problems {
+                println!("{problem}");
+            }
+            if !problems.is_empty() {
+                bail!("found {} problem(s) in the out dir", problems.len());
+            }
+            Ok(())
+        }
+        Some(Commands::Cache(CacheCommands::Ls)) => {
+            for entry in artificer::cache_ls(&settings)? {
+                println!("{}\t{}\t{}", entry.key, entry.integrity, entry.size);
+            }
+            Ok(())
+        }
+        Some(Commands::Cache(CacheCommands::Gc)) => {
+            artificer::cache_gc(&settings).await
+        }
+        Some(Commands::Cache(CacheCommands::Clear)) => {
+            artificer::cache_clear(&settings).await
+        }
+    }
 }
 
 #[derive(Parser, Debug)]
 #[command(about, author, version=BUILD_INFO.git.describe, styles=make_clap_v3_styles())]
-struct Cli {}
+struct Cli {
+    /// Path to the spec file. The lockfile is expected to be next to it.
+    #[arg(long, default_value = "./artificer.toml")]
+    spec: PathBuf,
+    /// Location of the download cache. Defaults to `$XDG_CACHE_HOME/artificer/store`.
+    #[arg(long)]
+    cache_dir: Option<PathBuf>,
+    /// Only use cached artifacts, erroring instead of accessing the network.
+    #[arg(long)]
+    offline: bool,
+    /// Allow running the custom extractors declared in the spec. These are
+    /// arbitrary shell commands.
+    #[arg(long)]
+    allow_custom_extractors: bool,
+    /// How many times to retry a download that failed because of a network error,
+    /// a server error or rate limiting. Waits exponentially longer between
+    /// attempts.
+    #[arg(long, default_value_t = 5)]
+    retries: u32,
+    /// Time limit in seconds for downloading a single artifact, including
+    /// retries. Interrupted downloads of artifacts with a known hash are resumed
+    /// on the next run.
+    #[arg(long, value_name = "SECONDS")]
+    timeout: Option<u64>,
+    /// Maximum number of artifacts to download at once.
+    #[arg(long, short, default_value = "4")]
+    jobs: NonZeroUsize,
+    /// Print JSON lines to stdout instead of progress bars, one per event.
+    /// Implied when stderr is not a terminal.
+    #[arg(long, short)]
+    quiet: bool,
+    /// If omitted, downloads all artifacts, erroring if the lockfile is out of date.
+    #[command(subcommand)]
+    command: Option<Commands>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Downloads every artifact in the spec and writes a new lockfile.
+    Lock,
+    /// Re-downloads the given artifacts and updates their lockfile entries.
+    Update {
+        /// Names of the artifacts to update. Updates all artifacts if omitted.
+        names: Vec<String>,
+    },
+    /// Checks, without network access, that the out dir matches the lockfile.
+    /// Reports missing, extra and corrupted files.
+    Verify,
+    /// Manages the download cache.
+    #[command(subcommand)]
+    Cache(CacheCommands),
+}
+
+#[derive(Subcommand, Debug)]
+enum CacheCommands {
+    /// Lists all cached artifacts.
+    Ls,
+    /// Removes cached artifacts that aren't referenced by the lockfile.
+    Gc,
+    /// Deletes the entire cache.
+    Clear,
+}
 
 /// Colors the CLI help
 fn make_clap_v3_styles() -> clap::builder::Styles {
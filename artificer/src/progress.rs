@@ -0,0 +1,295 @@
+//! Reporting of download progress, either as progress bars for humans or as JSON
+//! lines for scripts.
+
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    io::Write,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use cacache::Integrity;
+use indicatif::{HumanBytes, MultiProgress, ProgressBar, ProgressState, ProgressStyle};
+use serde::{Serialize, Serializer};
+
+use crate::config::ArtifactName;
+
+/// How often [`JsonLines`] emits [`Event::Progress`] for a single artifact.
+const JSON_PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Something that happened while running a download plan.
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum Event<'a> {
+    /// Started fetching an artifact, either from the cache or the network.
+    Started { artifact: &'a str, cached: bool },
+    /// Some bytes of an artifact were downloaded.
+    Progress {
+        artifact: &'a str,
+        bytes: u64,
+        total: Option<u64>,
+    },
+    /// The artifact was fetched and matched its expected hash.
+    Verified {
+        artifact: &'a str,
+        hash: &'a Integrity,
+        bytes: u64,
+    },
+    /// The artifact was extracted into the out dir.
+    Extracted {
+        artifact: &'a str,
+        extractor: String,
+    },
+    /// Fetching, verifying or extracting the artifact failed.
+    Failed { artifact: &'a str, error: String },
+    /// Every artifact that was fetched successfully, sent once at the end.
+    Summary { artifacts: &'a [ArtifactSummary] },
+}
+
+/// The outcome of fetching a single artifact.
+#[derive(Debug, Clone, Serialize)]
+pub struct ArtifactSummary {
+    pub artifact: ArtifactName,
+    pub hash: Integrity,
+    pub bytes: u64,
+    /// Whether the artifact came from the cache.
+    pub cached: bool,
+    /// Time taken to fetch, verify and commit the artifact.
+    #[serde(rename = "duration_ms", serialize_with = "serialize_millis")]
+    pub duration: Duration,
+}
+
+fn serialize_millis<S: Serializer>(d: &Duration, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_u128(d.as_millis())
+}
+
+/// Receives the [`Event`]s of a download plan.
+pub trait ProgressSink: Send + Sync {
+    fn event(&self, event: Event<'_>);
+}
+
+/// Progress bars on stderr, followed by a summary table.
+#[derive(Debug, Default)]
+pub struct Bars {
+    multi: MultiProgress,
+    bars: Mutex<HashMap<String, ProgressBar>>,
+}
+
+impl Bars {
+    fn style() -> ProgressStyle {
+        ProgressStyle::with_template("{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} ({msg})")
+        .unwrap()
+        .with_key("eta", |state: &ProgressState, w: &mut dyn std::fmt::Write| write!(w, "{:.1}s", state.eta().as_secs_f64()).unwrap())
+        .progress_chars("#>-")
+    }
+}
+
+impl ProgressSink for Bars {
+    fn event(&self, event: Event<'_>) {
+        let mut bars = self.bars.lock().unwrap();
+        match event {
+            Event::Started { artifact, cached } => {
+                if cached {
+                    tracing::info!("using cached `{artifact}`");
+                    return;
+                }
+                let bar = self.multi.add(
+                    ProgressBar::new_spinner()
+                        .with_message(artifact.to_owned())
+                        .with_style(Self::style()),
+                );
+                bars.insert(artifact.to_owned(), bar);
+            }
+            Event::Progress {
+                artifact,
+                bytes,
+                total,
+            } => {
+                if let Some(bar) = bars.get(artifact) {
+                    if let Some(total) = total {
+                        bar.set_length(total);
+                    }
+                    bar.set_position(bytes);
+                }
+            }
+            Event::Verified { artifact, .. } => {
+                if let Some(bar) = bars.remove(artifact) {
+                    bar.finish();
+                }
+            }
+            Event::Extracted {
+                artifact,
+                extractor,
+            } => {
+                tracing::info!("extracted `{artifact}` with {extractor}");
+            }
+            Event::Failed { artifact, .. } => {
+                if let Some(bar) = bars.remove(artifact) {
+                    bar.abandon();
+                }
+            }
+            Event::Summary { artifacts } => {
+                // Failures already surface through the returned error.
+                let _ = self.multi.clear();
+                eprint!("{}", summary_table(artifacts));
+            }
+        }
+    }
+}
+
+/// Renders one row per artifact, with aligned columns.
+fn summary_table(artifacts: &[ArtifactSummary]) -> String {
+    let rows: Vec<[String; 4]> = artifacts
+        .iter()
+        .map(|a| {
+            let time = if a.cached {
+                "cached".to_owned()
+            } else {
+                format!("{:.1}s", a.duration.as_secs_f64())
+            };
+            [
+                a.artifact.0.clone(),
+                HumanBytes(a.bytes).to_string(),
+                time,
+                a.hash.to_string(),
+            ]
+        })
+        .collect();
+    let header = ["ARTIFACT", "SIZE", "TIME", "HASH"].map(str::to_owned);
+    let mut widths = [0; 4];
+    for row in std::iter::once(&header).chain(&rows) {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    let mut table = String::new();
+    for row in std::iter::once(&header).chain(&rows) {
+        let [name, size, time, hash] = row;
+        let _ = writeln!(
+            table,
+            "{name:<w0$}  {size:>w1$}  {time:>w2$}  {hash}",
+            w0 = widths[0],
+            w1 = widths[1],
+            w2 = widths[2],
+        );
+    }
+    table
+}
+
+/// One JSON object per line, for consumption by scripts. Progress events are
+/// rate limited.
+#[derive(Debug)]
+pub struct JsonLines<W> {
+    out: Mutex<W>,
+    last_progress: Mutex<HashMap<String, Instant>>,
+}
+
+impl<W: Write + Send> JsonLines<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out: Mutex::new(out),
+            last_progress: Mutex::new(HashMap::new()),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn into_inner(self) -> W {
+        self.out.into_inner().unwrap()
+    }
+}
+
+impl<W: Write + Send> ProgressSink for JsonLines<W> {
+    fn event(&self, event: Event<'_>) {
+        if let Event::Progress {
+            artifact,
+            bytes,
+            total,
+        } = &event
+        {
+            let done = total.is_some_and(|total| *bytes >= total);
+            let mut last = self.last_progress.lock().unwrap();
+            let now = Instant::now();
+            match last.get(*artifact) {
+                Some(t) if !done && now - *t < JSON_PROGRESS_INTERVAL => return,
+                _ => {
+                    last.insert(artifact.to_string(), now);
+                }
+            }
+        }
+
+        let mut out = self.out.lock().unwrap();
+        let result = serde_json::to_writer(&mut *out, &event)
+            .map_err(std::io::Error::from)
+            .and_then(|()| writeln!(out))
+            .and_then(|()| out.flush());
+        if let Err(err) = result {
+            tracing::warn!("failed to write progress event: {err}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use cacache::Integrity;
+
+    use super::{summary_table, ArtifactSummary, Event, JsonLines, ProgressSink};
+    use crate::config::ArtifactName;
+
+    #[test]
+    fn test_json_lines() {
+        let sink = JsonLines::new(Vec::new());
+        let hash = Integrity::from(b"foo");
+        sink.event(Event::Started {
+            artifact: "foo",
+            cached: false,
+        });
+        for bytes in [1, 2, 3] {
+            sink.event(Event::Progress {
+                artifact: "foo",
+                bytes,
+                total: Some(3),
+            });
+        }
+        sink.event(Event::Verified {
+            artifact: "foo",
+            hash: &hash,
+            bytes: 3,
+        });
+        let output = String::from_utf8(sink.into_inner()).unwrap();
+        let lines: Vec<serde_json::Value> = output
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+
+        assert_eq!(lines.len(), 4, "progress should be rate limited: {output}");
+        assert_eq!(lines[0]["event"], "started");
+        assert_eq!(lines[1]["event"], "progress");
+        assert_eq!(lines[1]["bytes"], 1);
+        assert_eq!(lines[2]["bytes"], 3, "completion is always reported");
+        assert_eq!(lines[3]["event"], "verified");
+        assert_eq!(lines[3]["hash"], hash.to_string());
+    }
+
+    #[test]
+    fn test_summary_table() {
+        let summary = ArtifactSummary {
+            artifact: ArtifactName("foo".to_owned()),
+            hash: Integrity::from(b"foo"),
+            bytes: 2048,
+            cached: false,
+            duration: Duration::from_millis(1500),
+        };
+        let table = summary_table(&[summary]);
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("ARTIFACT"));
+        assert!(lines[1].starts_with("foo     "));
+        assert!(lines[1].contains("2.00 KiB"));
+        assert!(lines[1].contains("1.5s"));
+        assert!(lines[1].ends_with(&Integrity::from(b"foo").to_string()));
+    }
+}
@@ -0,0 +1,162 @@
+//! Retrying of downloads that failed for reasons that may go away on their own,
+//! like dropped connections, overloaded servers or rate limits.
+
+use std::{fmt, time::Duration};
+
+use color_eyre::eyre::Report;
+
+use crate::downloader::HttpError;
+
+/// How often and how long to wait before retrying a failed download. The wait
+/// doubles with every attempt, unless the server tells us how long to wait.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Number of retries after the first attempt.
+    pub retries: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// Longest wait the server may ask for. If it asks for more, e.g. because
+    /// the hourly github rate limit is exhausted, we give up instead.
+    pub max_retry_after: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            retries: 5,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+            max_retry_after: Duration::from_secs(5 * 60),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// How long to wait before retry number `attempt`, starting at 1, after the
+    /// previous attempt failed with `err`. `None` means give up.
+    pub fn delay(&self, attempt: u32, err: &Report) -> Option<Duration> {
+        if attempt > self.retries {
+            return None;
+        }
+        let backoff = self.backoff(attempt);
+        for cause in err.chain() {
+            if let Some(err) = cause.downcast_ref::<HttpError>() {
+                return match err.retry_after {
+                    _ if !err.is_transient() => None,
+                    Some(wait) if wait > self.max_retry_after => None,
+                    Some(wait) => Some(wait),
+                    None => Some(backoff),
+                };
+            }
+            if let Some(err) = cause.downcast_ref::<reqwest::Error>() {
+                if err.is_connect() || err.is_timeout() || err.is_request() {
+                    return Some(backoff);
+                }
+            }
+            if let Some(octocrab::Error::GitHub { source, .. }) =
+                cause.downcast_ref::<octocrab::Error>()
+            {
+                if source.message.contains("rate limit") {
+                    return Some(backoff);
+                }
+            }
+            if cause.is::<Interrupted>() {
+                return Some(backoff);
+            }
+        }
+
+        None
+    }
+
+    fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+}
+
+/// The connection broke while streaming the body of a download.
+#[derive(Debug)]
+pub struct Interrupted(pub std::io::Error);
+
+impl fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "download was interrupted")
+    }
+}
+
+impl std::error::Error for Interrupted {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use color_eyre::eyre::{eyre, Report, WrapErr};
+    use reqwest::StatusCode;
+
+    use super::{Interrupted, RetryPolicy};
+    use crate::downloader::HttpError;
+
+    fn http_error(status: StatusCode, retry_after: Option<u64>) -> Report {
+        let err = HttpError {
+            url: "https://example.com/foo".parse().unwrap(),
+            status,
+            retry_after: retry_after.map(Duration::from_secs),
+        };
+        Err::<(), _>(err)
+            .wrap_err("failed to download")
+            .unwrap_err()
+    }
+
+    #[test]
+    fn test_delay() {
+        let policy = RetryPolicy {
+            retries: 3,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(3),
+            max_retry_after: Duration::from_secs(60),
+        };
+        let interrupted = Report::new(Interrupted(std::io::ErrorKind::Other.into()));
+
+        assert_eq!(policy.delay(1, &interrupted), Some(Duration::from_secs(1)));
+        assert_eq!(policy.delay(2, &interrupted), Some(Duration::from_secs(2)));
+        assert_eq!(policy.delay(3, &interrupted), Some(Duration::from_secs(3)));
+        assert_eq!(policy.delay(4, &interrupted), None, "out of retries");
+
+        let unavailable = http_error(StatusCode::SERVICE_UNAVAILABLE, None);
+        assert_eq!(policy.delay(1, &unavailable), Some(Duration::from_secs(1)));
+        let rate_limited = http_error(StatusCode::TOO_MANY_REQUESTS, Some(30));
+        assert_eq!(
+            policy.delay(1, &rate_limited),
+            Some(Duration::from_secs(30)),
+            "server provided wait should be honoured"
+        );
+        let github_limited = http_error(StatusCode::FORBIDDEN, Some(10));
+        assert_eq!(
+            policy.delay(1, &github_limited),
+            Some(Duration::from_secs(10))
+        );
+        let github_exhausted = http_error(StatusCode::FORBIDDEN, Some(3000));
+        assert_eq!(
+            policy.delay(1, &github_exhausted),
+            None,
+            "waits longer than max_retry_after should give up"
+        );
+        assert!(format!("{github_exhausted:#}").contains("retry after 3000s"));
+
+        assert_eq!(
+            policy.delay(1, &http_error(StatusCode::FORBIDDEN, None)),
+            None
+        );
+        assert_eq!(
+            policy.delay(1, &http_error(StatusCode::NOT_FOUND, None)),
+            None
+        );
+        assert_eq!(policy.delay(1, &eyre!("not a network error")), None);
+    }
+}
@@ -0,0 +1,140 @@
+//! Verification of detached [minisign](https://jedisct1.github.io/minisign/)
+//! signatures of artifacts.
+
+use color_eyre::{
+    eyre::{bail, WrapErr},
+    Result,
+};
+use minisign_verify::{PublicKey, Signature};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::config::{self, sources::Source};
+
+/// Signature files bigger than this are rejected. Minisign signatures are a few
+/// hundred bytes, trusted comment included.
+pub const MAX_SIGNATURE_SIZE: u64 = 64 * 1024;
+
+/// The signature an artifact of a download plan must have.
+#[derive(Debug, Clone)]
+pub struct PlannedSignature {
+    /// Where the signature file is downloaded from.
+    pub source: Source,
+    pub public_key: PublicKey,
+}
+
+impl PlannedSignature {
+    /// The signature described by `signature` in the spec, for the artifact at the
+    /// resolved `artifact` source.
+    pub fn new(signature: &config::Signature, artifact: &Source) -> Result<Self> {
+        let public_key = PublicKey::from_base64(signature.public_key.trim())
+            .wrap_err("invalid minisign public key")?;
+        Ok(Self {
+            source: signature.source(artifact)?,
+            public_key,
+        })
+    }
+}
+
+/// Parses the contents of a minisign signature file.
+pub fn decode(contents: &[u8]) -> Result<Signature> {
+    let contents =
+        std::str::from_utf8(contents).wrap_err("signature file is not utf-8")?;
+    Signature::decode(contents).wrap_err("failed to parse minisign signature")
+}
+
+/// Checks that `signature` is a valid signature of the contents of `reader` by
+/// `public_key`. Prehashed signatures, the default since minisign 0.10, are
+/// verified while streaming. Legacy signatures need the whole artifact in memory.
+pub async fn verify(
+    public_key: &PublicKey,
+    signature: &Signature,
+    mut reader: impl AsyncRead + Unpin,
+) -> Result<()> {
+    let result = match public_key.verify_stream(signature) {
+        Ok(mut verifier) => {
+            let mut buf = vec![0; 64 * 1024];
+            loop {
+                let n = reader
+                    .read(&mut buf)
+                    .await
+                    .wrap_err("failed to read artifact")?;
+                if n == 0 {
+                    break;
+                }
+                verifier.update(&buf[..n]);
+            }
+            verifier.finalize()
+        }
+        Err(minisign_verify::Error::UnsupportedLegacyMode) => {
+            let mut contents = Vec::new();
+            reader
+                .read_to_end(&mut contents)
+                .await
+                .wrap_err("failed to read artifact")?;
+            public_key.verify(&contents, signature, true)
+        }
+        Err(err) => Err(err),
+    };
+    match result {
+        Ok(()) => Ok(()),
+        Err(minisign_verify::Error::UnexpectedKeyId) => {
+            bail!("artifact was signed by a different key than the trusted one")
+        }
+        Err(err) => Err(err).wrap_err("invalid signature"),
+    }
+}
+
+#[cfg(test)]
+pub mod test_vectors {
+    //! A signature of `b"test"`, from the minisign-verify docs.
+
+    pub const PUBLIC_KEY: &str =
+        "RWQf6LRCGA9i53mlYecO4IzT51TGPpvWucNSCh1CBM0QTaLn73Y7GFO3";
+
+    pub const SIGNED: &[u8] = b"test";
+
+    pub const SIGNATURE: &str = "untrusted comment: signature from minisign secret key
+RUQf6LRCGA9i559r3g7V1qNyJDApGip8MfqcadIgT9CuhV3EMhHoN1mGTkUidF/z7SrlQgXdy8ofjb7bNJJylDOocrCo8KLzZwo=
+trusted comment: timestamp:1633700835\tfile:test\tprehashed
+wLMDjy9FLAuxZ3q4NlEvkgtyhrr0gtTu6KC4KBJdITbbOeAi1zBIYo0v4iTgt8jJpIidRJnp94ABQkJAgAooBQ==
+";
+
+    /// Same key and data, but made by an old minisign without prehashing.
+    pub const LEGACY_SIGNATURE: &str = "untrusted comment: signature from minisign secret key
+RWQf6LRCGA9i59SLOFxz6NxvASXDJeRtuZykwQepbDEGt87ig1BNpWaVWuNrm73YiIiJbq71Wi+dP9eKL8OC351vwIasSSbXxwA=
+trusted comment: timestamp:1555779966\tfile:test
+QtKMXWyYcwdpZAlPF7tE2ENJkRd1ujvKjlj1m9RtHTBnZPa5WKU5uWRs5GoP5M/VqE81QFuMKI5k/SfNQUaOAA==
+";
+}
+
+#[cfg(test)]
+mod test {
+    use color_eyre::Result;
+    use minisign_verify::PublicKey;
+
+    use super::{decode, test_vectors::*, verify};
+
+    #[tokio::test]
+    async fn test_verify() -> Result<()> {
+        let public_key = PublicKey::from_base64(PUBLIC_KEY)?;
+        for signature in [SIGNATURE, LEGACY_SIGNATURE] {
+            let signature = decode(signature.as_bytes())?;
+            verify(&public_key, &signature, SIGNED).await?;
+            assert!(verify(&public_key, &signature, &b"tampered"[..])
+                .await
+                .is_err());
+        }
+
+        // Differs from `PUBLIC_KEY` in the key id.
+        let other_key = PublicKey::from_base64(
+            "RWQf7LRCGA9i53mlYecO4IzT51TGPpvWucNSCh1CBM0QTaLn73Y7GFO3",
+        )?;
+        let err = verify(&other_key, &decode(SIGNATURE.as_bytes())?, SIGNED)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("different key"), "{err}");
+
+        assert!(decode(b"not a signature").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,393 @@
+//! Offline auditing of an out dir against the lockfile.
+//!
+//! Plain artifacts are hashed directly. Extracted artifacts can't be compared to
+//! the hash of the archive they came from, so a [`Manifest`] of their files is
+//! recorded when they are committed, and the out dir is compared against that.
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    fs::File,
+    io::{self, BufReader, Read},
+    path::{Path, PathBuf},
+};
+
+use cacache::{Algorithm, Integrity};
+use color_eyre::{eyre::WrapErr, Result};
+use serde::{Deserialize, Serialize};
+use ssri::IntegrityOpts;
+
+use crate::config::{ArtifactName, LockedSpec, Spec};
+use crate::fs::METADATA_DIR;
+
+/// Directory inside [`METADATA_DIR`] holding the manifests of extracted artifacts.
+pub const MANIFESTS_DIR: &str = "manifests";
+
+/// Path of the manifest of the extracted artifact `name`, relative to the out dir.
+pub fn manifest_path(name: &ArtifactName) -> PathBuf {
+    Path::new(METADATA_DIR)
+        .join(MANIFESTS_DIR)
+        .join(format!("{}.toml", name.0))
+}
+
+/// The files an artifact was extracted into.
+#[derive(Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Hash of the artifact before extraction.
+    pub hash: Integrity,
+    /// Every file in the extracted dir, by path relative to it. Symlinks are
+    /// not followed, their hash is that of their target path.
+    pub files: BTreeMap<String, Integrity>,
+}
+
+impl Manifest {
+    /// Records the contents of `dir`, which the artifact with `hash` was extracted
+    /// into. Blocks while hashing.
+    pub fn compute(dir: &Path, hash: Integrity) -> Result<Self> {
+        let algorithm = hash.pick_algorithm();
+        Ok(Self {
+            files: hash_tree(dir, algorithm)?,
+            hash,
+        })
+    }
+}
+
+/// Something about the out dir that disagrees with the lockfile. Paths are
+/// relative to the out dir.
+#[derive(Debug, Eq, PartialEq)]
+pub enum Problem {
+    Missing(PathBuf),
+    Extra(PathBuf),
+    Corrupted {
+        path: PathBuf,
+        expected: Integrity,
+        actual: Integrity,
+    },
+}
+
+impl Problem {
+    pub fn path(&self) -> &Path {
+        match self {
+            Self::Missing(path) | Self::Extra(path) => path,
+            Self::Corrupted { path, .. } => path,
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Missing(path) => write!(f, "missing   {}", path.display()),
+            Self::Extra(path) => write!(f, "extra     {}", path.display()),
+            Self::Corrupted {
+                path,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "corrupted {}: expected {expected}, found {actual}",
+                path.display()
+            ),
+        }
+    }
+}
+
+/// Compares the contents of `out_dir` to `locked`. Blocks while hashing.
+pub fn verify_out_dir(
+    out_dir: &Path,
+    spec: &Spec,
+    locked: &LockedSpec,
+) -> Result<Vec<Problem>> {
+    let mut problems = Vec::new();
+    for (name, artifact) in &locked.artifacts {
+        let extracted = spec
+            .artifacts
+            .get(name)
+            .is_some_and(|a| a.extractor.is_some());
+        let dir = Path::new(&name.0);
+        if !out_dir.join(dir).exists() {
+            problems.push(Problem::Missing(dir.to_owned()));
+            continue;
+        }
+        let (expected, actual) = if extracted {
+            let manifest_path = manifest_path(name);
+            let manifest = match std::fs::read_to_string(out_dir.join(&manifest_path)) {
+                Ok(manifest) => manifest,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                    problems.push(Problem::Missing(manifest_path));
+                    continue;
+                }
+                Err(err) => {
+                    return Err(err).wrap_err_with(|| {
+                        format!("failed to read {}", manifest_path.display())
+                    })
+                }
+            };
+            let manifest: Manifest = toml::from_str(&manifest).wrap_err_with(|| {
+                format!("failed to parse {}", manifest_path.display())
+            })?;
+            if artifact.hash.matches(&manifest.hash).is_none() {
+                problems.push(Problem::Corrupted {
+                    path: manifest_path,
+                    expected: artifact.hash.clone(),
+                    actual: manifest.hash,
+                });
+                continue;
+            }
+            let algorithm = manifest.hash.pick_algorithm();
+            (manifest.files, hash_tree(&out_dir.join(dir), algorithm)?)
+        } else {
+            let expected = BTreeMap::from([(name.0.clone(), artifact.hash.clone())]);
+            let algorithm = artifact.hash.pick_algorithm();
+            (expected, hash_tree(&out_dir.join(dir), algorithm)?)
+        };
+        compare_files(dir, expected, actual, &mut problems);
+    }
+
+    let entries = match std::fs::read_dir(out_dir) {
+        Ok(entries) => Some(entries),
+        // All artifacts were reported missing above, and nothing is extra.
+        Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+        Err(err) => {
+            return Err(err).wrap_err_with(|| {
+                format!("failed to read out dir {}", out_dir.display())
+            })
+        }
+    };
+    for entry in entries.into_iter().flatten() {
+        let file_name = entry.wrap_err("failed to read out dir")?.file_name();
+        let name = ArtifactName(file_name.to_string_lossy().into_owned());
+        if file_name != METADATA_DIR && !locked.artifacts.contains_key(&name) {
+            problems.push(Problem::Extra(PathBuf::from(file_name)));
+        }
+    }
+
+    problems.sort_by(|a, b| a.path().cmp(b.path()));
+    Ok(problems)
+}
+
+fn compare_files(
+    dir: &Path,
+    mut expected: BTreeMap<String, Integrity>,
+    actual: BTreeMap<String, Integrity>,
+    problems: &mut Vec<Problem>,
+) {
+    for (file, actual) in actual {
+        let path = dir.join(&file);
+        match expected.remove(&file) {
+            None => problems.push(Problem::Extra(path)),
+            Some(expected) if expected.matches(&actual).is_none() => {
+                problems.push(Problem::Corrupted {
+                    path,
+                    expected,
+                    actual,
+                })
+            }
+            Some(_) => (),
+        }
+    }
+    problems.extend(expected.into_keys().map(|f| Problem::Missing(dir.join(f))));
+}
+
+/// Hashes every file below `dir`, by path relative to it.
+fn hash_tree(dir: &Path, algorithm: Algorithm) -> Result<BTreeMap<String, Integrity>> {
+    let mut files = BTreeMap::new();
+    for entry in walkdir::WalkDir::new(dir).min_depth(1) {
+        let entry = entry.wrap_err("failed to walk artifact dir")?;
+        let path = entry.path();
+        let integrity = if entry.path_is_symlink() {
+            let target = std::fs::read_link(path)
+                .wrap_err_with(|| format!("failed to read link {}", path.display()))?;
+            IntegrityOpts::new()
+                .algorithm(algorithm)
+                .chain(target.to_string_lossy().as_bytes())
+                .result()
+        } else if entry.file_type().is_file() {
+            hash_file(path, algorithm)?
+        } else {
+            continue;
+        };
+        let relative = path.strip_prefix(dir).expect("walkdir yields children");
+        files.insert(relative.to_string_lossy().into_owned(), integrity);
+    }
+
+    Ok(files)
+}
+
+fn hash_file(path: &Path, algorithm: Algorithm) -> Result<Integrity> {
+    let mut file = BufReader::new(
+        File::open(path)
+            .wrap_err_with(|| format!("failed to open {}", path.display()))?,
+    );
+    let mut opts = IntegrityOpts::new().algorithm(algorithm);
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let n = file
+            .read(&mut buf)
+            .wrap_err_with(|| format!("failed to read {}", path.display()))?;
+        if n == 0 {
+            break;
+        }
+        opts.input(&buf[..n]);
+    }
+
+    Ok(opts.result())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::BTreeMap,
+        path::{Path, PathBuf},
+    };
+
+    use cacache::Integrity;
+    use color_eyre::Result;
+
+    use super::{manifest_path, verify_out_dir, Manifest, Problem};
+    use crate::config::{
+        sources::{LocalPath, Source},
+        Artifact, ArtifactName, ExtractorName, LockedArtifact, LockedSpec, Spec,
+    };
+
+    fn spec(extracted: &[&str]) -> Spec {
+        let artifacts = extracted
+            .iter()
+            .map(|name| {
+                let artifact = Artifact {
+                    source: Source::Path(LocalPath {
+                        path: PathBuf::from(name),
+                    }),
+                    hash: None,
+                    extractor: Some(ExtractorName("tar.gz".to_owned())),
+                    signature: None,
+                };
+                (ArtifactName(name.to_string()), artifact)
+            })
+            .collect();
+        toml::from_str::<Spec>(
+            "artifacts = {}\nextractors = {}\n[artificer]\nversion = \"0.0.0\"\n\
+            out-dir = \"out\"",
+        )
+        .map(|spec| Spec { artifacts, ..spec })
+        .unwrap()
+    }
+
+    fn lock(artifacts: &[(&str, &[u8])]) -> LockedSpec {
+        let mut locked = LockedSpec::new();
+        for (name, contents) in artifacts {
+            let source = Source::Path(LocalPath {
+                path: PathBuf::from(name),
+            });
+            locked.artifacts.insert(
+                ArtifactName(name.to_string()),
+                LockedArtifact::new(source.clone(), &source, Integrity::from(contents)),
+            );
+        }
+        locked
+    }
+
+    #[test]
+    fn test_missing_out_dir() -> Result<()> {
+        let out = tempfile::tempdir()?;
+        let problems = verify_out_dir(
+            &out.path().join("out"),
+            &spec(&[]),
+            &lock(&[("a", b"a"), ("b", b"b")]),
+        )?;
+        assert_eq!(
+            problems,
+            [
+                Problem::Missing(PathBuf::from("a")),
+                Problem::Missing(PathBuf::from("b")),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_plain_artifacts() -> Result<()> {
+        let out = tempfile::tempdir()?;
+        let write = |path: &str, contents: &[u8]| {
+            let path = out.path().join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        };
+        write("good/good", b"good");
+        write("bad/bad", b"tampered");
+        write("good/stray.txt", b"");
+        write("unlocked/unlocked", b"");
+        write(".artificer-metadata/staging/foo", b"");
+        let locked = lock(&[("good", b"good"), ("bad", b"bad"), ("gone", b"")]);
+
+        let problems = verify_out_dir(out.path(), &spec(&[]), &locked)?;
+        assert_eq!(
+            problems,
+            [
+                Problem::Corrupted {
+                    path: "bad/bad".into(),
+                    expected: Integrity::from(b"bad"),
+                    actual: Integrity::from(b"tampered"),
+                },
+                Problem::Missing("gone".into()),
+                Problem::Extra("good/stray.txt".into()),
+                Problem::Extra("unlocked".into()),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_extracted_artifacts() -> Result<()> {
+        let out = tempfile::tempdir()?;
+        let name = ArtifactName("foo".to_owned());
+        let dir = out.path().join("foo");
+        std::fs::create_dir_all(dir.join("sub"))?;
+        std::fs::write(dir.join("a.txt"), b"a")?;
+        std::fs::write(dir.join("sub").join("b.txt"), b"b")?;
+        let locked = lock(&[("foo", b"archive")]);
+        let spec = spec(&["foo"]);
+
+        assert_eq!(
+            verify_out_dir(out.path(), &spec, &locked)?,
+            [Problem::Missing(manifest_path(&name))]
+        );
+
+        let manifest = Manifest::compute(&dir, Integrity::from(b"archive"))?;
+        assert_eq!(
+            manifest.files,
+            BTreeMap::from([
+                ("a.txt".to_owned(), Integrity::from(b"a")),
+                (
+                    Path::new("sub")
+                        .join("b.txt")
+                        .to_string_lossy()
+                        .into_owned(),
+                    Integrity::from(b"b")
+                ),
+            ])
+        );
+        let manifest_file = out.path().join(manifest_path(&name));
+        std::fs::create_dir_all(manifest_file.parent().unwrap())?;
+        std::fs::write(&manifest_file, toml::to_string(&manifest)?)?;
+        assert_eq!(verify_out_dir(out.path(), &spec, &locked)?, []);
+
+        std::fs::remove_file(dir.join("a.txt"))?;
+        std::fs::write(dir.join("sub").join("b.txt"), b"not b")?;
+        let problems = verify_out_dir(out.path(), &spec, &locked)?;
+        assert!(matches!(
+            problems.as_slice(),
+            [Problem::Missing(_), Problem::Corrupted { .. }]
+        ));
+
+        let relocked = lock(&[("foo", b"new archive")]);
+        let problems = verify_out_dir(out.path(), &spec, &relocked)?;
+        assert!(
+            matches!(problems.as_slice(), [Problem::Corrupted { path, .. }] if *path == manifest_path(&name)),
+            "manifest of a different archive: {problems:?}"
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,146 @@
+use std::{
+    io,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use futures_core::Stream;
+use futures_sink::Sink;
+use tokio::io::unix::AsyncFd;
+
+use crate::{
+    stream::{FrameStream, Received},
+    timestamp::Timestamps,
+    Error, Frame,
+};
+
+/// An asynchronous [`FrameStream`] driven by the tokio reactor
+///
+/// Received frames are yielded through [`Stream`] and frames are sent through [`Sink`], so that
+/// async consumers don't have to block a thread in [`FrameStream::recv`]. For one-off calls,
+/// [`recv_frame`] and [`send`] are available as well. Like [`FrameStream::recv`], both skip
+/// error frames, which are received with [`recv_timestamped`].
+///
+/// The underlying socket is switched to nonblocking mode.
+///
+/// [`recv_frame`]: AsyncFrameStream::recv_frame
+/// [`send`]: AsyncFrameStream::send
+/// [`recv_timestamped`]: AsyncFrameStream::recv_timestamped
+///
+/// # Examples
+///
+/// ```no_run
+/// use can_rs::{async_stream::AsyncFrameStream, stream::FrameStream, CAN_DATA_LEN};
+/// use futures::StreamExt;
+///
+/// # async fn run() -> Result<(), can_rs::Error> {
+/// let stream = FrameStream::<CAN_DATA_LEN>::new("can0".parse()?)?;
+/// let mut stream = AsyncFrameStream::new(stream)?;
+/// while let Some(frame) = stream.next().await {
+///     println!("{:?}", frame?);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct AsyncFrameStream<const N: usize> {
+    inner: AsyncFd<FrameStream<N>>,
+    /// Frame accepted by [`Sink::start_send`] that wasn't sent yet
+    pending: Option<Frame<N>>,
+}
+
+impl<const N: usize> AsyncFrameStream<N> {
+    /// Register `stream` with the tokio reactor
+    ///
+    /// Must be called from within a tokio runtime.
+    pub fn new(stream: FrameStream<N>) -> Result<Self, Error> {
+        crate::socket::set_nonblocking(&stream, true)?;
+        Ok(Self {
+            inner: AsyncFd::new(stream)?,
+            pending: None,
+        })
+    }
+
+    pub fn get_ref(&self) -> &FrameStream<N> {
+        self.inner.get_ref()
+    }
+
+    /// Deregister the stream from the tokio reactor. The socket stays in nonblocking mode.
+    pub fn into_inner(self) -> FrameStream<N> {
+        self.inner.into_inner()
+    }
+
+    pub async fn recv_frame(&self) -> io::Result<Frame<N>> {
+        self.inner
+            .async_io(tokio::io::Interest::READABLE, |stream| stream.recv_frame(0))
+            .await
+    }
+
+    /// See [`FrameStream::recv_timestamped`]
+    pub async fn recv_timestamped(&self) -> io::Result<(Received<N>, Timestamps)> {
+        self.inner
+            .async_io(tokio::io::Interest::READABLE, |stream| {
+                stream.recv_timestamped(0)
+            })
+            .await
+    }
+
+    pub async fn send(&self, frame: &Frame<N>) -> io::Result<usize> {
+        self.inner
+            .async_io(tokio::io::Interest::WRITABLE, |stream| {
+                stream.send(frame, 0)
+            })
+            .await
+    }
+
+    fn poll_send_pending(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while let Some(frame) = &self.pending {
+            let mut guard = ready!(self.inner.poll_write_ready(cx))?;
+            match guard.try_io(|stream| stream.get_ref().send(frame, 0)) {
+                Ok(result) => {
+                    self.pending = None;
+                    result?;
+                }
+                Err(_would_block) => continue,
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<const N: usize> Stream for AsyncFrameStream<N> {
+    type Item = io::Result<Frame<N>>;
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        loop {
+            let mut guard = ready!(self.inner.poll_read_ready(cx))?;
+            match guard.try_io(|stream| stream.get_ref().recv_frame(0)) {
+                Ok(result) => return Poll::Ready(Some(result)),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+}
+
+impl<const N: usize> Sink<Frame<N>> for AsyncFrameStream<N> {
+    type Error = io::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_send_pending(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, frame: Frame<N>) -> io::Result<()> {
+        self.get_mut().pending = Some(frame);
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_send_pending(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_send_pending(cx)
+    }
+}
@@ -0,0 +1,670 @@
+//! SocketCAN Broadcast Manager (BCM)
+//!
+//! The BCM offloads periodic CAN traffic to the kernel: cyclic transmissions keep going without
+//! a thread waking up for every frame, and receive filters only report content changes and
+//! timeouts instead of every single frame.
+//!
+//! See <https://www.kernel.org/doc/html/latest/networking/can.html#broadcast-manager-protocol-sockets-sock-dgram>
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use std::time::Duration;
+//!
+//! use can_rs::{
+//!     bcm::{BcmMessage, BcmSocket, RxSetup, TxSetup},
+//!     Frame, Id, CAN_DATA_LEN,
+//! };
+//!
+//! # fn main() -> Result<(), can_rs::Error> {
+//! let socket = BcmSocket::<CAN_DATA_LEN>::connect("can0".parse()?)?;
+//!
+//! // Send a heartbeat every 100ms
+//! let mut heartbeat = Frame::empty();
+//! heartbeat.len = 1;
+//! socket.tx_setup(&TxSetup::cyclic(
+//!     Id::Extended(0x80),
+//!     heartbeat,
+//!     Duration::from_millis(100),
+//! ))?;
+//!
+//! // Get notified when the peer stops talking for 500ms
+//! let mut rx = RxSetup::new(Id::Extended(0x81));
+//! rx.timeout(Duration::from_millis(500));
+//! socket.rx_setup(&rx)?;
+//! loop {
+//!     match socket.recv()? {
+//!         BcmMessage::RxTimeout { id } => println!("{id:?} went silent"),
+//!         BcmMessage::RxChanged { frame, .. } => println!("{frame:?}"),
+//!         _ => {}
+//!     }
+//! }
+//! # }
+//! ```
+
+use std::{
+    io,
+    os::{
+        fd::OwnedFd,
+        unix::prelude::{AsRawFd, IntoRawFd, RawFd},
+    },
+    time::Duration,
+};
+
+use self::imp::{RawBcmMsgHead, RawBcmTimeval};
+use crate::{
+    addr::CanAddr, socket, Error, Frame, Id, Protocol, Type, CANFD_DATA_LEN,
+    CAN_DATA_LEN,
+};
+
+/// Maximum number of frames in a single BCM message, as defined by the kernel
+pub const MAX_NFRAMES: usize = 256;
+
+pub const TX_SETUP: u32 = 1;
+pub const TX_DELETE: u32 = 2;
+pub const TX_READ: u32 = 3;
+pub const TX_SEND: u32 = 4;
+pub const RX_SETUP: u32 = 5;
+pub const RX_DELETE: u32 = 6;
+pub const RX_READ: u32 = 7;
+pub const TX_STATUS: u32 = 8;
+pub const TX_EXPIRED: u32 = 9;
+pub const RX_STATUS: u32 = 10;
+pub const RX_TIMEOUT: u32 = 11;
+pub const RX_CHANGED: u32 = 12;
+
+pub const SETTIMER: u32 = 0x0001;
+pub const STARTTIMER: u32 = 0x0002;
+pub const TX_COUNTEVT: u32 = 0x0004;
+pub const TX_ANNOUNCE: u32 = 0x0008;
+pub const TX_CP_CAN_ID: u32 = 0x0010;
+pub const RX_FILTER_ID: u32 = 0x0020;
+pub const RX_CHECK_DLC: u32 = 0x0040;
+pub const RX_NO_AUTOTIMER: u32 = 0x0080;
+pub const RX_ANNOUNCE_RESUME: u32 = 0x0100;
+pub const TX_RESET_MULTI_IDX: u32 = 0x0200;
+pub const RX_RTR_FRAME: u32 = 0x0400;
+pub const CAN_FD_FRAME: u32 = 0x0800;
+
+/// A cyclic transmission, registered with [`BcmSocket::tx_setup`]
+///
+/// If several frames are given, they are sent in turn, one per interval (multiplexing).
+#[derive(Debug, Clone)]
+pub struct TxSetup<const N: usize> {
+    pub(crate) id: Id,
+    pub(crate) frames: Vec<Frame<N>>,
+    pub(crate) interval: Duration,
+    pub(crate) count: u32,
+    pub(crate) count_interval: Duration,
+    pub(crate) announce: bool,
+    pub(crate) notify_expired: bool,
+    pub(crate) update_only: bool,
+}
+
+impl<const N: usize> TxSetup<N> {
+    /// Send `frame` with the CAN ID `id` every `interval`
+    pub fn cyclic(id: Id, frame: Frame<N>, interval: Duration) -> Self {
+        Self::multiplex(id, vec![frame], interval)
+    }
+
+    /// Send `frames` in turn with the CAN ID `id`, one every `interval`
+    pub fn multiplex(id: Id, frames: Vec<Frame<N>>, interval: Duration) -> Self {
+        Self {
+            id,
+            frames,
+            interval,
+            count: 0,
+            count_interval: Duration::ZERO,
+            announce: false,
+            notify_expired: false,
+            update_only: false,
+        }
+    }
+
+    /// Send `count` frames every `count_interval` first, before continuing every `interval`
+    pub fn initial_burst(&mut self, count: u32, count_interval: Duration) -> &mut Self {
+        self.count = count;
+        self.count_interval = count_interval;
+        self
+    }
+
+    /// Send the new frames immediately when updating a running transmission, instead of with
+    /// its next interval. Starting the timer always sends the first frame immediately.
+    pub fn announce(&mut self, announce: bool) -> &mut Self {
+        self.announce = announce;
+        self
+    }
+
+    /// Receive a [`BcmMessage::TxExpired`] once the initial burst has been sent
+    pub fn notify_expired(&mut self, notify_expired: bool) -> &mut Self {
+        self.notify_expired = notify_expired;
+        self
+    }
+
+    /// Only replace the frames of the running transmission with the same CAN ID, keeping its
+    /// timer. The intervals and the initial burst of this setup are ignored.
+    pub fn update_only(&mut self, update_only: bool) -> &mut Self {
+        self.update_only = update_only;
+        self
+    }
+}
+
+/// A receive filter, registered with [`BcmSocket::rx_setup`]
+#[derive(Debug, Clone)]
+pub struct RxSetup<const N: usize> {
+    pub(crate) id: Id,
+    pub(crate) mask: Option<Frame<N>>,
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) throttle: Option<Duration>,
+    pub(crate) check_dlc: bool,
+    pub(crate) announce_resume: bool,
+}
+
+impl<const N: usize> RxSetup<N> {
+    /// Report every frame with the CAN ID `id`
+    pub fn new(id: Id) -> Self {
+        Self {
+            id,
+            mask: None,
+            timeout: None,
+            throttle: None,
+            check_dlc: false,
+            announce_resume: false,
+        }
+    }
+
+    /// Only report frames whose data changed in the bits set in `mask.data`
+    pub fn changes(&mut self, mask: Frame<N>) -> &mut Self {
+        self.mask = Some(mask);
+        self
+    }
+
+    /// Report a [`BcmMessage::RxTimeout`] if no frame was received for `timeout`. The timer
+    /// starts right away, so a peer that never talks is detected as well.
+    pub fn timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Report changes at most once every `throttle`
+    pub fn throttle(&mut self, throttle: Duration) -> &mut Self {
+        self.throttle = Some(throttle);
+        self
+    }
+
+    /// Also report changes of the frame length
+    pub fn check_dlc(&mut self, check_dlc: bool) -> &mut Self {
+        self.check_dlc = check_dlc;
+        self
+    }
+
+    /// Report the first frame received after a timeout, even if its data didn't change
+    pub fn announce_resume(&mut self, announce_resume: bool) -> &mut Self {
+        self.announce_resume = announce_resume;
+        self
+    }
+}
+
+/// A message from the broadcast manager, see [`BcmSocket::recv`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum BcmMessage<const N: usize> {
+    /// Reply to [`BcmSocket::tx_read`]
+    TxStatus {
+        id: Id,
+        interval: Duration,
+        frames: Vec<Frame<N>>,
+    },
+    /// The initial burst of a cyclic transmission was sent, see [`TxSetup::notify_expired`]
+    TxExpired { id: Id },
+    /// Reply to [`BcmSocket::rx_read`]
+    RxStatus {
+        id: Id,
+        timeout: Duration,
+        frames: Vec<Frame<N>>,
+    },
+    /// No frame was received within the timeout, see [`RxSetup::timeout`]
+    RxTimeout { id: Id },
+    /// A frame whose content changed was received
+    RxChanged { id: Id, frame: Frame<N> },
+}
+
+/// A broadcast manager socket, connected to a CAN network interface
+///
+/// The underlying SocketCAN socket and file descriptor will be closed and cleaned when the value
+/// is dropped, which also removes all of its cyclic transmissions and receive filters.
+pub struct BcmSocket<const N: usize> {
+    pub(crate) fd: OwnedFd,
+    pub(crate) addr: CanAddr,
+}
+
+pub struct BcmSocketBuilder<const N: usize> {
+    pub(crate) nonblocking: bool,
+}
+
+impl<const N: usize> BcmSocketBuilder<N> {
+    pub fn new() -> Self {
+        Self { nonblocking: false }
+    }
+
+    pub fn nonblocking(&mut self, nonblocking: bool) -> &mut Self {
+        self.nonblocking = nonblocking;
+        self
+    }
+}
+
+impl<const N: usize> Default for BcmSocketBuilder<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> BcmSocketBuilder<N>
+where
+    [(); N]: crate::stream::AllowedToBind,
+{
+    pub fn connect(&self, addr: CanAddr) -> Result<BcmSocket<N>, Error> {
+        let socket = BcmSocket {
+            fd: socket::new(Type::DGRAM, Protocol::BCM)?,
+            addr,
+        };
+        socket::set_nonblocking(&socket, self.nonblocking)?;
+        socket::connect(&socket, &socket.addr)?;
+        Ok(socket)
+    }
+}
+
+impl BcmSocket<CAN_DATA_LEN> {
+    pub fn connect(addr: CanAddr) -> Result<Self, Error> {
+        BcmSocketBuilder::<CAN_DATA_LEN>::new().connect(addr)
+    }
+
+    pub fn build() -> BcmSocketBuilder<CAN_DATA_LEN> {
+        BcmSocketBuilder::new()
+    }
+}
+
+impl BcmSocket<CANFD_DATA_LEN> {
+    pub fn connect(addr: CanAddr) -> Result<Self, Error> {
+        BcmSocketBuilder::<CANFD_DATA_LEN>::new().connect(addr)
+    }
+
+    pub fn build() -> BcmSocketBuilder<CANFD_DATA_LEN> {
+        BcmSocketBuilder::new()
+    }
+}
+
+impl<const N: usize> BcmSocket<N> {
+    /// Register or update a cyclic transmission
+    ///
+    /// The timer is (re)started and the first frame sent immediately, also when replacing a
+    /// running transmission with the same CAN ID. Use [`TxSetup::update_only`] to replace the
+    /// frames of a running transmission without touching its timer.
+    pub fn tx_setup(&self, setup: &TxSetup<N>) -> Result<(), Error> {
+        let mut head = RawBcmMsgHead::new(TX_SETUP, setup.id);
+        head.flags |= TX_CP_CAN_ID;
+        if !setup.update_only {
+            head.flags |= SETTIMER | STARTTIMER;
+        }
+        if setup.announce {
+            head.flags |= TX_ANNOUNCE;
+        }
+        if setup.notify_expired {
+            head.flags |= TX_COUNTEVT;
+        }
+        head.count = setup.count;
+        head.ival1 = setup.count_interval.into();
+        head.ival2 = setup.interval.into();
+        self.write_msg(head, &setup.frames)
+    }
+
+    /// Stop the cyclic transmission with the CAN ID `id`
+    pub fn tx_delete(&self, id: Id) -> Result<(), Error> {
+        self.write_msg(RawBcmMsgHead::new(TX_DELETE, id), &[])
+    }
+
+    /// Send a single frame
+    pub fn tx_send(&self, frame: &Frame<N>) -> Result<(), Error> {
+        self.write_msg(RawBcmMsgHead::new(TX_SEND, frame.id), &[*frame])
+    }
+
+    /// Request a [`BcmMessage::TxStatus`] for the cyclic transmission with the CAN ID `id`
+    pub fn tx_read(&self, id: Id) -> Result<(), Error> {
+        self.write_msg(RawBcmMsgHead::new(TX_READ, id), &[])
+    }
+
+    /// Register or update a receive filter
+    pub fn rx_setup(&self, setup: &RxSetup<N>) -> Result<(), Error> {
+        let mut head = RawBcmMsgHead::new(RX_SETUP, setup.id);
+        if setup.timeout.is_some() || setup.throttle.is_some() {
+            head.flags |= SETTIMER;
+        }
+        if setup.timeout.is_some() {
+            head.flags |= STARTTIMER;
+        }
+        if setup.check_dlc {
+            head.flags |= RX_CHECK_DLC;
+        }
+        if setup.announce_resume {
+            head.flags |= RX_ANNOUNCE_RESUME;
+        }
+        head.ival1 = setup.timeout.unwrap_or_default().into();
+        head.ival2 = setup.throttle.unwrap_or_default().into();
+        match &setup.mask {
+            Some(mask) => self.write_msg(head, &[*mask]),
+            None => {
+                head.flags |= RX_FILTER_ID;
+                self.write_msg(head, &[])
+            }
+        }
+    }
+
+    /// Remove the receive filter for the CAN ID `id`
+    pub fn rx_delete(&self, id: Id) -> Result<(), Error> {
+        self.write_msg(RawBcmMsgHead::new(RX_DELETE, id), &[])
+    }
+
+    /// Request a [`BcmMessage::RxStatus`] for the receive filter with the CAN ID `id`
+    pub fn rx_read(&self, id: Id) -> Result<(), Error> {
+        self.write_msg(RawBcmMsgHead::new(RX_READ, id), &[])
+    }
+
+    /// Receive the next message from the broadcast manager
+    pub fn recv(&self) -> Result<BcmMessage<N>, Error> {
+        let mut buf =
+            vec![0u8; imp::FRAMES_OFFSET + MAX_NFRAMES * imp::frame_size::<N>()];
+        let ret = unsafe {
+            libc::read(
+                self.as_raw_fd(),
+                buf.as_mut_ptr() as *mut libc::c_void,
+                buf.len(),
+            )
+        };
+        if ret < 0 {
+            return Err(Error::Io(io::Error::last_os_error()));
+        }
+        buf.truncate(ret as usize);
+        imp::decode(&buf)
+    }
+
+    pub fn try_clone(&self) -> Result<Self, Error> {
+        Ok(Self {
+            fd: self
+                .fd
+                .try_clone()
+                .map_err(|e| crate::Error::CanStreamClone { source: e })?,
+            addr: self.addr.clone(),
+        })
+    }
+
+    fn write_msg(
+        &self,
+        mut head: RawBcmMsgHead,
+        frames: &[Frame<N>],
+    ) -> Result<(), Error> {
+        if N == CANFD_DATA_LEN {
+            head.flags |= CAN_FD_FRAME;
+        }
+        let opcode = head.opcode;
+        let buf = imp::encode(head, frames)?;
+        let ret = unsafe {
+            libc::write(
+                self.as_raw_fd(),
+                buf.as_ptr() as *const libc::c_void,
+                buf.len(),
+            )
+        };
+        if ret < 0 {
+            return Err(Error::Syscall {
+                syscall: "write(2)".to_string(),
+                context: Some(format!("sending BCM message with opcode {opcode}")),
+                source: io::Error::last_os_error(),
+            });
+        }
+        Ok(())
+    }
+}
+
+impl<const N: usize> AsRawFd for BcmSocket<N> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+impl<const N: usize> IntoRawFd for BcmSocket<N> {
+    fn into_raw_fd(self) -> RawFd {
+        self.fd.into_raw_fd()
+    }
+}
+
+impl From<Duration> for RawBcmTimeval {
+    fn from(duration: Duration) -> Self {
+        Self {
+            tv_sec: duration.as_secs() as libc::c_long,
+            tv_usec: duration.subsec_micros() as libc::c_long,
+        }
+    }
+}
+
+impl From<RawBcmTimeval> for Duration {
+    fn from(timeval: RawBcmTimeval) -> Self {
+        Duration::from_secs(timeval.tv_sec as u64)
+            + Duration::from_micros(timeval.tv_usec as u64)
+    }
+}
+
+pub(crate) mod imp {
+    use super::{BcmMessage, MAX_NFRAMES};
+    use crate::{stream::imp::RawFrame, Error, Frame, Id};
+
+    // struct bcm_timeval {
+    //     long tv_sec;
+    //     long tv_usec;
+    // };
+    #[derive(Debug, Clone, Copy, Default)]
+    #[repr(C)]
+    pub(crate) struct RawBcmTimeval {
+        pub(crate) tv_sec: libc::c_long,
+        pub(crate) tv_usec: libc::c_long,
+    }
+
+    // struct bcm_msg_head {
+    //     __u32 opcode;
+    //     __u32 flags;
+    //     __u32 count;
+    //     struct bcm_timeval ival1, ival2;
+    //     canid_t can_id;
+    //     __u32 nframes;
+    //     struct can_frame frames[];
+    // };
+    #[derive(Debug, Clone, Copy)]
+    #[repr(C)]
+    pub(crate) struct RawBcmMsgHead {
+        pub(crate) opcode: u32,
+        pub(crate) flags: u32,
+        pub(crate) count: u32,
+        pub(crate) ival1: RawBcmTimeval,
+        pub(crate) ival2: RawBcmTimeval,
+        pub(crate) can_id: u32,
+        pub(crate) nframes: u32,
+    }
+
+    impl RawBcmMsgHead {
+        pub(crate) fn new(opcode: u32, id: Id) -> Self {
+            Self {
+                opcode,
+                flags: 0,
+                count: 0,
+                ival1: RawBcmTimeval::default(),
+                ival2: RawBcmTimeval::default(),
+                can_id: id.wire_value(),
+                nframes: 0,
+            }
+        }
+    }
+
+    /// The frames follow the header aligned to 8 bytes, like `struct can_frame` is
+    pub(crate) const FRAMES_OFFSET: usize =
+        (std::mem::size_of::<RawBcmMsgHead>() + 7) & !7;
+
+    pub(crate) const fn frame_size<const N: usize>() -> usize {
+        std::mem::size_of::<RawFrame<N>>()
+    }
+
+    pub(crate) fn encode<const N: usize>(
+        mut head: RawBcmMsgHead,
+        frames: &[Frame<N>],
+    ) -> Result<Vec<u8>, Error> {
+        if frames.len() > MAX_NFRAMES {
+            return Err(Error::BcmTooManyFrames(frames.len()));
+        }
+        head.nframes = frames.len() as u32;
+
+        let mut buf = vec![0u8; FRAMES_OFFSET + frames.len() * frame_size::<N>()];
+        unsafe {
+            std::ptr::write_unaligned(buf.as_mut_ptr() as *mut RawBcmMsgHead, head);
+            for (i, frame) in frames.iter().enumerate() {
+                std::ptr::write_unaligned(
+                    buf.as_mut_ptr().add(FRAMES_OFFSET + i * frame_size::<N>())
+                        as *mut RawFrame<N>,
+                    RawFrame::from(*frame),
+                );
+            }
+        }
+        Ok(buf)
+    }
+
+    pub(crate) fn decode<const N: usize>(buf: &[u8]) -> Result<BcmMessage<N>, Error> {
+        if buf.len() < std::mem::size_of::<RawBcmMsgHead>() {
+            return Err(Error::InvalidDataLength(buf.len()));
+        }
+        let head: RawBcmMsgHead =
+            unsafe { std::ptr::read_unaligned(buf.as_ptr() as *const RawBcmMsgHead) };
+        let nframes = head.nframes as usize;
+        if nframes > 0 && buf.len() < FRAMES_OFFSET + nframes * frame_size::<N>() {
+            return Err(Error::InvalidDataLength(buf.len()));
+        }
+        let mut frames = (0..nframes).map(|i| {
+            let raw: RawFrame<N> = unsafe {
+                std::ptr::read_unaligned(
+                    buf.as_ptr().add(FRAMES_OFFSET + i * frame_size::<N>())
+                        as *const RawFrame<N>,
+                )
+            };
+            Frame::from(raw)
+        });
+
+        let id = Id::from(head.can_id);
+        let message = match head.opcode {
+            super::TX_STATUS => BcmMessage::TxStatus {
+                id,
+                interval: head.ival2.into(),
+                frames: frames.collect(),
+            },
+            super::TX_EXPIRED => BcmMessage::TxExpired { id },
+            super::RX_STATUS => BcmMessage::RxStatus {
+                id,
+                timeout: head.ival1.into(),
+                frames: frames.collect(),
+            },
+            super::RX_TIMEOUT => BcmMessage::RxTimeout { id },
+            super::RX_CHANGED => BcmMessage::RxChanged {
+                id,
+                frame: frames.next().ok_or(Error::InvalidDataLength(buf.len()))?,
+            },
+            opcode => return Err(Error::InvalidBcmOpcode(opcode)),
+        };
+        Ok(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(id: Id, value: u8) -> Frame<CAN_DATA_LEN> {
+        Frame {
+            id,
+            len: 2,
+            flags: 0,
+            data: [value; CAN_DATA_LEN],
+        }
+    }
+
+    #[test]
+    fn layout_matches_kernel() {
+        // sizeof(struct bcm_msg_head), with the padding before `ival1`
+        #[cfg(target_pointer_width = "64")]
+        assert_eq!(imp::FRAMES_OFFSET, 56);
+        #[cfg(target_pointer_width = "32")]
+        assert_eq!(imp::FRAMES_OFFSET, 40);
+        assert_eq!(imp::frame_size::<CAN_DATA_LEN>(), 16);
+        assert_eq!(imp::frame_size::<CANFD_DATA_LEN>(), 72);
+    }
+
+    #[test]
+    fn encode_and_decode_messages() -> Result<(), Error> {
+        let id = Id::Extended(0x81);
+        let mut head = RawBcmMsgHead::new(RX_CHANGED, id);
+        head.ival1 = Duration::from_millis(1500).into();
+        let buf = imp::encode(head, &[frame(id, 0xAB)])?;
+        assert_eq!(buf.len(), imp::FRAMES_OFFSET + 16);
+        assert_eq!(
+            imp::decode::<CAN_DATA_LEN>(&buf)?,
+            BcmMessage::RxChanged {
+                id,
+                frame: frame(id, 0xAB),
+            }
+        );
+
+        let mut head = RawBcmMsgHead::new(TX_STATUS, id);
+        head.ival2 = Duration::from_millis(100).into();
+        let buf = imp::encode(head, &[frame(id, 1), frame(id, 2)])?;
+        assert_eq!(
+            imp::decode::<CAN_DATA_LEN>(&buf)?,
+            BcmMessage::TxStatus {
+                id,
+                interval: Duration::from_millis(100),
+                frames: vec![frame(id, 1), frame(id, 2)],
+            }
+        );
+
+        let buf = imp::encode::<CAN_DATA_LEN>(RawBcmMsgHead::new(RX_TIMEOUT, id), &[])?;
+        assert_eq!(
+            imp::decode::<CAN_DATA_LEN>(&buf)?,
+            BcmMessage::RxTimeout { id }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn decode_invalid_messages() -> Result<(), Error> {
+        let id = Id::Standard(0x12);
+        let buf = imp::encode::<CAN_DATA_LEN>(RawBcmMsgHead::new(TX_SETUP, id), &[])?;
+        assert!(matches!(
+            imp::decode::<CAN_DATA_LEN>(&buf),
+            Err(Error::InvalidBcmOpcode(TX_SETUP))
+        ));
+
+        let buf = imp::encode(RawBcmMsgHead::new(RX_CHANGED, id), &[frame(id, 0)])?;
+        assert!(matches!(
+            imp::decode::<CAN_DATA_LEN>(&buf[..buf.len() - 1]),
+            Err(Error::InvalidDataLength(_))
+        ));
+        assert!(matches!(
+            imp::encode(
+                RawBcmMsgHead::new(TX_SETUP, id),
+                &vec![frame(id, 0); MAX_NFRAMES + 1]
+            ),
+            Err(Error::BcmTooManyFrames(257))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn durations_round_trip() {
+        let duration = Duration::from_micros(2_345_678);
+        let timeval = RawBcmTimeval::from(duration);
+        assert_eq!((timeval.tv_sec, timeval.tv_usec), (2, 345_678));
+        assert_eq!(Duration::from(timeval), duration);
+    }
+}
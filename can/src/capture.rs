@@ -0,0 +1,810 @@
+//! Recording and replaying CAN traffic as log files
+//!
+//! Frames are recorded together with the kernel timestamp of their reception, and written in one
+//! of the supported [`Format`]s:
+//!
+//! + [`Format::Candump`] - the `candump -l` format of can-utils, which can be replayed with
+//!   `canplayer` as well.
+//! + [`Format::Asc`] - the Vector ASCII format, for CANalyzer / CANoe.
+//!
+//! Vector's binary BLF format is written and read by the `blf` module, with the `blf` feature.
+//!
+//! Logs in either format can be read back with [`LogReader`] and replayed onto an interface with
+//! [`replay`], with the original timing or accelerated.
+//!
+//! Since [`Frame`] doesn't carry the RTR and error flags, only data frames are recorded and
+//! replayed.
+
+use std::{
+    fmt::Write as _,
+    io::{self, BufRead, Write},
+    marker::PhantomData,
+    thread,
+    time::{Duration, Instant},
+};
+
+#[cfg(feature = "blf")]
+pub mod blf;
+
+use crate::{
+    stream::{FrameStream, Received},
+    timestamp::Timestamping,
+    Error, Frame, Id, CANFD_FDF_FLAG, CAN_DATA_LEN,
+};
+
+/// A log file format
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// `(1436509052.249713) can0 123#DEADBEEF`, one frame per line
+    Candump,
+    /// Vector ASCII log
+    Asc,
+}
+
+/// A frame, when and where it was received
+#[derive(Clone, Debug, PartialEq)]
+pub struct Record<const N: usize> {
+    /// Time since the UNIX epoch for recorded frames, candump and BLF logs, time since the
+    /// start of the measurement for ASC logs
+    pub timestamp: Duration,
+    /// Interface name, or the channel number for ASC and BLF logs
+    pub interface: String,
+    pub frame: Frame<N>,
+}
+
+/// Records frames received on a [`FrameStream`] together with their kernel timestamps
+///
+/// # Examples
+///
+/// ```no_run
+/// use can_rs::{
+///     capture::{Format, LogWriter, Recorder},
+///     stream::FrameStream,
+///     CANFD_DATA_LEN,
+/// };
+///
+/// # fn main() -> Result<(), can_rs::Error> {
+/// let stream = FrameStream::<CANFD_DATA_LEN>::new("can0".parse()?)?;
+/// let recorder = Recorder::new(stream)?;
+/// let mut writer = LogWriter::new(std::io::stdout(), Format::Candump);
+/// loop {
+///     writer.write(&recorder.recv()?)?;
+/// }
+/// # }
+/// ```
+pub struct Recorder<const N: usize> {
+    stream: FrameStream<N>,
+}
+
+impl<const N: usize> Recorder<N> {
+    /// Enable software timestamps on `stream`, replacing hardware timestamps if enabled
+    pub fn new(stream: FrameStream<N>) -> Result<Self, Error> {
+        stream.set_timestamping(Timestamping::Software)?;
+        Ok(Self { stream })
+    }
+
+    pub fn get_ref(&self) -> &FrameStream<N> {
+        &self.stream
+    }
+
+    pub fn into_inner(self) -> FrameStream<N> {
+        self.stream
+    }
+
+    /// Receive the next frame and its kernel timestamp, skipping error frames
+    ///
+    /// Fails if the kernel didn't timestamp the frame, e.g. because timestamping was disabled on
+    /// the stream afterwards.
+    pub fn recv(&self) -> io::Result<Record<N>> {
+        loop {
+            let (received, timestamps) = self.stream.recv_timestamped(0)?;
+            let Received::Frame(frame) = received else {
+                continue;
+            };
+            let timestamp = timestamps.software.ok_or_else(|| {
+                io::Error::other("received a frame without a kernel timestamp")
+            })?;
+            return Ok(Record {
+                timestamp,
+                interface: self.stream.addr.name.clone(),
+                frame,
+            });
+        }
+    }
+}
+
+/// Writes [`Record`]s to a log
+///
+/// ASC logs need a trailer, call [`LogWriter::finish`] when done.
+pub struct LogWriter<W: Write> {
+    inner: W,
+    format: Format,
+    /// Timestamp of the first record, ASC timestamps are relative to it
+    start: Option<Duration>,
+    /// Interfaces in order of appearance, their ASC channel is the index + 1
+    channels: Vec<String>,
+}
+
+impl<W: Write> LogWriter<W> {
+    pub fn new(inner: W, format: Format) -> Self {
+        Self {
+            inner,
+            format,
+            start: None,
+            channels: Vec::new(),
+        }
+    }
+
+    pub fn write<const N: usize>(&mut self, record: &Record<N>) -> io::Result<()> {
+        let start = match self.start {
+            Some(start) => start,
+            None => {
+                if self.format == Format::Asc {
+                    imp::write_asc_header(&mut self.inner, record.timestamp)?;
+                }
+                *self.start.insert(record.timestamp)
+            }
+        };
+        match self.format {
+            Format::Candump => imp::write_candump(&mut self.inner, record),
+            Format::Asc => {
+                let channel = match self
+                    .channels
+                    .iter()
+                    .position(|name| *name == record.interface)
+                {
+                    Some(index) => index + 1,
+                    None => {
+                        self.channels.push(record.interface.clone());
+                        self.channels.len()
+                    }
+                };
+                let timestamp = record.timestamp.saturating_sub(start);
+                imp::write_asc(&mut self.inner, timestamp, channel, &record.frame)
+            }
+        }
+    }
+
+    /// Write the trailer of the log, flush it, and return the underlying writer
+    pub fn finish(mut self) -> io::Result<W> {
+        if self.format == Format::Asc {
+            if self.start.is_none() {
+                let now = std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .unwrap_or_default();
+                imp::write_asc_header(&mut self.inner, now)?;
+            }
+            writeln!(self.inner, "End TriggerBlock")?;
+        }
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+/// Reads [`Record`]s from a log, skipping comments, headers and non-frame events
+pub struct LogReader<R: BufRead, const N: usize> {
+    inner: R,
+    format: Format,
+    line: usize,
+    _frame: PhantomData<Frame<N>>,
+}
+
+impl<R: BufRead, const N: usize> LogReader<R, N> {
+    pub fn new(inner: R, format: Format) -> Self {
+        Self {
+            inner,
+            format,
+            line: 0,
+            _frame: PhantomData,
+        }
+    }
+}
+
+impl<R: BufRead, const N: usize> Iterator for LogReader<R, N> {
+    type Item = Result<Record<N>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            match self.inner.read_line(&mut line) {
+                Ok(0) => return None,
+                Ok(_) => self.line += 1,
+                Err(e) => return Some(Err(e.into())),
+            }
+            let parsed = match self.format {
+                Format::Candump => imp::parse_candump(line.trim()),
+                Format::Asc => imp::parse_asc(line.trim()),
+            };
+            match parsed {
+                Ok(Some(record)) => return Some(Ok(record)),
+                Ok(None) => continue,
+                Err(reason) => {
+                    return Some(Err(Error::InvalidLogLine {
+                        line: self.line,
+                        reason,
+                    }))
+                }
+            }
+        }
+    }
+}
+
+/// Pacing of a [`replay`]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Timing {
+    /// Keep the original gaps between frames
+    Original,
+    /// Divide the original gaps between frames by the factor, which must be positive
+    Accelerated(f64),
+    /// Send frames back to back
+    Immediate,
+}
+
+/// Send all `records` on `stream`, paced by `timing`, and return the number of frames sent
+///
+/// Frames are sent on the interface of `stream` regardless of the interface they were recorded
+/// on. Timestamps going backwards send the frame right away.
+pub fn replay<const N: usize, I>(
+    stream: &FrameStream<N>,
+    records: I,
+    timing: Timing,
+) -> Result<usize, Error>
+where
+    I: IntoIterator<Item = Result<Record<N>, Error>>,
+{
+    let started = Instant::now();
+    let mut first = None;
+    let mut sent = 0;
+    for record in records {
+        let record = record?;
+        let first = *first.get_or_insert(record.timestamp);
+        if let Some(offset) =
+            imp::offset(record.timestamp.saturating_sub(first), timing)
+        {
+            let remaining = offset.saturating_sub(started.elapsed());
+            if !remaining.is_zero() {
+                thread::sleep(remaining);
+            }
+        }
+        stream.send(&record.frame, 0)?;
+        sent += 1;
+    }
+    Ok(sent)
+}
+
+fn is_fd<const N: usize>(frame: &Frame<N>) -> bool {
+    N > CAN_DATA_LEN
+        && (frame.flags & CANFD_FDF_FLAG != 0 || frame.len as usize > CAN_DATA_LEN)
+}
+
+fn id_to_hex(id: Id, out: &mut String) {
+    match id {
+        Id::Standard(id) => write!(out, "{:03X}", id),
+        Id::Extended(id) => write!(out, "{:08X}", id),
+    }
+    .expect("writing to a String never fails")
+}
+
+mod imp {
+    use std::{fmt::Write as _, io, time::Duration};
+
+    use super::{id_to_hex, is_fd, Record, Timing};
+    use crate::{
+        convert_dlc_to_len, convert_len_to_dlc, error_frame::CAN_ERR_FLAG, Frame, Id,
+        Length, CANFD_FDF_FLAG, CAN_DATA_LEN,
+    };
+
+    const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov",
+        "Dec",
+    ];
+
+    pub(super) fn offset(elapsed: Duration, timing: Timing) -> Option<Duration> {
+        match timing {
+            Timing::Original => Some(elapsed),
+            // A tiny factor slows the replay down beyond what a Duration can hold
+            Timing::Accelerated(factor) if factor > 0.0 => Some(
+                Duration::try_from_secs_f64(elapsed.as_secs_f64() / factor)
+                    .unwrap_or(Duration::MAX),
+            ),
+            Timing::Accelerated(_) | Timing::Immediate => None,
+        }
+    }
+
+    pub(super) fn write_candump<const N: usize, W: io::Write>(
+        out: &mut W,
+        record: &Record<N>,
+    ) -> io::Result<()> {
+        let frame = &record.frame;
+        let mut line = format!(
+            "({}.{:06}) {} ",
+            record.timestamp.as_secs(),
+            record.timestamp.subsec_micros(),
+            record.interface
+        );
+        id_to_hex(frame.id, &mut line);
+        line.push('#');
+        if is_fd(frame) {
+            let flags = frame.flags & !CANFD_FDF_FLAG & 0x0F;
+            write!(line, "#{flags:X}").expect("writing to a String never fails");
+        }
+        for byte in &frame.data[..frame.len as usize] {
+            write!(line, "{byte:02X}").expect("writing to a String never fails");
+        }
+        writeln!(out, "{line}")
+    }
+
+    /// Parse a `candump -l` line, `None` for empty lines and comments
+    pub(super) fn parse_candump<const N: usize>(
+        line: &str,
+    ) -> Result<Option<Record<N>>, String> {
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(None);
+        }
+        let mut fields = line.split_whitespace();
+        let (Some(timestamp), Some(interface), Some(frame), None) =
+            (fields.next(), fields.next(), fields.next(), fields.next())
+        else {
+            return Err("expected `(<timestamp>) <interface> <frame>`".to_string());
+        };
+        let timestamp = timestamp
+            .strip_prefix('(')
+            .and_then(|t| t.strip_suffix(')'))
+            .ok_or_else(|| format!("invalid timestamp `{timestamp}`"))?;
+        let timestamp = parse_seconds(timestamp)?;
+        let (id, data) = frame
+            .split_once('#')
+            .ok_or_else(|| format!("invalid frame `{frame}`"))?;
+        let id = match id.len() {
+            3 => standard_id(parse_hex(id)?)?,
+            // Error frames of `candump -e` have 8 digit ids with the error flag
+            8 if parse_hex(id)? & CAN_ERR_FLAG != 0 => return Ok(None),
+            8 => extended_id(parse_hex(id)?)?,
+            _ => return Err(format!("invalid id `{id}`")),
+        };
+        if data.starts_with('R') {
+            return Ok(None);
+        }
+        let (flags, data) = match data.strip_prefix('#') {
+            Some(fd) => {
+                let flags = fd
+                    .get(..1)
+                    .and_then(|flags| u8::from_str_radix(flags, 16).ok())
+                    .ok_or_else(|| format!("invalid CAN FD flags in `{frame}`"))?;
+                (flags | CANFD_FDF_FLAG, &fd[1..])
+            }
+            None => (0, data),
+        };
+        let data = data
+            .as_bytes()
+            .chunks(2)
+            .map(|byte| {
+                std::str::from_utf8(byte)
+                    .ok()
+                    .filter(|byte| byte.len() == 2)
+                    .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                    .ok_or_else(|| format!("invalid data in `{frame}`"))
+            })
+            .collect::<Result<Vec<u8>, String>>()?;
+        Ok(Some(Record {
+            timestamp,
+            interface: interface.to_string(),
+            frame: to_frame(id, flags, &data)?,
+        }))
+    }
+
+    pub(super) fn write_asc_header<W: io::Write>(
+        out: &mut W,
+        since_epoch: Duration,
+    ) -> io::Result<()> {
+        let date = format_date(since_epoch);
+        writeln!(out, "date {date}")?;
+        writeln!(out, "base hex  timestamps absolute")?;
+        writeln!(out, "internal events logged")?;
+        writeln!(out, "Begin Triggerblock {date}")?;
+        writeln!(out, "   0.000000 Start of measurement")
+    }
+
+    pub(super) fn write_asc<const N: usize, W: io::Write>(
+        out: &mut W,
+        timestamp: Duration,
+        channel: usize,
+        frame: &Frame<N>,
+    ) -> io::Result<()> {
+        let id = match frame.id {
+            Id::Standard(id) => format!("{id:X}"),
+            Id::Extended(id) => format!("{id:X}x"),
+        };
+        let data = frame.data[..frame.len as usize]
+            .iter()
+            .map(|byte| format!("{byte:02X}"))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let time = format!("{}.{:06}", timestamp.as_secs(), timestamp.subsec_micros());
+        if is_fd(frame) {
+            let dlc = u8::from(convert_len_to_dlc(Length::Bytes(frame.len)));
+            let brs = frame.flags & libc::CANFD_BRS as u8 != 0;
+            let esi = frame.flags & libc::CANFD_ESI as u8 != 0;
+            // Duration, bit length and CRC of the message aren't known, flags only has EDL set
+            writeln!(
+                out,
+                "{time:>11} CANFD {channel:>3} Rx {id:>8} {:>32} {} {} {dlc:x} {:>2} {data} \
+                 {:>8} {:>4} {:>8X} {:>8}",
+                "",
+                u8::from(brs),
+                u8::from(esi),
+                frame.len,
+                0,
+                0,
+                0x1000,
+                0
+            )
+        } else {
+            writeln!(
+                out,
+                "{time:>11} {channel:<2} {id:<15} Rx   d {} {data}",
+                frame.len
+            )
+        }
+    }
+
+    /// Parse an ASC line, `None` for headers and events other than received or sent frames
+    pub(super) fn parse_asc<const N: usize>(
+        line: &str,
+    ) -> Result<Option<Record<N>>, String> {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let Some(timestamp) = fields.first().and_then(|t| parse_seconds(t).ok()) else {
+            return Ok(None);
+        };
+        let is_dir = |field: Option<&&str>| matches!(field, Some(&"Rx") | Some(&"Tx"));
+        if fields.get(1) == Some(&"CANFD") {
+            // <time> CANFD <channel> <dir> <id> [<symbolic name>] <brs> <esi> <dlc> <len> <data>
+            if !is_dir(fields.get(3)) || fields.len() < 6 {
+                return Ok(None);
+            }
+            let id = parse_asc_id(fields[4])?;
+            let rest = match fields[5] {
+                "0" | "1" => &fields[5..],
+                _ => &fields[6..],
+            };
+            let [brs, esi, _dlc, len, data @ ..] = rest else {
+                return Err("truncated CAN FD frame".to_string());
+            };
+            let len: usize = len
+                .parse()
+                .map_err(|_| format!("invalid data length `{len}`"))?;
+            let mut flags = CANFD_FDF_FLAG;
+            if *brs == "1" {
+                flags |= libc::CANFD_BRS as u8;
+            }
+            if *esi == "1" {
+                flags |= libc::CANFD_ESI as u8;
+            }
+            let data = parse_asc_data(data, len)?;
+            return Ok(Some(Record {
+                timestamp,
+                interface: fields[2].to_string(),
+                frame: to_frame(id, flags, &data)?,
+            }));
+        }
+        // <time> <channel> <id> <dir> d <dlc> <data>
+        if fields.len() < 6
+            || fields[1].parse::<u32>().is_err()
+            || !is_dir(fields.get(3))
+            || fields[4] != "d"
+        {
+            return Ok(None);
+        }
+        let id = parse_asc_id(fields[2])?;
+        let dlc: u8 = u8::from_str_radix(fields[5], 16)
+            .map_err(|_| format!("invalid dlc `{}`", fields[5]))?;
+        let len = u8::from(convert_dlc_to_len(Length::Dlc(dlc))) as usize;
+        let data = parse_asc_data(&fields[6..], len.min(CAN_DATA_LEN))?;
+        Ok(Some(Record {
+            timestamp,
+            interface: fields[1].to_string(),
+            frame: to_frame(id, 0, &data)?,
+        }))
+    }
+
+    fn parse_asc_id(id: &str) -> Result<Id, String> {
+        match id.strip_suffix('x') {
+            Some(extended) => extended_id(parse_hex(extended)?),
+            None => standard_id(parse_hex(id)?),
+        }
+    }
+
+    fn standard_id(id: u32) -> Result<Id, String> {
+        if id > libc::CAN_SFF_MASK {
+            return Err(format!("standard id {id:X} out of range"));
+        }
+        Ok(Id::Standard(id))
+    }
+
+    fn extended_id(id: u32) -> Result<Id, String> {
+        if id > libc::CAN_EFF_MASK {
+            return Err(format!("extended id {id:X} out of range"));
+        }
+        Ok(Id::Extended(id))
+    }
+
+    fn parse_asc_data(fields: &[&str], len: usize) -> Result<Vec<u8>, String> {
+        if fields.len() < len {
+            return Err(format!("expected {len} data bytes"));
+        }
+        fields[..len]
+            .iter()
+            .map(|byte| {
+                u8::from_str_radix(byte, 16)
+                    .map_err(|_| format!("invalid byte `{byte}`"))
+            })
+            .collect()
+    }
+
+    pub(super) fn to_frame<const N: usize>(
+        id: Id,
+        flags: u8,
+        data: &[u8],
+    ) -> Result<Frame<N>, String> {
+        if data.len() > N {
+            return Err(format!(
+                "{} data bytes don't fit into a frame of {N}",
+                data.len()
+            ));
+        }
+        let mut frame = Frame::empty();
+        frame.id = id;
+        frame.flags = if N > CAN_DATA_LEN { flags } else { 0 };
+        frame.len = data.len() as u8;
+        frame.data[..data.len()].copy_from_slice(data);
+        Ok(frame)
+    }
+
+    fn parse_hex(hex: &str) -> Result<u32, String> {
+        u32::from_str_radix(hex, 16).map_err(|_| format!("invalid id `{hex}`"))
+    }
+
+    fn parse_seconds(seconds: &str) -> Result<Duration, String> {
+        let invalid = || format!("invalid timestamp `{seconds}`");
+        let (secs, fraction) = seconds.split_once('.').unwrap_or((seconds, ""));
+        if fraction.len() > 9 || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(invalid());
+        }
+        let secs: u64 = secs.parse().map_err(|_| invalid())?;
+        let nanos = format!("{fraction:0<9}").parse().map_err(|_| invalid())?;
+        Ok(Duration::new(secs, nanos))
+    }
+
+    /// `Thu Jan 01 12:00:00.000 am 1970`, in UTC
+    pub(super) fn format_date(since_epoch: Duration) -> String {
+        let secs = since_epoch.as_secs();
+        let days = secs / 86400;
+        let (year, month, day) = civil_from_days(days as i64);
+        let (hour, minute, second) = (secs % 86400 / 3600, secs % 3600 / 60, secs % 60);
+        let (hour12, meridiem) = match hour {
+            0 => (12, "am"),
+            1..=11 => (hour, "am"),
+            12 => (12, "pm"),
+            _ => (hour - 12, "pm"),
+        };
+        format!(
+            "{} {} {day:02} {hour12:02}:{minute:02}:{second:02}.{:03} {meridiem} {year}",
+            DAYS[(days % 7) as usize],
+            MONTHS[month as usize - 1],
+            since_epoch.subsec_millis()
+        )
+    }
+
+    /// Year, month and day of the days since the UNIX epoch, see
+    /// <https://howardhinnant.github.io/date_algorithms.html#civil_from_days>
+    pub(super) fn civil_from_days(days: i64) -> (i64, u32, u32) {
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z.rem_euclid(146_097);
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = yoe + era * 400 + i64::from(month <= 2);
+        (year, month, day)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CANFD_DATA_LEN;
+
+    fn record<const N: usize>(
+        timestamp: Duration,
+        id: Id,
+        flags: u8,
+        data: &[u8],
+    ) -> Record<N> {
+        let mut frame = Frame::empty();
+        frame.id = id;
+        frame.flags = flags;
+        frame.len = data.len() as u8;
+        frame.data[..data.len()].copy_from_slice(data);
+        Record {
+            timestamp,
+            interface: "vcan0".to_string(),
+            frame,
+        }
+    }
+
+    fn records() -> Vec<Record<CANFD_DATA_LEN>> {
+        vec![
+            record(
+                Duration::new(1_436_509_052, 249_713_000),
+                Id::Standard(0x123),
+                0,
+                &[0xDE, 0xAD, 0xBE, 0xEF],
+            ),
+            record(
+                Duration::new(1_436_509_052, 251_000_000),
+                Id::Extended(0x1ABC_DEF0),
+                0,
+                &[],
+            ),
+            record(
+                Duration::new(1_436_509_053, 0),
+                Id::Standard(0x7FF),
+                CANFD_FDF_FLAG | libc::CANFD_BRS as u8,
+                &[0x55; 12],
+            ),
+        ]
+    }
+
+    fn round_trip(format: Format) -> (String, Vec<Record<CANFD_DATA_LEN>>) {
+        let mut writer = LogWriter::new(Vec::new(), format);
+        for record in records() {
+            writer.write(&record).unwrap();
+        }
+        let log = String::from_utf8(writer.finish().unwrap()).unwrap();
+        let read = LogReader::new(log.as_bytes(), format)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        (log, read)
+    }
+
+    #[test]
+    fn candump_round_trip() {
+        let (log, read) = round_trip(Format::Candump);
+        assert_eq!(
+            log,
+            "(1436509052.249713) vcan0 123#DEADBEEF\n\
+             (1436509052.251000) vcan0 1ABCDEF0#\n\
+             (1436509053.000000) vcan0 7FF##1555555555555555555555555\n"
+        );
+        assert_eq!(read, records());
+    }
+
+    #[test]
+    fn asc_round_trip() {
+        let (log, read) = round_trip(Format::Asc);
+        assert!(log.starts_with("date Fri Jul 10 06:17:32.249 am 2015\n"));
+        assert!(log.ends_with("End TriggerBlock\n"));
+        let expected: Vec<_> = records()
+            .into_iter()
+            .map(|mut record| {
+                record.timestamp -= Duration::new(1_436_509_052, 249_713_000);
+                record.interface = "1".to_string();
+                record
+            })
+            .collect();
+        assert_eq!(read, expected);
+    }
+
+    #[test]
+    fn parse_canutils_candump() {
+        let log = "(1436509052.249713) can0 00000123#R\n\
+                   (1436509052.349781) can0 20000004#0004000000000000\n\
+                   (1436509052.449847) can1 5AA#11223344\n";
+        let read: Vec<Record<CAN_DATA_LEN>> =
+            LogReader::new(log.as_bytes(), Format::Candump)
+                .collect::<Result<_, _>>()
+                .unwrap();
+        assert_eq!(read.len(), 1);
+        assert_eq!(read[0].interface, "can1");
+        assert_eq!(read[0].timestamp, Duration::new(1_436_509_052, 449_847_000));
+        assert_eq!(read[0].frame.id, Id::Standard(0x5AA));
+        assert_eq!(&read[0].frame.data[..4], &[0x11, 0x22, 0x33, 0x44]);
+    }
+
+    #[test]
+    fn parse_vector_asc() {
+        let log = "date Wed Oct 15 09:12:01.000 am 2026\n\
+                   base hex  timestamps absolute\n\
+                   Begin Triggerblock Wed Oct 15 09:12:01.000 am 2026\n\
+                   \x20  0.000000 Start of measurement\n\
+                   \x20  0.015991 CAN 1 Status:chip status error active\n\
+                   \x20  1.015991 2  18EBFF00x       Rx   d 8 01 02 03 04 05 06 07 08  \
+                   Length = 0 BitCount = 0 ID = 418119424x\n\
+                   \x20 17.876707 CANFD   1 Tx   2A1  EngineData  1 0 9 12 \
+                   00 01 02 03 04 05 06 07 08 09 0A 0B   0    0     1000 0\n\
+                   End TriggerBlock\n";
+        let read: Vec<Record<CANFD_DATA_LEN>> =
+            LogReader::new(log.as_bytes(), Format::Asc)
+                .collect::<Result<_, _>>()
+                .unwrap();
+        assert_eq!(read.len(), 2);
+        assert_eq!(read[0].interface, "2");
+        assert_eq!(read[0].frame.id, Id::Extended(0x18EB_FF00));
+        assert_eq!(read[0].frame.len, 8);
+        assert_eq!(read[1].timestamp, Duration::new(17, 876_707_000));
+        assert_eq!(read[1].frame.id, Id::Standard(0x2A1));
+        assert_eq!(read[1].frame.flags, CANFD_FDF_FLAG | libc::CANFD_BRS as u8);
+        assert_eq!(read[1].frame.len, 12);
+    }
+
+    #[test]
+    fn invalid_lines_are_reported() {
+        let log = "(1436509052.249713) can0 123#DEADBEEF\n(oops) can0 123#00\n";
+        let read: Vec<Result<Record<CAN_DATA_LEN>, Error>> =
+            LogReader::new(log.as_bytes(), Format::Candump).collect();
+        assert!(read[0].is_ok());
+        assert!(matches!(
+            read[1],
+            Err(Error::InvalidLogLine { line: 2, .. })
+        ));
+
+        let log = "(1436509052.249713) can0 123##1DEADBEEFDEADBEEFDEADBEEF\n";
+        let read: Vec<Result<Record<CAN_DATA_LEN>, Error>> =
+            LogReader::new(log.as_bytes(), Format::Candump).collect();
+        assert!(matches!(
+            read[0],
+            Err(Error::InvalidLogLine { line: 1, .. })
+        ));
+
+        for log in [
+            "(1436509052.249713) can0 800#00\n",
+            "(1436509052.249713) can0 40000000#00\n",
+        ] {
+            let read: Vec<Result<Record<CAN_DATA_LEN>, Error>> =
+                LogReader::new(log.as_bytes(), Format::Candump).collect();
+            assert!(matches!(
+                read[0],
+                Err(Error::InvalidLogLine { line: 1, .. })
+            ));
+        }
+        let log = "   1.000000 1  800             Rx   d 1 00\n";
+        let read: Vec<Result<Record<CAN_DATA_LEN>, Error>> =
+            LogReader::new(log.as_bytes(), Format::Asc).collect();
+        assert!(matches!(
+            read[0],
+            Err(Error::InvalidLogLine { line: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn replay_offsets() {
+        let second = Duration::from_secs(1);
+        assert_eq!(imp::offset(second, Timing::Original), Some(second));
+        assert_eq!(
+            imp::offset(second, Timing::Accelerated(4.0)),
+            Some(Duration::from_millis(250))
+        );
+        assert_eq!(
+            imp::offset(Duration::MAX, Timing::Accelerated(f64::MIN_POSITIVE)),
+            Some(Duration::MAX)
+        );
+        assert_eq!(imp::offset(second, Timing::Accelerated(0.0)), None);
+        assert_eq!(imp::offset(second, Timing::Immediate), None);
+    }
+
+    #[test]
+    fn asc_dates() {
+        assert_eq!(
+            imp::format_date(Duration::ZERO),
+            "Thu Jan 01 12:00:00.000 am 1970"
+        );
+        assert_eq!(
+            imp::format_date(Duration::from_millis(1_709_211_845_500)),
+            "Thu Feb 29 01:04:05.500 pm 2024"
+        );
+    }
+}
@@ -0,0 +1,853 @@
+//! Vector's binary logging format (BLF), as written by CANoe / CANalyzer
+//!
+//! A BLF log is a file header followed by objects, which are mostly stored in zlib compressed
+//! containers. [`BlfWriter`] writes CAN and CAN FD messages, [`BlfReader`] reads them back and
+//! skips all other objects, e.g. error frames and bus statistics.
+//!
+//! Objects are timestamped relative to the start of the measurement, which the file header
+//! stores in UTC with millisecond precision. [`Record::timestamp`]s are converted back to time
+//! since the UNIX epoch when reading, and [`Record::interface`] is the 1-based channel number.
+
+use std::{
+    io::{self, Read, Seek, SeekFrom, Write},
+    marker::PhantomData,
+    time::Duration,
+};
+
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+
+use super::{
+    imp::{civil_from_days, to_frame},
+    is_fd, Record,
+};
+use crate::{
+    convert_len_to_dlc, Error, Frame, Id, Length, CANFD_DATA_LEN, CANFD_FDF_FLAG,
+    CAN_DATA_LEN,
+};
+
+const FILE_SIGNATURE: &[u8; 4] = b"LOGG";
+const FILE_HEADER_SIZE: usize = 144;
+const OBJECT_SIGNATURE: &[u8; 4] = b"LOBJ";
+/// Signature, header size and version, object size and type
+const OBJECT_HEADER_BASE_SIZE: usize = 16;
+/// Base header, flags, client index, object version and timestamp. Version 2 headers add
+/// fields after the timestamp.
+const OBJECT_HEADER_V1_SIZE: usize = 32;
+/// Compression method and uncompressed size
+const CONTAINER_HEADER_SIZE: usize = 16;
+/// Objects are written in containers of at most this many uncompressed bytes
+const MAX_CONTAINER_SIZE: usize = 128 * 1024;
+/// Largest object read, compressed or not. Far above the containers written by CANoe and by
+/// [`BlfWriter`], so that corrupt sizes fail instead of allocating gigabytes.
+const MAX_OBJECT_SIZE: usize = 16 * 1024 * 1024;
+
+const CAN_MESSAGE: u32 = 1;
+const LOG_CONTAINER: u32 = 10;
+const CAN_MESSAGE2: u32 = 86;
+const CAN_FD_MESSAGE: u32 = 100;
+const CAN_FD_MESSAGE_64: u32 = 101;
+
+const NO_COMPRESSION: u16 = 0;
+const ZLIB_DEFLATE: u16 = 2;
+
+const TIME_TEN_MICS: u32 = 1;
+const TIME_ONE_NANS: u32 = 2;
+
+const CAN_MSG_EXT: u32 = 0x8000_0000;
+const CAN_MSG_REMOTE: u8 = 0x80;
+const CAN_FD_MSG_EDL: u8 = 0x01;
+const CAN_FD_MSG_BRS: u8 = 0x02;
+const CAN_FD_MSG_ESI: u8 = 0x04;
+const CAN_FD_MSG_64_REMOTE: u32 = 0x0010;
+const CAN_FD_MSG_64_EDL: u32 = 0x1000;
+const CAN_FD_MSG_64_BRS: u32 = 0x2000;
+const CAN_FD_MSG_64_ESI: u32 = 0x4000;
+
+/// Writes [`Record`]s as CAN and CAN FD messages to a BLF log
+///
+/// The file header holds the size of the log and the number of objects, so it's rewritten by
+/// [`BlfWriter::finish`], which has to be called to complete the log.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::fs::File;
+///
+/// use can_rs::{
+///     capture::{blf::BlfWriter, Recorder},
+///     stream::FrameStream,
+///     CANFD_DATA_LEN,
+/// };
+///
+/// # fn main() -> Result<(), can_rs::Error> {
+/// let stream = FrameStream::<CANFD_DATA_LEN>::build().bind("can0".parse()?)?;
+/// let recorder = Recorder::new(stream)?;
+/// let mut writer = BlfWriter::new(File::create("can0.blf")?)?;
+/// for _ in 0..100 {
+///     writer.write(&recorder.recv()?)?;
+/// }
+/// writer.finish()?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct BlfWriter<W: Write + Seek> {
+    inner: W,
+    /// Position of the file header in `inner`
+    header_pos: u64,
+    /// Start of the measurement, truncated to the precision of the file header
+    start: Option<Duration>,
+    stop: Duration,
+    channels: Vec<String>,
+    /// Objects of the current container, before compression
+    container: Vec<u8>,
+    objects: u32,
+    uncompressed_size: u64,
+}
+
+impl<W: Write + Seek> BlfWriter<W> {
+    /// Start a log at the current position of `inner`
+    pub fn new(mut inner: W) -> io::Result<Self> {
+        let header_pos = inner.stream_position()?;
+        let mut writer = Self {
+            inner,
+            header_pos,
+            start: None,
+            stop: Duration::ZERO,
+            channels: Vec::new(),
+            container: Vec::new(),
+            objects: 0,
+            uncompressed_size: FILE_HEADER_SIZE as u64,
+        };
+        writer.write_header(0)?;
+        Ok(writer)
+    }
+
+    /// Write a record. Channels are numbered by the order their interfaces appear in.
+    pub fn write<const N: usize>(&mut self, record: &Record<N>) -> io::Result<()> {
+        let start = *self.start.get_or_insert_with(|| {
+            Duration::new(
+                record.timestamp.as_secs(),
+                record.timestamp.subsec_millis() * 1_000_000,
+            )
+        });
+        self.stop = self.stop.max(record.timestamp);
+        let channel = match self.channels.iter().position(|c| *c == record.interface) {
+            Some(index) => index + 1,
+            None => {
+                self.channels.push(record.interface.clone());
+                self.channels.len()
+            }
+        };
+        let channel = u16::try_from(channel).unwrap_or(u16::MAX);
+
+        let frame = &record.frame;
+        let id = match frame.id {
+            Id::Standard(id) => id,
+            Id::Extended(id) => id | CAN_MSG_EXT,
+        };
+        let len = frame.len as usize;
+        let (obj_type, payload) = if is_fd(frame) {
+            let mut payload = Vec::with_capacity(20 + CANFD_DATA_LEN);
+            payload.extend_from_slice(&channel.to_le_bytes());
+            payload.push(0); // received
+            payload.push(u8::from(convert_len_to_dlc(Length::Bytes(frame.len))));
+            payload.extend_from_slice(&id.to_le_bytes());
+            payload.extend_from_slice(&[0; 5]); // frame length in ns, bit count
+            let mut fd_flags = CAN_FD_MSG_EDL;
+            if frame.flags & libc::CANFD_BRS as u8 != 0 {
+                fd_flags |= CAN_FD_MSG_BRS;
+            }
+            if frame.flags & libc::CANFD_ESI as u8 != 0 {
+                fd_flags |= CAN_FD_MSG_ESI;
+            }
+            payload.push(fd_flags);
+            payload.push(frame.len);
+            payload.extend_from_slice(&[0; 5]);
+            payload.extend_from_slice(&frame.data[..len]);
+            payload.resize(20 + CANFD_DATA_LEN, 0);
+            (CAN_FD_MESSAGE, payload)
+        } else {
+            let mut payload = Vec::with_capacity(8 + CAN_DATA_LEN);
+            payload.extend_from_slice(&channel.to_le_bytes());
+            payload.push(0); // received
+            payload.push(frame.len);
+            payload.extend_from_slice(&id.to_le_bytes());
+            payload.extend_from_slice(&frame.data[..len]);
+            payload.resize(8 + CAN_DATA_LEN, 0);
+            (CAN_MESSAGE, payload)
+        };
+
+        let timestamp = record.timestamp.saturating_sub(start).as_nanos();
+        let obj_size = OBJECT_HEADER_V1_SIZE + payload.len();
+        write_object_header(&mut self.container, obj_size, obj_type);
+        self.container
+            .extend_from_slice(&TIME_ONE_NANS.to_le_bytes());
+        self.container.extend_from_slice(&[0; 4]); // client index, object version
+        self.container.extend_from_slice(
+            &u64::try_from(timestamp).unwrap_or(u64::MAX).to_le_bytes(),
+        );
+        self.container.extend_from_slice(&payload);
+        self.objects += 1;
+
+        if self.container.len() >= MAX_CONTAINER_SIZE {
+            self.write_container()?;
+        }
+        Ok(())
+    }
+
+    /// Write the last container and complete the file header
+    pub fn finish(mut self) -> io::Result<W> {
+        self.write_container()?;
+        let end = self.inner.stream_position()?;
+        self.inner.seek(SeekFrom::Start(self.header_pos))?;
+        self.write_header(end - self.header_pos)?;
+        self.inner.seek(SeekFrom::Start(end))?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    fn write_container(&mut self) -> io::Result<()> {
+        if self.container.is_empty() {
+            return Ok(());
+        }
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&self.container)?;
+        let compressed = encoder.finish()?;
+
+        let header_size = OBJECT_HEADER_BASE_SIZE + CONTAINER_HEADER_SIZE;
+        let obj_size = header_size + compressed.len();
+        let mut header = Vec::with_capacity(header_size);
+        write_object_header(&mut header, obj_size, LOG_CONTAINER);
+        header.extend_from_slice(&ZLIB_DEFLATE.to_le_bytes());
+        header.extend_from_slice(&[0; 6]);
+        header.extend_from_slice(&(self.container.len() as u32).to_le_bytes());
+        header.extend_from_slice(&[0; 4]);
+        self.inner.write_all(&header)?;
+        self.inner.write_all(&compressed)?;
+        self.inner.write_all(&[0; 3][..obj_size % 4])?;
+
+        self.uncompressed_size += (header_size + self.container.len()) as u64;
+        self.container.clear();
+        Ok(())
+    }
+
+    fn write_header(&mut self, file_size: u64) -> io::Result<()> {
+        let mut header = Vec::with_capacity(FILE_HEADER_SIZE);
+        header.extend_from_slice(FILE_SIGNATURE);
+        header.extend_from_slice(&(FILE_HEADER_SIZE as u32).to_le_bytes());
+        // Application id, application version and BLF version, as written by python-can
+        header.extend_from_slice(&[5, 0, 0, 0, 2, 6, 8, 1]);
+        header.extend_from_slice(&file_size.to_le_bytes());
+        header.extend_from_slice(&self.uncompressed_size.to_le_bytes());
+        header.extend_from_slice(&self.objects.to_le_bytes());
+        header.extend_from_slice(&[0; 4]); // objects read
+        header.extend_from_slice(&system_time(self.start));
+        header.extend_from_slice(&system_time(self.start.map(|_| self.stop)));
+        header.resize(FILE_HEADER_SIZE, 0);
+        self.inner.write_all(&header)
+    }
+}
+
+/// Reads the CAN and CAN FD messages of a BLF log as [`Record`]s
+///
+/// Remote frames are skipped, like all objects other than messages. Iteration stops after the
+/// first error.
+pub struct BlfReader<R: Read, const N: usize> {
+    inner: R,
+    /// Start of the measurement, once the file header was read
+    start: Option<Duration>,
+    /// Objects of the containers read so far, parsed up to `pos`. Objects may continue in the
+    /// next container.
+    objects: Vec<u8>,
+    pos: usize,
+    failed: bool,
+    _frame: PhantomData<Frame<N>>,
+}
+
+impl<R: Read, const N: usize> BlfReader<R, N> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            start: None,
+            objects: Vec::new(),
+            pos: 0,
+            failed: false,
+            _frame: PhantomData,
+        }
+    }
+
+    fn next_record(&mut self) -> Result<Option<Record<N>>, Error> {
+        let start = match self.start {
+            Some(start) => start,
+            None => *self.start.insert(read_file_header(&mut self.inner)?),
+        };
+        loop {
+            while let Some((len, record)) =
+                parse_object(&self.objects[self.pos..], start)?
+            {
+                self.pos += len;
+                if record.is_some() {
+                    return Ok(record);
+                }
+            }
+            self.objects.drain(..self.pos);
+            self.pos = 0;
+            if !self.read_container()? {
+                if self.objects.windows(4).any(|w| w == OBJECT_SIGNATURE) {
+                    return Err(invalid("truncated object"));
+                }
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Append the objects of the next container, `false` at the end of the log
+    fn read_container(&mut self) -> Result<bool, Error> {
+        loop {
+            let mut header = [0; OBJECT_HEADER_BASE_SIZE];
+            if !read_or_eof(&mut self.inner, &mut header)? {
+                return Ok(false);
+            }
+            if &header[..4] != OBJECT_SIGNATURE {
+                return Err(invalid("missing object signature"));
+            }
+            let obj_size = u32_at(&header, 8) as usize;
+            let obj_type = u32_at(&header, 12);
+            let Some(body_size) = obj_size.checked_sub(OBJECT_HEADER_BASE_SIZE) else {
+                return Err(invalid(format!("object of {obj_size} bytes")));
+            };
+            if obj_size > MAX_OBJECT_SIZE {
+                return Err(invalid(format!(
+                    "object of {obj_size} bytes is too large"
+                )));
+            }
+            // Padding may be missing after the last object
+            let padding = (obj_size % 4) as u64;
+            if obj_type != LOG_CONTAINER {
+                let skipped = io::copy(
+                    &mut (&mut self.inner).take(body_size as u64),
+                    &mut io::sink(),
+                )?;
+                if skipped < body_size as u64 {
+                    return Err(invalid("truncated object"));
+                }
+                io::copy(&mut (&mut self.inner).take(padding), &mut io::sink())?;
+                continue;
+            }
+
+            let mut body = Vec::new();
+            (&mut self.inner)
+                .take(body_size as u64)
+                .read_to_end(&mut body)?;
+            if body.len() < body_size {
+                return Err(invalid("truncated container"));
+            }
+            io::copy(&mut (&mut self.inner).take(padding), &mut io::sink())?;
+            if body.len() < CONTAINER_HEADER_SIZE {
+                return Err(invalid("truncated container"));
+            }
+            let data = &body[CONTAINER_HEADER_SIZE..];
+            match u16_at(&body, 0) {
+                NO_COMPRESSION => self.objects.extend_from_slice(data),
+                ZLIB_DEFLATE => {
+                    let size = u32_at(&body, 8) as usize;
+                    if size > MAX_OBJECT_SIZE {
+                        return Err(invalid(format!(
+                            "container of {size} bytes is too large"
+                        )));
+                    }
+                    let start = self.objects.len();
+                    ZlibDecoder::new(data)
+                        .take(size as u64 + 1)
+                        .read_to_end(&mut self.objects)
+                        .map_err(|e| invalid(format!("corrupt container: {e}")))?;
+                    if self.objects.len() - start != size {
+                        return Err(invalid(format!(
+                            "container doesn't decompress to {size} bytes"
+                        )));
+                    }
+                }
+                method => {
+                    return Err(invalid(format!("unsupported compression {method}")));
+                }
+            }
+            return Ok(true);
+        }
+    }
+}
+
+impl<R: Read, const N: usize> Iterator for BlfReader<R, N> {
+    type Item = Result<Record<N>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let next = self.next_record().transpose();
+        self.failed = matches!(next, Some(Err(_)));
+        next
+    }
+}
+
+fn invalid(reason: impl Into<String>) -> Error {
+    Error::InvalidBlf {
+        reason: reason.into(),
+    }
+}
+
+fn write_object_header(out: &mut Vec<u8>, obj_size: usize, obj_type: u32) {
+    let header_size = if obj_type == LOG_CONTAINER {
+        OBJECT_HEADER_BASE_SIZE
+    } else {
+        OBJECT_HEADER_V1_SIZE
+    };
+    out.extend_from_slice(OBJECT_SIGNATURE);
+    out.extend_from_slice(&(header_size as u16).to_le_bytes());
+    out.extend_from_slice(&1u16.to_le_bytes());
+    out.extend_from_slice(&(obj_size as u32).to_le_bytes());
+    out.extend_from_slice(&obj_type.to_le_bytes());
+}
+
+/// Start of the measurement, or zero if the header doesn't have it
+fn read_file_header<R: Read>(inner: &mut R) -> Result<Duration, Error> {
+    let mut header = [0; FILE_HEADER_SIZE];
+    inner.read_exact(&mut header[..8])?;
+    if &header[..4] != FILE_SIGNATURE {
+        return Err(invalid("missing file signature"));
+    }
+    let header_size = u32_at(&header, 4) as usize;
+    if header_size < 72 {
+        return Err(invalid(format!("file header of {header_size} bytes")));
+    }
+    let read = header_size.min(FILE_HEADER_SIZE);
+    inner.read_exact(&mut header[8..read])?;
+    io::copy(
+        &mut inner.take((header_size - read) as u64),
+        &mut io::sink(),
+    )?;
+    Ok(from_system_time(&header[40..56]))
+}
+
+/// A Windows `SYSTEMTIME` in UTC, all zeros for `None`
+fn system_time(since_epoch: Option<Duration>) -> [u8; 16] {
+    let mut out = [0; 16];
+    let Some(since_epoch) = since_epoch else {
+        return out;
+    };
+    let secs = since_epoch.as_secs();
+    let days = secs / 86400;
+    let (year, month, day) = civil_from_days(days as i64);
+    let fields = [
+        u16::try_from(year).unwrap_or(u16::MAX),
+        month as u16,
+        ((days + 4) % 7) as u16, // 1970-01-01 was a Thursday
+        day as u16,
+        (secs % 86400 / 3600) as u16,
+        (secs % 3600 / 60) as u16,
+        (secs % 60) as u16,
+        since_epoch.subsec_millis() as u16,
+    ];
+    for (chunk, field) in out.chunks_exact_mut(2).zip(fields) {
+        chunk.copy_from_slice(&field.to_le_bytes());
+    }
+    out
+}
+
+fn from_system_time(time: &[u8]) -> Duration {
+    let field = |i: usize| u64::from(u16_at(time, 2 * i));
+    let (year, month, day) = (field(0), field(1), field(3));
+    if year < 1970 || !(1..=12).contains(&month) || day == 0 {
+        return Duration::ZERO;
+    }
+    let days = days_from_civil(year as i64, month as u32, day as u32) as u64;
+    let secs = days * 86400 + field(4) * 3600 + field(5) * 60 + field(6);
+    Duration::from_secs(secs) + Duration::from_millis(field(7))
+}
+
+/// Days since the UNIX epoch of a date, see
+/// <https://howardhinnant.github.io/date_algorithms.html#days_from_civil>
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = i64::from((month + 9) % 12);
+    let doy = (153 * mp + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Length and record of the next object in `objects`, `None` if it's incomplete. Objects
+/// other than messages have no record.
+fn parse_object<const N: usize>(
+    objects: &[u8],
+    start: Duration,
+) -> Result<Option<(usize, Option<Record<N>>)>, Error> {
+    // Objects are padded to 4 bytes by CANoe, and by the payload size modulo 4 by python-can
+    let window = &objects[..objects.len().min(8)];
+    let Some(skip) = window.windows(4).position(|w| w == OBJECT_SIGNATURE) else {
+        if objects.len() < 8 {
+            return Ok(None);
+        }
+        return Err(invalid("missing object signature"));
+    };
+    let object = &objects[skip..];
+    if object.len() < OBJECT_HEADER_BASE_SIZE {
+        return Ok(None);
+    }
+    let header_size = u16_at(object, 4) as usize;
+    let header_version = u16_at(object, 6);
+    let obj_size = u32_at(object, 8) as usize;
+    let obj_type = u32_at(object, 12);
+    if !matches!(header_version, 1 | 2) {
+        return Err(invalid(format!("object header version {header_version}")));
+    }
+    if header_size < OBJECT_HEADER_V1_SIZE || obj_size < header_size {
+        return Err(invalid(format!(
+            "object of {obj_size} bytes with a header of {header_size}"
+        )));
+    }
+    if obj_size > MAX_OBJECT_SIZE {
+        return Err(invalid(format!("object of {obj_size} bytes is too large")));
+    }
+    if object.len() < obj_size {
+        return Ok(None);
+    }
+
+    let timestamp = u64_at(object, 24);
+    let offset = match u32_at(object, 16) {
+        TIME_TEN_MICS => Duration::from_micros(timestamp.saturating_mul(10)),
+        _ => Duration::from_nanos(timestamp),
+    };
+    let payload = &object[header_size..obj_size];
+    let message = match obj_type {
+        CAN_MESSAGE | CAN_MESSAGE2 => parse_can_message(payload)?,
+        CAN_FD_MESSAGE => parse_can_fd_message(payload)?,
+        CAN_FD_MESSAGE_64 => parse_can_fd_message_64(payload)?,
+        _ => None,
+    };
+    let record = message.map(|(channel, frame)| Record {
+        timestamp: start.saturating_add(offset),
+        interface: channel.to_string(),
+        frame,
+    });
+    Ok(Some((skip + obj_size, record)))
+}
+
+fn parse_can_message<const N: usize>(
+    payload: &[u8],
+) -> Result<Option<(u16, Frame<N>)>, Error> {
+    if payload.len() < 8 + CAN_DATA_LEN {
+        return Err(invalid("truncated CAN message"));
+    }
+    if payload[2] & CAN_MSG_REMOTE != 0 {
+        return Ok(None);
+    }
+    let len = (payload[3] as usize).min(CAN_DATA_LEN);
+    let frame = to_frame(to_id(u32_at(payload, 4)), 0, &payload[8..8 + len])
+        .map_err(invalid)?;
+    Ok(Some((u16_at(payload, 0), frame)))
+}
+
+fn parse_can_fd_message<const N: usize>(
+    payload: &[u8],
+) -> Result<Option<(u16, Frame<N>)>, Error> {
+    if payload.len() < 20 + CANFD_DATA_LEN {
+        return Err(invalid("truncated CAN FD message"));
+    }
+    if payload[2] & CAN_MSG_REMOTE != 0 {
+        return Ok(None);
+    }
+    let fd_flags = payload[13];
+    let mut flags = 0;
+    if fd_flags & CAN_FD_MSG_EDL != 0 {
+        flags |= CANFD_FDF_FLAG;
+    }
+    if fd_flags & CAN_FD_MSG_BRS != 0 {
+        flags |= libc::CANFD_BRS as u8;
+    }
+    if fd_flags & CAN_FD_MSG_ESI != 0 {
+        flags |= libc::CANFD_ESI as u8;
+    }
+    // The DLC of an FD frame may round up its length
+    let len = (payload[14] as usize).min(CANFD_DATA_LEN);
+    let data = &payload[20..20 + len];
+    let frame = to_frame(to_id(u32_at(payload, 4)), flags, data).map_err(invalid)?;
+    Ok(Some((u16_at(payload, 0), frame)))
+}
+
+fn parse_can_fd_message_64<const N: usize>(
+    payload: &[u8],
+) -> Result<Option<(u16, Frame<N>)>, Error> {
+    if payload.len() < 40 {
+        return Err(invalid("truncated CAN FD message"));
+    }
+    let msg_flags = u32_at(payload, 12);
+    if msg_flags & CAN_FD_MSG_64_REMOTE != 0 {
+        return Ok(None);
+    }
+    let mut flags = 0;
+    if msg_flags & CAN_FD_MSG_64_EDL != 0 {
+        flags |= CANFD_FDF_FLAG;
+    }
+    if msg_flags & CAN_FD_MSG_64_BRS != 0 {
+        flags |= libc::CANFD_BRS as u8;
+    }
+    if msg_flags & CAN_FD_MSG_64_ESI != 0 {
+        flags |= libc::CANFD_ESI as u8;
+    }
+    let len = (payload[2] as usize).min(CANFD_DATA_LEN);
+    let data = payload.get(40..40 + len).ok_or_else(|| {
+        invalid(format!("CAN FD message with less than {len} data bytes"))
+    })?;
+    let frame = to_frame(to_id(u32_at(payload, 4)), flags, data).map_err(invalid)?;
+    Ok(Some((u16::from(payload[0]), frame)))
+}
+
+fn to_id(id: u32) -> Id {
+    if id & CAN_MSG_EXT != 0 {
+        Id::Extended(id & libc::CAN_EFF_MASK)
+    } else {
+        Id::Standard(id & libc::CAN_SFF_MASK)
+    }
+}
+
+/// Fill `buf`, `false` if `inner` is at its end
+fn read_or_eof<R: Read>(inner: &mut R, buf: &mut [u8]) -> io::Result<bool> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match inner.read(&mut buf[filled..]) {
+            Ok(0) if filled == 0 => return Ok(false),
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(true)
+}
+
+fn u16_at(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+fn u64_at(bytes: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn record(
+        timestamp: Duration,
+        interface: &str,
+        id: Id,
+        flags: u8,
+        data: &[u8],
+    ) -> Record<CANFD_DATA_LEN> {
+        let mut frame = Frame::empty();
+        frame.id = id;
+        frame.flags = flags;
+        frame.len = data.len() as u8;
+        frame.data[..data.len()].copy_from_slice(data);
+        Record {
+            timestamp,
+            interface: interface.to_string(),
+            frame,
+        }
+    }
+
+    fn records() -> Vec<Record<CANFD_DATA_LEN>> {
+        vec![
+            record(
+                Duration::new(1_436_509_052, 249_713_000),
+                "vcan0",
+                Id::Standard(0x123),
+                0,
+                &[0xDE, 0xAD, 0xBE, 0xEF],
+            ),
+            record(
+                Duration::new(1_436_509_052, 251_000_000),
+                "vcan1",
+                Id::Extended(0x1ABC_DEF0),
+                0,
+                &[],
+            ),
+            record(
+                Duration::new(1_436_509_053, 0),
+                "vcan0",
+                Id::Standard(0x7FF),
+                CANFD_FDF_FLAG | libc::CANFD_BRS as u8,
+                &[0x55; 12],
+            ),
+        ]
+    }
+
+    fn write(records: &[Record<CANFD_DATA_LEN>]) -> Vec<u8> {
+        let mut writer = BlfWriter::new(Cursor::new(Vec::new())).unwrap();
+        for record in records {
+            writer.write(record).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn read(log: &[u8]) -> Result<Vec<Record<CANFD_DATA_LEN>>, Error> {
+        BlfReader::new(log).collect()
+    }
+
+    /// A CAN message object with a version 1 header, timestamped in 10 µs units
+    fn can_message(timestamp: u64, flags: u8, id: u32, data: &[u8]) -> Vec<u8> {
+        let mut object = Vec::new();
+        write_object_header(&mut object, 48, CAN_MESSAGE);
+        object.extend_from_slice(&TIME_TEN_MICS.to_le_bytes());
+        object.extend_from_slice(&[0; 4]);
+        object.extend_from_slice(&timestamp.to_le_bytes());
+        object.extend_from_slice(&[2, 0, flags, data.len() as u8]);
+        object.extend_from_slice(&id.to_le_bytes());
+        object.extend_from_slice(data);
+        object.resize(48, 0);
+        object
+    }
+
+    fn uncompressed_container(objects: &[u8]) -> Vec<u8> {
+        let obj_size = OBJECT_HEADER_BASE_SIZE + CONTAINER_HEADER_SIZE + objects.len();
+        let mut container = Vec::new();
+        write_object_header(&mut container, obj_size, LOG_CONTAINER);
+        container.extend_from_slice(&NO_COMPRESSION.to_le_bytes());
+        container.extend_from_slice(&[0; 6]);
+        container.extend_from_slice(&(objects.len() as u32).to_le_bytes());
+        container.extend_from_slice(&[0; 4]);
+        container.extend_from_slice(objects);
+        container.resize(container.len() + obj_size % 4, 0);
+        container
+    }
+
+    #[test]
+    fn round_trip() {
+        let log = write(&records());
+        assert_eq!(&log[..4], FILE_SIGNATURE);
+        assert_eq!(u64_at(&log, 16), log.len() as u64);
+        assert_eq!(u32_at(&log, 32), 3);
+        // 2015-07-10 06:17:32.249 UTC, a Friday
+        assert_eq!(
+            system_time(Some(Duration::new(1_436_509_052, 249_713_000))),
+            [223, 7, 7, 0, 5, 0, 10, 0, 6, 0, 17, 0, 32, 0, 249, 0]
+        );
+        assert_eq!(
+            &log[40..56],
+            system_time(Some(Duration::new(1_436_509_052, 249_000_000)))
+        );
+        assert_eq!(
+            &log[56..72],
+            system_time(Some(Duration::new(1_436_509_053, 0)))
+        );
+
+        let mut expected = records();
+        expected[0].interface = "1".to_string();
+        expected[1].interface = "2".to_string();
+        expected[2].interface = "1".to_string();
+        assert_eq!(read(&log).unwrap(), expected);
+    }
+
+    #[test]
+    fn classic_frames_only() {
+        let log = write(&records()[..2]);
+        let read = BlfReader::<_, CAN_DATA_LEN>::new(&log[..])
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(read.len(), 2);
+        assert_eq!(read[0].frame.id, Id::Standard(0x123));
+        assert_eq!(read[0].frame.data[..4], [0xDE, 0xAD, 0xBE, 0xEF]);
+        assert_eq!(read[1].frame.id, Id::Extended(0x1ABC_DEF0));
+    }
+
+    #[test]
+    fn large_logs_are_split_into_containers() {
+        let records: Vec<_> = (0..5000)
+            .map(|i| {
+                record(
+                    Duration::from_millis(1_700_000_000_000 + i),
+                    "can0",
+                    Id::Standard(i as u32 & 0x7FF),
+                    0,
+                    &i.to_le_bytes(),
+                )
+            })
+            .collect();
+        let log = write(&records);
+        let containers = log
+            .windows(4)
+            .zip(log.iter().skip(12))
+            .filter(|(w, ty)| *w == OBJECT_SIGNATURE && **ty == LOG_CONTAINER as u8)
+            .count();
+        assert!(containers > 1);
+        let read = read(&log).unwrap();
+        assert_eq!(read.len(), records.len());
+        assert_eq!(read[4999].frame.data[..8], 4999u64.to_le_bytes());
+    }
+
+    #[test]
+    fn objects_span_containers() {
+        let mut objects = can_message(100, 0, 0x123, &[1, 2, 3]);
+        // Remote frames and other objects are skipped
+        objects.extend(can_message(200, CAN_MSG_REMOTE, 0x124, &[]));
+        let mut other = can_message(300, 0, 0x125, &[]);
+        other[12] = 73; // CAN_ERROR_EXT
+        objects.extend(other);
+        objects.extend(can_message(400, 0, 0x8000_0456, &[4, 5]));
+
+        let mut log = write(&[]);
+        log.extend(uncompressed_container(&objects[..100]));
+        log.extend(uncompressed_container(&objects[100..]));
+        let read = read(&log).unwrap();
+        assert_eq!(read.len(), 2);
+        assert_eq!(read[0].timestamp, Duration::from_millis(1));
+        assert_eq!(read[0].interface, "2");
+        assert_eq!(read[0].frame.id, Id::Standard(0x123));
+        assert_eq!(read[0].frame.data[..3], [1, 2, 3]);
+        assert_eq!(read[1].timestamp, Duration::from_millis(4));
+        assert_eq!(read[1].frame.id, Id::Extended(0x456));
+    }
+
+    #[test]
+    fn invalid_logs_are_reported() {
+        assert!(matches!(
+            read(b"LOGF\x90\0\0\0"),
+            Err(Error::InvalidBlf { .. })
+        ));
+
+        let mut log = write(&[]);
+        log.extend(uncompressed_container(&can_message(0, 0, 0x123, &[])[..40]));
+        let mut reader = BlfReader::<_, CANFD_DATA_LEN>::new(&log[..]);
+        assert!(matches!(reader.next(), Some(Err(Error::InvalidBlf { .. }))));
+        assert!(reader.next().is_none());
+
+        let mut log = write(&records());
+        log.truncate(log.len() - 10);
+        assert!(matches!(read(&log), Err(Error::InvalidBlf { .. })));
+
+        // Corrupt sizes fail without allocating them
+        let mut log = write(&[]);
+        write_object_header(&mut log, u32::MAX as usize, LOG_CONTAINER);
+        assert!(matches!(read(&log), Err(Error::InvalidBlf { .. })));
+
+        let mut log = write(&records());
+        let size = FILE_HEADER_SIZE + OBJECT_HEADER_BASE_SIZE + 8;
+        log[size..size + 4].copy_from_slice(&1u32.to_le_bytes());
+        assert!(matches!(read(&log), Err(Error::InvalidBlf { .. })));
+    }
+
+    #[test]
+    fn civil_dates() {
+        for days in [-1, 0, 59, 16_626, 19_723, 2_932_896] {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+    }
+}
@@ -0,0 +1,370 @@
+//! Decoding of CAN error frames
+//!
+//! Controllers report bus and controller problems as error frames, which are only received by
+//! sockets that subscribed to them with an error mask, see [`FrameStreamBuilder::error_filter`].
+//! [`FrameStream::recv_timestamped`] decodes them into an [`ErrorFrame`].
+//!
+//! See `include/uapi/linux/can/error.h` in the kernel for the layout of error frames.
+//!
+//! [`FrameStreamBuilder::error_filter`]: crate::stream::FrameStreamBuilder::error_filter
+//! [`FrameStream::recv_timestamped`]: crate::stream::FrameStream::recv_timestamped
+
+/// Set in the CAN ID of error frames
+pub const CAN_ERR_FLAG: u32 = 0x2000_0000;
+
+/// Error classes, set in the CAN ID of error frames and used as error mask
+pub const CAN_ERR_TX_TIMEOUT: u32 = 0x0001;
+pub const CAN_ERR_LOSTARB: u32 = 0x0002;
+pub const CAN_ERR_CRTL: u32 = 0x0004;
+pub const CAN_ERR_PROT: u32 = 0x0008;
+pub const CAN_ERR_TRX: u32 = 0x0010;
+pub const CAN_ERR_ACK: u32 = 0x0020;
+pub const CAN_ERR_BUSOFF: u32 = 0x0040;
+pub const CAN_ERR_BUSERROR: u32 = 0x0080;
+pub const CAN_ERR_RESTARTED: u32 = 0x0100;
+pub const CAN_ERR_CNT: u32 = 0x0200;
+/// All error classes
+pub const CAN_ERR_MASK: u32 = 0x1FFF_FFFF;
+
+/// A decoded error frame
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ErrorFrame {
+    /// All errors reported by the frame, a single frame can report several
+    pub errors: Vec<CanError>,
+    /// Transmit and receive error counters, if reported by the controller
+    pub counters: Option<ErrorCounters>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ErrorCounters {
+    pub tx: u8,
+    pub rx: u8,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CanError {
+    /// TX timeout, reported by the netdevice driver
+    TransmitTimeout,
+    /// Lost arbitration, at the given bit if known
+    LostArbitration { bit: Option<u8> },
+    /// Controller problems, e.g. a change of the error state
+    Controller(Vec<ControllerProblem>),
+    /// Protocol violations, and where in the frame they occurred
+    Protocol {
+        violations: Vec<ProtocolViolation>,
+        location: ProtocolLocation,
+    },
+    /// Transceiver status
+    Transceiver(TransceiverError),
+    /// No acknowledgement on transmission
+    NoAck,
+    /// The controller went bus-off
+    BusOff,
+    /// Bus error, may flood the socket
+    BusError,
+    /// The controller restarted after bus-off
+    Restarted,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ControllerProblem {
+    RxOverflow,
+    TxOverflow,
+    /// Reached the warning level for RX errors
+    RxWarning,
+    /// Reached the warning level for TX errors
+    TxWarning,
+    /// Reached the error passive status for RX
+    RxPassive,
+    /// Reached the error passive status for TX
+    TxPassive,
+    /// Recovered to the error active state
+    Active,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ProtocolViolation {
+    /// Single bit error
+    Bit,
+    /// Frame format error
+    Form,
+    /// Bit stuffing error
+    Stuff,
+    /// Unable to send a dominant bit
+    Bit0,
+    /// Unable to send a recessive bit
+    Bit1,
+    /// Bus overload
+    Overload,
+    /// Active error announcement
+    Active,
+    /// Error occurred on transmission
+    Tx,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ProtocolLocation {
+    Unspecified,
+    StartOfFrame,
+    /// ID bits 28 - 21 (SFF: 10 - 3)
+    Id28To21,
+    /// ID bits 20 - 18 (SFF: 2 - 0)
+    Id20To18,
+    /// Substitute RTR (SFF: RTR)
+    Srtr,
+    /// Identifier extension
+    Ide,
+    /// ID bits 17 - 13
+    Id17To13,
+    /// ID bits 12 - 5
+    Id12To5,
+    /// ID bits 4 - 0
+    Id4To0,
+    Rtr,
+    /// Reserved bit 1
+    Res1,
+    /// Reserved bit 0
+    Res0,
+    /// Data length code
+    Dlc,
+    Data,
+    CrcSequence,
+    CrcDelimiter,
+    AckSlot,
+    AckDelimiter,
+    EndOfFrame,
+    /// Intermission
+    Intermission,
+    Unknown(u8),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TransceiverError {
+    Unspecified,
+    CanHNoWire,
+    CanHShortToBattery,
+    CanHShortToVcc,
+    CanHShortToGround,
+    CanLNoWire,
+    CanLShortToBattery,
+    CanLShortToVcc,
+    CanLShortToGround,
+    CanLShortToCanH,
+    Unknown(u8),
+}
+
+/// Error state of a CAN controller, from least to most severe
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ControllerState {
+    ErrorActive,
+    ErrorWarning,
+    ErrorPassive,
+    BusOff,
+}
+
+impl ErrorFrame {
+    /// Decode an error frame from its CAN ID, with or without [`CAN_ERR_FLAG`], and its data
+    pub fn decode(id: u32, data: &[u8; 8]) -> Self {
+        let class = id & CAN_ERR_MASK;
+        let mut errors = Vec::new();
+        if class & CAN_ERR_TX_TIMEOUT != 0 {
+            errors.push(CanError::TransmitTimeout);
+        }
+        if class & CAN_ERR_LOSTARB != 0 {
+            let bit = (data[0] != 0).then_some(data[0]);
+            errors.push(CanError::LostArbitration { bit });
+        }
+        if class & CAN_ERR_CRTL != 0 {
+            errors.push(CanError::Controller(decode_flags(
+                data[1],
+                &[
+                    ControllerProblem::RxOverflow,
+                    ControllerProblem::TxOverflow,
+                    ControllerProblem::RxWarning,
+                    ControllerProblem::TxWarning,
+                    ControllerProblem::RxPassive,
+                    ControllerProblem::TxPassive,
+                    ControllerProblem::Active,
+                ],
+            )));
+        }
+        if class & CAN_ERR_PROT != 0 {
+            errors.push(CanError::Protocol {
+                violations: decode_flags(
+                    data[2],
+                    &[
+                        ProtocolViolation::Bit,
+                        ProtocolViolation::Form,
+                        ProtocolViolation::Stuff,
+                        ProtocolViolation::Bit0,
+                        ProtocolViolation::Bit1,
+                        ProtocolViolation::Overload,
+                        ProtocolViolation::Active,
+                        ProtocolViolation::Tx,
+                    ],
+                ),
+                location: ProtocolLocation::from(data[3]),
+            });
+        }
+        if class & CAN_ERR_TRX != 0 {
+            errors.push(CanError::Transceiver(TransceiverError::from(data[4])));
+        }
+        if class & CAN_ERR_ACK != 0 {
+            errors.push(CanError::NoAck);
+        }
+        if class & CAN_ERR_BUSOFF != 0 {
+            errors.push(CanError::BusOff);
+        }
+        if class & CAN_ERR_BUSERROR != 0 {
+            errors.push(CanError::BusError);
+        }
+        if class & CAN_ERR_RESTARTED != 0 {
+            errors.push(CanError::Restarted);
+        }
+        let counters = (class & CAN_ERR_CNT != 0).then_some(ErrorCounters {
+            tx: data[6],
+            rx: data[7],
+        });
+        Self { errors, counters }
+    }
+
+    /// Error state the controller reported with this frame, if any
+    pub fn controller_state(&self) -> Option<ControllerState> {
+        self.errors
+            .iter()
+            .filter_map(|error| match error {
+                CanError::BusOff => Some(ControllerState::BusOff),
+                CanError::Restarted => Some(ControllerState::ErrorActive),
+                CanError::Controller(problems) => {
+                    problems.iter().filter_map(|p| p.state()).max()
+                }
+                _ => None,
+            })
+            .max()
+    }
+}
+
+impl ControllerProblem {
+    fn state(self) -> Option<ControllerState> {
+        match self {
+            ControllerProblem::RxOverflow | ControllerProblem::TxOverflow => None,
+            ControllerProblem::RxWarning | ControllerProblem::TxWarning => {
+                Some(ControllerState::ErrorWarning)
+            }
+            ControllerProblem::RxPassive | ControllerProblem::TxPassive => {
+                Some(ControllerState::ErrorPassive)
+            }
+            ControllerProblem::Active => Some(ControllerState::ErrorActive),
+        }
+    }
+}
+
+impl From<u8> for ProtocolLocation {
+    fn from(location: u8) -> Self {
+        match location {
+            0x00 => Self::Unspecified,
+            0x03 => Self::StartOfFrame,
+            0x02 => Self::Id28To21,
+            0x06 => Self::Id20To18,
+            0x04 => Self::Srtr,
+            0x05 => Self::Ide,
+            0x07 => Self::Id17To13,
+            0x0F => Self::Id12To5,
+            0x0E => Self::Id4To0,
+            0x0C => Self::Rtr,
+            0x0D => Self::Res1,
+            0x09 => Self::Res0,
+            0x0B => Self::Dlc,
+            0x0A => Self::Data,
+            0x08 => Self::CrcSequence,
+            0x18 => Self::CrcDelimiter,
+            0x19 => Self::AckSlot,
+            0x1B => Self::AckDelimiter,
+            0x1A => Self::EndOfFrame,
+            0x12 => Self::Intermission,
+            other => Self::Unknown(other),
+        }
+    }
+}
+
+impl From<u8> for TransceiverError {
+    fn from(status: u8) -> Self {
+        match status {
+            0x00 => Self::Unspecified,
+            0x04 => Self::CanHNoWire,
+            0x05 => Self::CanHShortToBattery,
+            0x06 => Self::CanHShortToVcc,
+            0x07 => Self::CanHShortToGround,
+            0x40 => Self::CanLNoWire,
+            0x50 => Self::CanLShortToBattery,
+            0x60 => Self::CanLShortToVcc,
+            0x70 => Self::CanLShortToGround,
+            0x80 => Self::CanLShortToCanH,
+            other => Self::Unknown(other),
+        }
+    }
+}
+
+/// Variants of the set bits of `byte`, `variants[n]` for bit `n`
+fn decode_flags<T: Copy>(byte: u8, variants: &[T]) -> Vec<T> {
+    variants
+        .iter()
+        .enumerate()
+        .filter(|(bit, _)| byte & (1 << bit) != 0)
+        .map(|(_, variant)| *variant)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_bus_off() {
+        let frame = ErrorFrame::decode(CAN_ERR_FLAG | CAN_ERR_BUSOFF, &[0; 8]);
+        assert_eq!(frame.errors, [CanError::BusOff]);
+        assert_eq!(frame.counters, None);
+        assert_eq!(frame.controller_state(), Some(ControllerState::BusOff));
+    }
+
+    #[test]
+    fn decode_controller_state_with_counters() {
+        let frame = ErrorFrame::decode(
+            CAN_ERR_FLAG | CAN_ERR_CRTL | CAN_ERR_CNT,
+            &[0, 0x04 | 0x20, 0, 0, 0, 0, 128, 96],
+        );
+        assert_eq!(
+            frame.errors,
+            [CanError::Controller(vec![
+                ControllerProblem::RxWarning,
+                ControllerProblem::TxPassive
+            ])]
+        );
+        assert_eq!(frame.counters, Some(ErrorCounters { tx: 128, rx: 96 }));
+        assert_eq!(
+            frame.controller_state(),
+            Some(ControllerState::ErrorPassive)
+        );
+    }
+
+    #[test]
+    fn decode_protocol_violation_and_transceiver() {
+        let frame = ErrorFrame::decode(
+            CAN_ERR_PROT | CAN_ERR_TRX | CAN_ERR_BUSERROR | CAN_ERR_LOSTARB,
+            &[5, 0, 0x04 | 0x80, 0x19, 0x70, 0, 0, 0],
+        );
+        assert_eq!(
+            frame.errors,
+            [
+                CanError::LostArbitration { bit: Some(5) },
+                CanError::Protocol {
+                    violations: vec![ProtocolViolation::Stuff, ProtocolViolation::Tx],
+                    location: ProtocolLocation::AckSlot,
+                },
+                CanError::Transceiver(TransceiverError::CanLShortToGround),
+                CanError::BusError,
+            ]
+        );
+        assert_eq!(frame.controller_state(), None);
+    }
+}
@@ -0,0 +1,115 @@
+use std::{
+    io::{self, Read, Write},
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use tokio::io::{unix::AsyncFd, AsyncRead, AsyncWrite, ReadBuf};
+
+use super::stream::IsotpStream;
+use crate::Error;
+
+/// An asynchronous [`IsotpStream`] driven by the tokio reactor
+///
+/// Implements [`AsyncRead`] and [`AsyncWrite`], so that it works with the extension traits in
+/// `tokio::io` instead of blocking a thread in [`Read::read`].
+///
+/// As with [`IsotpStream`], every read returns a single ISO-TP message and every write sends a
+/// single message. If the buffer of a read is too small, the rest of the message is discarded.
+///
+/// The underlying socket is switched to nonblocking mode.
+///
+/// # Examples
+///
+/// ```no_run
+/// use can_rs::{
+///     isotp::{addr::CanIsotpAddr, async_stream::AsyncIsotpStream, stream::IsotpStream},
+///     Id, CAN_DATA_LEN,
+/// };
+/// use tokio::io::{AsyncReadExt, AsyncWriteExt};
+///
+/// # async fn run() -> Result<(), can_rs::Error> {
+/// let addr = CanIsotpAddr::new("can0", Id::Standard(0x321), Id::Standard(0x123))?;
+/// let stream = IsotpStream::<CAN_DATA_LEN>::build().bind(addr)?;
+/// let mut stream = AsyncIsotpStream::new(stream)?;
+///
+/// stream.write_all(&[0x01, 0x02, 0x03]).await?;
+/// let mut buf = [0u8; 4095];
+/// let len = stream.read(&mut buf).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct AsyncIsotpStream<const N: usize> {
+    inner: AsyncFd<IsotpStream<N>>,
+}
+
+impl<const N: usize> AsyncIsotpStream<N> {
+    /// Register `stream` with the tokio reactor
+    ///
+    /// Must be called from within a tokio runtime.
+    pub fn new(stream: IsotpStream<N>) -> Result<Self, Error> {
+        crate::socket::set_nonblocking(&stream, true)?;
+        Ok(Self {
+            inner: AsyncFd::new(stream)?,
+        })
+    }
+
+    pub fn get_ref(&self) -> &IsotpStream<N> {
+        self.inner.get_ref()
+    }
+
+    /// Deregister the stream from the tokio reactor. The socket stays in nonblocking mode.
+    pub fn into_inner(self) -> IsotpStream<N> {
+        self.inner.into_inner()
+    }
+}
+
+impl<const N: usize> AsyncRead for AsyncIsotpStream<N> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            let mut guard = ready!(this.inner.poll_read_ready_mut(cx))?;
+            let unfilled = buf.initialize_unfilled();
+            match guard.try_io(|stream| stream.get_mut().read(unfilled)) {
+                Ok(Ok(len)) => {
+                    buf.advance(len);
+                    return Poll::Ready(Ok(()));
+                }
+                Ok(Err(err)) => return Poll::Ready(Err(err)),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+}
+
+impl<const N: usize> AsyncWrite for AsyncIsotpStream<N> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        loop {
+            let mut guard = ready!(this.inner.poll_write_ready_mut(cx))?;
+            match guard.try_io(|stream| stream.get_mut().write(buf)) {
+                Ok(result) => return Poll::Ready(result),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
@@ -4,6 +4,9 @@ pub mod linklayer;
 pub mod socket_isotp;
 pub mod stream;
 
+#[cfg(feature = "tokio")]
+pub mod async_stream;
+
 /// Defined in the Kernel as SOL_CAN_BASE + CAN_ISOTP, which comes out to 106
 pub const SOL_CAN_ISOTP: libc::c_int = 106;
 pub const CAN_ISOTP_OPTS: libc::c_int = 1;
@@ -0,0 +1,192 @@
+use std::{ffi::CStr, io, os::unix::prelude::RawFd};
+
+use super::{J1939_NO_ADDR, J1939_NO_NAME, J1939_NO_PGN};
+use crate::{
+    addr::{try_string_to_ifindex, AF_CAN},
+    Error,
+};
+
+/// A J1939 socket address: an interface together with a NAME, a PGN and an address
+///
+/// When binding, these are the local NAME and address, and the PGN to receive (or
+/// [`J1939_NO_PGN`] for all). When sending, they are the destination and the PGN of the message.
+#[derive(Debug, Clone)]
+pub struct CanJ1939Addr {
+    pub name: String,
+    pub(crate) inner: RawJ1939Addr,
+}
+
+impl CanJ1939Addr {
+    pub fn new(name: &str, j1939_name: u64, pgn: u32, addr: u8) -> Result<Self, Error> {
+        let if_index = try_string_to_ifindex(name)?;
+        Ok(CanJ1939Addr {
+            name: String::from(name),
+            inner: RawJ1939Addr {
+                family: AF_CAN,
+                ifindex: if_index as libc::c_int,
+                name: j1939_name,
+                pgn,
+                addr,
+            },
+        })
+    }
+
+    /// Address `addr` on interface `name`, without a NAME or a PGN
+    pub fn with_address(name: &str, addr: u8) -> Result<Self, Error> {
+        Self::new(name, J1939_NO_NAME, J1939_NO_PGN, addr)
+    }
+
+    /// Another node on the same interface, e.g. the destination of a message with the PGN `pgn`
+    pub fn peer(&self, pgn: u32, addr: u8) -> Self {
+        Self {
+            name: self.name.clone(),
+            inner: RawJ1939Addr {
+                name: J1939_NO_NAME,
+                pgn,
+                addr,
+                ..self.inner
+            },
+        }
+    }
+
+    /// All nodes on the same interface, for broadcasting a message with the PGN `pgn`
+    pub fn broadcast(&self, pgn: u32) -> Self {
+        self.peer(pgn, J1939_NO_ADDR)
+    }
+
+    /// The 64 bit J1939 NAME
+    pub fn j1939_name(&self) -> u64 {
+        self.inner.name
+    }
+
+    pub fn pgn(&self) -> u32 {
+        self.inner.pgn
+    }
+
+    pub fn addr(&self) -> u8 {
+        self.inner.addr
+    }
+}
+
+impl AsMut<RawJ1939Addr> for CanJ1939Addr {
+    fn as_mut(&mut self) -> &mut RawJ1939Addr {
+        &mut self.inner
+    }
+}
+
+impl AsRef<RawJ1939Addr> for CanJ1939Addr {
+    fn as_ref(&self) -> &RawJ1939Addr {
+        &self.inner
+    }
+}
+
+impl TryFrom<RawFd> for CanJ1939Addr {
+    type Error = Error;
+
+    fn try_from(fd: RawFd) -> Result<Self, Error> {
+        let inner = RawJ1939Addr::try_from(fd)?;
+        let mut buffer: Vec<libc::c_char> = Vec::with_capacity(libc::IF_NAMESIZE);
+        let buffer_ptr = buffer.as_mut_ptr();
+        let ret =
+            unsafe { libc::if_indextoname(inner.ifindex as libc::c_uint, buffer_ptr) };
+        if ret.is_null() {
+            return Err(Error::CanAddrIfindexToName {
+                index: inner.ifindex as u32,
+                source: io::Error::last_os_error(),
+            });
+        }
+        let result = unsafe { CStr::from_ptr(buffer_ptr) }
+            .to_str()
+            .map_err(|err| Error::ParseIndexToName {
+                index: inner.ifindex as u32,
+                source: err,
+            })?;
+        Ok(CanJ1939Addr {
+            name: String::from(result),
+            inner,
+        })
+    }
+}
+
+// struct sockaddr_can {
+//     __kernel_sa_family_t can_family;
+//     int         can_ifindex;
+//     union {
+//         /* transport protocol class address information (e.g. ISOTP) */
+//         struct { canid_t rx_id, tx_id; } tp;
+//
+//         /* J1939 address information */
+//         struct {
+//             /* 8 byte name when using dynamic addressing */
+//             __u64 name;
+//
+//             /* pgn:
+//              * 8 bit: PS in PDU2 case, else 0
+//              * 8 bit: PF
+//              * 1 bit: DP
+//              * 1 bit: reserved
+//              */
+//             __u32 pgn;
+//
+//             /* 1 byte address */
+//             __u8 addr;
+//         } j1939;
+//     } can_addr;
+// };
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[repr(C)]
+pub struct RawJ1939Addr {
+    pub(crate) family: u16,
+    pub(crate) ifindex: i32,
+    pub(crate) name: u64,
+    pub(crate) pgn: u32,
+    pub(crate) addr: u8,
+}
+
+impl RawJ1939Addr {
+    pub(crate) fn empty() -> Self {
+        Self {
+            family: AF_CAN,
+            ifindex: 0,
+            name: J1939_NO_NAME,
+            pgn: J1939_NO_PGN,
+            addr: J1939_NO_ADDR,
+        }
+    }
+}
+
+impl TryFrom<RawFd> for RawJ1939Addr {
+    type Error = Error;
+
+    fn try_from(fd: RawFd) -> Result<Self, Error> {
+        let mut inst = Self::empty();
+        let mut len = std::mem::size_of::<RawJ1939Addr>() as libc::socklen_t;
+        let ret = unsafe {
+            libc::getsockname(
+                fd,
+                (&mut inst as *mut RawJ1939Addr) as *mut libc::sockaddr,
+                &mut len,
+            )
+        };
+        if ret < 0 {
+            return Err(Error::Syscall {
+                syscall: "getsockname(2)".to_string(),
+                context: Some("getting bound J1939 sockaddr from fd".to_string()),
+                source: io::Error::last_os_error(),
+            });
+        }
+        Ok(inst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(target_pointer_width = "64")]
+    #[test]
+    fn raw_addr_matches_sockaddr_can() {
+        assert_eq!(std::mem::size_of::<RawJ1939Addr>(), 24);
+        assert_eq!(std::mem::align_of::<RawJ1939Addr>(), 8);
+    }
+}
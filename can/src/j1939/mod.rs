@@ -0,0 +1,39 @@
+//! SocketCAN J1939 (SAE J1939) sockets
+//!
+//! The kernel takes care of the transport protocols: payloads of more than 8 bytes are sent and
+//! received as multi-packet messages (TP up to 1785 bytes, ETP beyond), so every `send_to` /
+//! `recv_from` on a [`stream::J1939Stream`] handles a complete message.
+//!
+//! See <https://www.kernel.org/doc/html/latest/networking/j1939.html>
+
+pub mod addr;
+pub mod stream;
+
+/// Defined in the Kernel as SOL_CAN_BASE + CAN_J1939, which comes out to 107
+pub const SOL_CAN_J1939: libc::c_int = 107;
+pub const SO_J1939_FILTER: libc::c_int = 1;
+pub const SO_J1939_PROMISC: libc::c_int = 2;
+pub const SO_J1939_SEND_PRIO: libc::c_int = 3;
+pub const SO_J1939_ERRQUEUE: libc::c_int = 4;
+
+/// No NAME, for sockets that only use addresses
+pub const J1939_NO_NAME: u64 = 0;
+/// No PGN, for sockets that don't filter or fix the PGN
+pub const J1939_NO_PGN: u32 = 0x40000;
+/// The global (broadcast) address, or no address when binding
+pub const J1939_NO_ADDR: u8 = 0xFF;
+/// The address of a node that couldn't claim one
+pub const J1939_IDLE_ADDR: u8 = 0xFE;
+/// The highest address that can be claimed
+pub const J1939_MAX_UNICAST_ADDR: u8 = 0xFD;
+
+pub const J1939_PGN_REQUEST: u32 = 0x0EA00;
+pub const J1939_PGN_ADDRESS_CLAIMED: u32 = 0x0EE00;
+pub const J1939_PGN_ADDRESS_COMMANDED: u32 = 0x0FED8;
+pub const J1939_PGN_PDU1_MAX: u32 = 0x3FF00;
+pub const J1939_PGN_MAX: u32 = 0x3FFFF;
+
+/// Largest payload of a single message, using the extended transport protocol
+pub const J1939_MAX_ETP_PACKET_SIZE: usize = 7 * 0x00FF_FFFF;
+/// Largest payload of a single message, using the transport protocol
+pub const J1939_MAX_TP_PACKET_SIZE: usize = 7 * 255;
@@ -0,0 +1,339 @@
+use std::{
+    io,
+    os::{
+        fd::OwnedFd,
+        unix::prelude::{AsRawFd, IntoRawFd, RawFd},
+    },
+    time::{Duration, Instant},
+};
+
+use super::{
+    addr::CanJ1939Addr, J1939_NO_ADDR, J1939_NO_NAME, J1939_PGN_ADDRESS_CLAIMED,
+};
+use crate::{socket, Error};
+
+/// Time to wait for contending address claims, as specified by J1939-81
+pub const ADDRESS_CLAIM_TIMEOUT: Duration = Duration::from_millis(250);
+
+/// A J1939 socket, bound to a local NAME and/or address
+///
+/// The underlying SocketCAN socket and file descriptor will be closed and cleaned when the value
+/// is dropped.
+///
+/// # Examples
+///
+/// ```no_run
+/// use can_rs::j1939::{addr::CanJ1939Addr, stream::J1939Stream, J1939_NO_PGN};
+///
+/// # fn main() -> Result<(), can_rs::Error> {
+/// let local = CanJ1939Addr::new("can0", 0x1234_5678_9ABC_DEF0, J1939_NO_PGN, 0x80)?;
+/// let stream = J1939Stream::build().broadcast(true).bind(local.clone())?;
+/// stream.claim_address()?;
+///
+/// // Payloads of more than 8 bytes are sent with the transport protocol
+/// stream.send_to(&[0u8; 100], &local.peer(0x0EF00, 0x20))?;
+///
+/// let mut buf = [0u8; 1785];
+/// let mut src = local.clone();
+/// let len = stream.recv_from(&mut buf, &mut src)?;
+/// println!("{len} bytes of PGN {:#x} from {:#x}", src.pgn(), src.addr());
+/// # Ok(())
+/// # }
+/// ```
+pub struct J1939Stream {
+    pub(crate) fd: OwnedFd,
+    pub(crate) addr: CanJ1939Addr,
+}
+
+pub struct J1939StreamBuilder {
+    pub(crate) nonblocking: bool,
+    pub(crate) broadcast: bool,
+    pub(crate) promiscuous: bool,
+    pub(crate) send_priority: Option<u8>,
+}
+
+impl J1939StreamBuilder {
+    pub fn new() -> Self {
+        Self {
+            nonblocking: false,
+            broadcast: false,
+            promiscuous: false,
+            send_priority: None,
+        }
+    }
+
+    pub fn nonblocking(&mut self, nonblocking: bool) -> &mut Self {
+        self.nonblocking = nonblocking;
+        self
+    }
+
+    /// Allow sending to and receiving from the global address
+    pub fn broadcast(&mut self, broadcast: bool) -> &mut Self {
+        self.broadcast = broadcast;
+        self
+    }
+
+    /// Receive all messages on the bus, not just those addressed to this socket
+    pub fn promiscuous(&mut self, promiscuous: bool) -> &mut Self {
+        self.promiscuous = promiscuous;
+        self
+    }
+
+    /// Priority of sent messages, from 0 (highest) to 7 (lowest). The kernel defaults to 6.
+    pub fn send_priority(&mut self, priority: u8) -> &mut Self {
+        self.send_priority = Some(priority);
+        self
+    }
+
+    pub fn bind(&self, addr: CanJ1939Addr) -> Result<J1939Stream, Error> {
+        imp::bind(addr, self)
+    }
+}
+
+impl Default for J1939StreamBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl J1939Stream {
+    pub fn build() -> J1939StreamBuilder {
+        J1939StreamBuilder::new()
+    }
+
+    pub fn new(addr: CanJ1939Addr) -> Result<Self, Error> {
+        J1939StreamBuilder::new().bind(addr)
+    }
+
+    /// Address this stream is bound to
+    pub fn addr(&self) -> &CanJ1939Addr {
+        &self.addr
+    }
+
+    /// Send `data` to `dest_addr`, using the PGN of `dest_addr`
+    pub fn send_to(&self, data: &[u8], dest_addr: &CanJ1939Addr) -> io::Result<usize> {
+        let ret = unsafe {
+            libc::sendto(
+                self.as_raw_fd(),
+                data.as_ptr() as *const libc::c_void,
+                data.len(),
+                0,
+                (dest_addr.as_ref() as *const imp::RawJ1939Addr)
+                    as *const libc::sockaddr,
+                std::mem::size_of::<imp::RawJ1939Addr>() as libc::socklen_t,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(ret as usize)
+    }
+
+    /// Receive a complete message into `buf`, and its source NAME, address and PGN into
+    /// `src_addr`. Bytes that don't fit into `buf` are discarded.
+    pub fn recv_from(
+        &self,
+        buf: &mut [u8],
+        src_addr: &mut CanJ1939Addr,
+    ) -> io::Result<usize> {
+        let mut len = std::mem::size_of::<imp::RawJ1939Addr>() as libc::socklen_t;
+        let ret = unsafe {
+            libc::recvfrom(
+                self.as_raw_fd(),
+                buf.as_mut_ptr() as *mut libc::c_void,
+                buf.len(),
+                0,
+                (src_addr.as_mut() as *mut imp::RawJ1939Addr) as *mut libc::sockaddr,
+                &mut len,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(ret as usize)
+    }
+
+    /// Claim the address this stream is bound to for its NAME
+    ///
+    /// Broadcasts an Address Claimed message and waits [`ADDRESS_CLAIM_TIMEOUT`] for contending
+    /// claims. A contender with a lower NAME wins the address, in which case
+    /// [`Error::J1939AddressClaimLost`] is returned. Against a contender with a higher NAME, the
+    /// claim is repeated and the wait starts over.
+    ///
+    /// The claim is sent to the global address, so the stream has to be built with
+    /// [`J1939StreamBuilder::broadcast`].
+    pub fn claim_address(&self) -> Result<(), Error> {
+        let name = self.addr.j1939_name();
+        let addr = self.addr.addr();
+        let claims = J1939StreamBuilder::new()
+            .broadcast(true)
+            .promiscuous(true)
+            .bind(CanJ1939Addr {
+                name: self.addr.name.clone(),
+                inner: imp::RawJ1939Addr {
+                    name: J1939_NO_NAME,
+                    pgn: J1939_PGN_ADDRESS_CLAIMED,
+                    addr: J1939_NO_ADDR,
+                    ..self.addr.inner
+                },
+            })?;
+
+        let claim = self.addr.broadcast(J1939_PGN_ADDRESS_CLAIMED);
+        self.send_to(&name.to_le_bytes(), &claim)?;
+        let mut deadline = Instant::now() + ADDRESS_CLAIM_TIMEOUT;
+        let mut buf = [0u8; 8];
+        let mut src = claims.addr.clone();
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() || !socket::poll_readable(&claims, remaining)? {
+                return Ok(());
+            }
+            let len = claims.recv_from(&mut buf, &mut src)?;
+            if len != buf.len() || src.addr() != addr {
+                continue;
+            }
+            match imp::contend(name, u64::from_le_bytes(buf)) {
+                imp::Contention::Own => {}
+                imp::Contention::Lost => {
+                    return Err(Error::J1939AddressClaimLost { addr, name });
+                }
+                imp::Contention::Won => {
+                    // Give other contenders the full timeout to answer the repeated claim
+                    self.send_to(&name.to_le_bytes(), &claim)?;
+                    deadline = Instant::now() + ADDRESS_CLAIM_TIMEOUT;
+                }
+            }
+        }
+    }
+
+    pub fn try_clone(&self) -> Result<Self, Error> {
+        Ok(Self {
+            fd: self
+                .fd
+                .try_clone()
+                .map_err(|e| crate::Error::CanStreamClone { source: e })?,
+            addr: self.addr.clone(),
+        })
+    }
+}
+
+impl AsRawFd for J1939Stream {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+impl IntoRawFd for J1939Stream {
+    fn into_raw_fd(self) -> RawFd {
+        self.fd.into_raw_fd()
+    }
+}
+
+mod imp {
+    use std::{io, os::unix::prelude::AsRawFd};
+
+    use super::{J1939Stream, J1939StreamBuilder};
+    pub(super) use crate::j1939::addr::RawJ1939Addr;
+    use crate::{
+        j1939::{
+            addr::CanJ1939Addr, SOL_CAN_J1939, SO_J1939_PROMISC, SO_J1939_SEND_PRIO,
+        },
+        socket, Error, Protocol, Type,
+    };
+
+    pub(super) fn bind(
+        addr: CanJ1939Addr,
+        options: &J1939StreamBuilder,
+    ) -> Result<J1939Stream, Error> {
+        let stream = J1939Stream {
+            fd: socket::new(Type::DGRAM, Protocol::J1939)?,
+            addr,
+        };
+
+        socket::set_nonblocking(&stream, options.nonblocking)?;
+        socket::set_broadcast(&stream, options.broadcast)?;
+        if options.promiscuous {
+            set_j1939_opt(&stream, SO_J1939_PROMISC, 1, "SO_J1939_PROMISC")?;
+        }
+        if let Some(priority) = options.send_priority {
+            set_j1939_opt(
+                &stream,
+                SO_J1939_SEND_PRIO,
+                priority as libc::c_int,
+                "SO_J1939_SEND_PRIO",
+            )?;
+        }
+
+        let ret = unsafe {
+            libc::bind(
+                stream.as_raw_fd(),
+                (stream.addr.as_ref() as *const RawJ1939Addr) as *const libc::sockaddr,
+                std::mem::size_of::<RawJ1939Addr>() as libc::socklen_t,
+            )
+        };
+        if ret < 0 {
+            return Err(Error::Syscall {
+                syscall: "bind(2)".to_string(),
+                context: Some(format!("binding J1939 socket to {:?}", stream.addr)),
+                source: io::Error::last_os_error(),
+            });
+        }
+        Ok(stream)
+    }
+
+    fn set_j1939_opt<T: AsRawFd>(
+        fd: &T,
+        opt: libc::c_int,
+        value: libc::c_int,
+        opt_name: &str,
+    ) -> Result<(), Error> {
+        let ret = unsafe {
+            libc::setsockopt(
+                fd.as_raw_fd(),
+                SOL_CAN_J1939,
+                opt,
+                (&value as *const libc::c_int) as *const libc::c_void,
+                std::mem::size_of::<libc::c_int>() as u32,
+            )
+        };
+        if ret < 0 {
+            return Err(Error::Syscall {
+                syscall: "setsockopt(2)".to_string(),
+                context: Some(format!("setting {opt_name} ({value})")),
+                source: io::Error::last_os_error(),
+            });
+        }
+        Ok(())
+    }
+
+    #[derive(Debug, PartialEq, Eq)]
+    pub(super) enum Contention {
+        /// Our own claim, looped back
+        Own,
+        /// The contender has priority
+        Lost,
+        /// We have priority
+        Won,
+    }
+
+    /// Arbitrate a claim for the same address, the lower NAME wins
+    pub(super) fn contend(own: u64, other: u64) -> Contention {
+        match other.cmp(&own) {
+            std::cmp::Ordering::Equal => Contention::Own,
+            std::cmp::Ordering::Less => Contention::Lost,
+            std::cmp::Ordering::Greater => Contention::Won,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::imp::{contend, Contention};
+
+    #[test]
+    fn lower_name_wins_address_claim() {
+        assert_eq!(Contention::Own, contend(0x1234, 0x1234));
+        assert_eq!(Contention::Lost, contend(0x1234, 0x0FFF));
+        assert_eq!(Contention::Won, contend(0x1234, 0xA000_0000_0000_0000));
+    }
+}
@@ -1,12 +1,23 @@
 pub mod addr;
+#[cfg(feature = "bcm")]
+pub mod bcm;
+pub mod capture;
+pub mod error_frame;
 pub mod filter;
 pub mod frame;
 mod socket;
 pub mod stream;
+pub mod timestamp;
 
 #[cfg(feature = "isotp")]
 pub mod isotp;
 
+#[cfg(feature = "j1939")]
+pub mod j1939;
+
+#[cfg(feature = "tokio")]
+pub mod async_stream;
+
 use std::{
     ffi::{CString, OsStr},
     io::{self, Read},
@@ -139,6 +150,21 @@ pub enum Error {
     #[error("invalid frame data length: `{0}`")]
     InvalidDataLength(usize),
 
+    #[error("too many frames for a single BCM message: `{0}`")]
+    BcmTooManyFrames(usize),
+
+    #[error("unexpected BCM opcode: `{0}`")]
+    InvalidBcmOpcode(u32),
+
+    #[error("lost J1939 address claim for `{addr:#04x}` with NAME `{name:#018x}`")]
+    J1939AddressClaimLost { addr: u8, name: u64 },
+
+    #[error("invalid log line {line}: {reason}")]
+    InvalidLogLine { line: usize, reason: String },
+
+    #[error("invalid BLF log: {reason}")]
+    InvalidBlf { reason: String },
+
     #[error("syscall `{syscall}` failed: `{context:#?}`")]
     Syscall {
         syscall: String,
@@ -108,6 +108,73 @@ pub(crate) fn set_nonblocking<T: AsRawFd>(
     Ok(())
 }
 
+/// Subscribe to the error frames of the error classes in `mask`
+pub(crate) fn set_error_filter<T: AsRawFd>(fd: &T, mask: u32) -> Result<(), Error> {
+    let ret = unsafe {
+        libc::setsockopt(
+            fd.as_raw_fd(),
+            libc::SOL_CAN_RAW,
+            libc::CAN_RAW_ERR_FILTER,
+            (&mask as *const u32) as *const libc::c_void,
+            std::mem::size_of::<u32>() as u32,
+        )
+    };
+    if ret < 0 {
+        return Err(Error::Syscall {
+            syscall: "setsockopt(2)".to_string(),
+            context: Some(format!("setting CAN_RAW_ERR_FILTER ({mask:#x})")),
+            source: io::Error::last_os_error(),
+        });
+    }
+    Ok(())
+}
+
+/// Allow sending to and receiving from broadcast addresses, as required by J1939 sockets
+#[cfg(feature = "j1939")]
+pub(crate) fn set_broadcast<T: AsRawFd>(fd: &T, broadcast: bool) -> Result<(), Error> {
+    let value = libc::c_int::from(broadcast);
+    let ret = unsafe {
+        libc::setsockopt(
+            fd.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_BROADCAST,
+            (&value as *const libc::c_int) as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as u32,
+        )
+    };
+    if ret < 0 {
+        return Err(Error::Syscall {
+            syscall: "setsockopt(2)".to_string(),
+            context: Some(format!("setting SO_BROADCAST ({broadcast})")),
+            source: io::Error::last_os_error(),
+        });
+    }
+    Ok(())
+}
+
+/// Wait up to `timeout` for the socket to become readable, returns `false` on timeout
+#[cfg(feature = "j1939")]
+pub(crate) fn poll_readable<T: AsRawFd>(
+    fd: &T,
+    timeout: std::time::Duration,
+) -> Result<bool, Error> {
+    let mut pollfd = libc::pollfd {
+        fd: fd.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    let timeout = timeout.as_millis().try_into().unwrap_or(libc::c_int::MAX);
+    let ret = unsafe { libc::poll(&mut pollfd, 1, timeout) };
+    if ret < 0 {
+        return Err(Error::Syscall {
+            syscall: "poll(2)".to_string(),
+            context: Some("waiting for socket to become readable".to_string()),
+            source: io::Error::last_os_error(),
+        });
+    }
+    Ok(ret > 0)
+}
+
 pub(crate) fn mtu_from_addr<T: AsRawFd, R: AsRef<RawCanAddr>>(
     fd: &T,
     addr: R,
@@ -170,6 +237,29 @@ pub(crate) fn bind<T: AsRawFd, R: AsRef<RawCanAddr>>(
     Ok(())
 }
 
+/// Connects a CAN socket to the given address, as required by BCM sockets instead of binding
+#[cfg(feature = "bcm")]
+pub(crate) fn connect<T: AsRawFd, R: AsRef<RawCanAddr>>(
+    fd: &T,
+    addr: R,
+) -> Result<(), Error> {
+    let ret = unsafe {
+        libc::connect(
+            fd.as_raw_fd(),
+            (addr.as_ref() as *const RawCanAddr) as *const libc::sockaddr,
+            std::mem::size_of::<RawCanAddr>() as libc::c_uint,
+        )
+    };
+    if ret < 0 {
+        return Err(Error::Syscall {
+            syscall: "connect(2)".to_string(),
+            context: None,
+            source: io::Error::last_os_error(),
+        });
+    }
+    Ok(())
+}
+
 /// Filters messages such that the socket only receives frames whose SFF/EFF ID
 /// bitwise AND a CAN filter mask matches the bitwise AND of that same CAN filter's
 /// mask.
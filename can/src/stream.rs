@@ -1,7 +1,12 @@
 use std::{io, os::fd::OwnedFd};
 
 use self::imp::{Empty, RawFrame, SetMut};
-use crate::{filter::Filter, *};
+use crate::{
+    error_frame::ErrorFrame,
+    filter::Filter,
+    timestamp::{Timestamping, Timestamps},
+    *,
+};
 
 /// A raw classical and flexible data-rate (FD) compatible CAN frame stream
 ///
@@ -56,6 +61,15 @@ pub struct FrameStream<const N: usize> {
 pub struct FrameStreamBuilder<const N: usize> {
     pub(crate) nonblocking: bool,
     pub(crate) filters: Vec<Filter>,
+    pub(crate) error_mask: u32,
+    pub(crate) timestamping: Option<Timestamping>,
+}
+
+/// A frame or an error frame, as received by [`FrameStream::recv_timestamped`]
+#[derive(Clone, Debug, PartialEq)]
+pub enum Received<const N: usize> {
+    Frame(Frame<N>),
+    Error(ErrorFrame),
 }
 
 impl<const N: usize> FrameStreamBuilder<N> {
@@ -63,6 +77,8 @@ impl<const N: usize> FrameStreamBuilder<N> {
         Self {
             nonblocking: false,
             filters: vec![],
+            error_mask: 0,
+            timestamping: None,
         }
     }
 
@@ -75,6 +91,19 @@ impl<const N: usize> FrameStreamBuilder<N> {
         self.filters = filters;
         self
     }
+
+    /// Subscribe to the error frames of the error classes in `mask`, e.g.
+    /// [`error_frame::CAN_ERR_MASK`] for all of them. None by default.
+    pub fn error_filter(&mut self, mask: u32) -> &mut Self {
+        self.error_mask = mask;
+        self
+    }
+
+    /// Timestamp received frames, see [`FrameStream::recv_timestamped`]
+    pub fn timestamping(&mut self, timestamping: Timestamping) -> &mut Self {
+        self.timestamping = Some(timestamping);
+        self
+    }
 }
 
 impl<const N: usize> Default for FrameStreamBuilder<N> {
@@ -135,21 +164,43 @@ impl<const N: usize> FrameStream<N> {
         filters.extend(ffi_filters.into_iter().map(Into::into));
         Ok(filters)
     }
+
+    /// See [`FrameStreamBuilder::error_filter`]
+    pub fn set_error_filter(&self, mask: u32) -> Result<(), Error> {
+        socket::set_error_filter(self, mask)
+    }
+
+    /// See [`FrameStreamBuilder::timestamping`]
+    pub fn set_timestamping(&self, timestamping: Timestamping) -> Result<(), Error> {
+        timestamp::set_timestamping(self, timestamping)
+    }
 }
 
 impl<const N: usize> FrameStream<N> {
+    /// See [`FrameStream::recv`]
     pub fn recv_frame(&self, flags: c_int) -> io::Result<Frame<N>> {
         let mut frame = Frame::empty();
         self.recv(&mut frame, flags).map(|_| frame)
     }
 
+    /// Receive the next data frame
+    ///
+    /// Error frames the stream subscribed to with [`FrameStreamBuilder::error_filter`] are
+    /// skipped, use [`FrameStream::recv_timestamped`] to receive them. They are consumed even
+    /// with `MSG_PEEK`, which only applies to data frames.
     pub fn recv(&self, frame: &mut Frame<N>, flags: c_int) -> io::Result<usize> {
         let mut raw = RawFrame::empty();
-        let size = imp::recv_from(self.as_raw_fd(), &mut raw, flags, Empty)?;
+        let size = loop {
+            let size = imp::recv_from(self.as_raw_fd(), &mut raw, flags, Empty)?;
+            if !imp::skip_error_frame(self.as_raw_fd(), &raw, flags)? {
+                break size;
+            }
+        };
         let _ = std::mem::replace(frame, raw.into());
         Ok(size)
     }
 
+    /// See [`FrameStream::recv`]
     pub fn recv_from(
         &self,
         frame: &mut Frame<N>,
@@ -157,11 +208,40 @@ impl<const N: usize> FrameStream<N> {
         src_addr: &mut CanAddr,
     ) -> io::Result<usize> {
         let mut raw = RawFrame::empty();
-        let size = imp::recv_from(self.as_raw_fd(), &mut raw, flags, SetMut(src_addr))?;
+        let size = loop {
+            let size = imp::recv_from(
+                self.as_raw_fd(),
+                &mut raw,
+                flags,
+                SetMut(&mut *src_addr),
+            )?;
+            if !imp::skip_error_frame(self.as_raw_fd(), &raw, flags)? {
+                break size;
+            }
+        };
         let _ = std::mem::replace(frame, raw.into());
         Ok(size)
     }
 
+    /// Receive a frame together with its receive timestamps
+    ///
+    /// Timestamps are only taken once enabled with [`FrameStreamBuilder::timestamping`], they
+    /// are `None` otherwise. Error frames the stream subscribed to with
+    /// [`FrameStreamBuilder::error_filter`] are decoded, unlike with [`FrameStream::recv`].
+    pub fn recv_timestamped(
+        &self,
+        flags: c_int,
+    ) -> io::Result<(Received<N>, Timestamps)> {
+        let mut raw = RawFrame::empty();
+        let (_, timestamps) =
+            timestamp::recv_with_timestamps(self.as_raw_fd(), &mut raw, flags)?;
+        let received = match raw.error_frame() {
+            Some(error) => Received::Error(error),
+            None => Received::Frame(raw.into()),
+        };
+        Ok((received, timestamps))
+    }
+
     pub fn send(&self, frame: &Frame<N>, flags: c_int) -> io::Result<usize> {
         let raw = RawFrame::from(*frame);
         imp::send_to(self, &raw, flags, Empty)
@@ -227,15 +307,16 @@ impl<const N: usize> IntoRawFd for FrameStream<N> {
     }
 }
 
-mod imp {
+pub(crate) mod imp {
     use std::{io, os::unix::prelude::AsRawFd};
 
     use super::{FrameStream, FrameStreamBuilder};
     use crate::{
         addr::CanAddr,
+        error_frame::{ErrorFrame, CAN_ERR_FLAG},
         filter::{Filter, RawFilter},
-        socket, Error, Frame, Id, Protocol, RawCanAddr, Type, CANFD_DATA_LEN,
-        CAN_DATA_LEN,
+        socket, timestamp, Error, Frame, Id, Protocol, RawCanAddr, Type,
+        CANFD_DATA_LEN, CAN_DATA_LEN,
     };
 
     pub trait Sealed {}
@@ -272,6 +353,12 @@ mod imp {
         socket::set_nonblocking(fd, options.nonblocking)?;
 
         set_filters_fd(fd, &options.filters)?;
+        if options.error_mask != 0 {
+            socket::set_error_filter(fd, options.error_mask)?;
+        }
+        if let Some(timestamping) = options.timestamping {
+            timestamp::set_timestamping(fd, timestamping)?;
+        }
         socket::bind(fd.as_raw_fd(), addr)?;
         Ok(())
     }
@@ -344,7 +431,7 @@ mod imp {
     }
 
     #[repr(C)]
-    pub(super) struct RawFrame<const N: usize> {
+    pub(crate) struct RawFrame<const N: usize> {
         id: u32,
         len: u8,
         flags: u8,
@@ -364,6 +451,17 @@ mod imp {
                 data: [0u8; N],
             }
         }
+
+        /// The decoded error frame, if this is one. Error frames are always classical frames.
+        pub(crate) fn error_frame(&self) -> Option<ErrorFrame> {
+            if self.id & CAN_ERR_FLAG == 0 {
+                return None;
+            }
+            let mut data = [0u8; CAN_DATA_LEN];
+            let len = N.min(CAN_DATA_LEN);
+            data[..len].copy_from_slice(&self.data[..len]);
+            Some(ErrorFrame::decode(self.id, &data))
+        }
     }
 
     impl<const N: usize> From<Frame<N>> for RawFrame<N> {
@@ -407,6 +505,23 @@ mod imp {
         Ok(ret as usize)
     }
 
+    /// Whether `raw` is an error frame to skip. Error frames that were only peeked at are
+    /// consumed, so that the next receive doesn't return them again.
+    pub(super) fn skip_error_frame<const N: usize, F: AsRawFd + std::fmt::Debug>(
+        fd: F,
+        raw: &RawFrame<N>,
+        flags: libc::c_int,
+    ) -> io::Result<bool> {
+        if raw.id & CAN_ERR_FLAG == 0 {
+            return Ok(false);
+        }
+        if flags & libc::MSG_PEEK != 0 {
+            let mut consumed = RawFrame::<N>::empty();
+            recv_from(fd, &mut consumed, flags & !libc::MSG_PEEK, Empty)?;
+        }
+        Ok(true)
+    }
+
     pub(super) fn recv_from<
         const N: usize,
         F: AsRawFd + std::fmt::Debug,
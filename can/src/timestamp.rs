@@ -0,0 +1,164 @@
+//! Receive timestamps of frames
+//!
+//! Once enabled on a socket with [`FrameStreamBuilder::timestamping`], the kernel attaches the
+//! time of reception to every frame, which [`FrameStream::recv_timestamped`] returns together
+//! with the frame. Software timestamps are taken by the kernel when the frame is received,
+//! hardware timestamps by the CAN controller, if it supports them.
+//!
+//! [`FrameStreamBuilder::timestamping`]: crate::stream::FrameStreamBuilder::timestamping
+//! [`FrameStream::recv_timestamped`]: crate::stream::FrameStream::recv_timestamped
+
+use std::{
+    io,
+    os::unix::prelude::{AsRawFd, RawFd},
+    time::Duration,
+};
+
+use crate::Error;
+
+// Neither is exported by all libc versions. mips uses the generic values, sparc has its own.
+// parisc differs as well, but has no Rust target.
+#[cfg(not(any(target_arch = "sparc", target_arch = "sparc64")))]
+const SO_TIMESTAMP: libc::c_int = 29;
+#[cfg(not(any(target_arch = "sparc", target_arch = "sparc64")))]
+const SO_TIMESTAMPING: libc::c_int = 37;
+#[cfg(any(target_arch = "sparc", target_arch = "sparc64"))]
+const SO_TIMESTAMP: libc::c_int = 0x1d;
+#[cfg(any(target_arch = "sparc", target_arch = "sparc64"))]
+const SO_TIMESTAMPING: libc::c_int = 0x23;
+const SCM_TIMESTAMP: libc::c_int = SO_TIMESTAMP;
+const SCM_TIMESTAMPING: libc::c_int = SO_TIMESTAMPING;
+
+const SOF_TIMESTAMPING_RX_HARDWARE: libc::c_int = 1 << 2;
+const SOF_TIMESTAMPING_RX_SOFTWARE: libc::c_int = 1 << 3;
+const SOF_TIMESTAMPING_SOFTWARE: libc::c_int = 1 << 4;
+const SOF_TIMESTAMPING_RAW_HARDWARE: libc::c_int = 1 << 6;
+
+/// Which timestamps to take of received frames
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Timestamping {
+    /// Software timestamps only
+    Software,
+    /// Hardware timestamps of the controller, in addition to software timestamps
+    Hardware,
+}
+
+/// Receive timestamps of a frame, as time since the UNIX epoch
+///
+/// Hardware timestamps are in the clock of the controller, which may not be synchronized to the
+/// system clock.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Timestamps {
+    pub software: Option<Duration>,
+    pub hardware: Option<Duration>,
+}
+
+pub(crate) fn set_timestamping<T: AsRawFd>(
+    fd: &T,
+    timestamping: Timestamping,
+) -> Result<(), Error> {
+    let flags = match timestamping {
+        Timestamping::Software => {
+            SOF_TIMESTAMPING_RX_SOFTWARE | SOF_TIMESTAMPING_SOFTWARE
+        }
+        Timestamping::Hardware => {
+            SOF_TIMESTAMPING_RX_SOFTWARE
+                | SOF_TIMESTAMPING_SOFTWARE
+                | SOF_TIMESTAMPING_RX_HARDWARE
+                | SOF_TIMESTAMPING_RAW_HARDWARE
+        }
+    };
+    let ret = unsafe {
+        libc::setsockopt(
+            fd.as_raw_fd(),
+            libc::SOL_SOCKET,
+            SO_TIMESTAMPING,
+            (&flags as *const libc::c_int) as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as u32,
+        )
+    };
+    if ret < 0 {
+        return Err(Error::Syscall {
+            syscall: "setsockopt(2)".to_string(),
+            context: Some(format!("setting SO_TIMESTAMPING ({timestamping:?})")),
+            source: io::Error::last_os_error(),
+        });
+    }
+    Ok(())
+}
+
+/// Receive into `buf` with recvmsg(2), together with the timestamps the kernel attached
+///
+/// Understands both `SO_TIMESTAMP` and `SO_TIMESTAMPING` timestamps. `T` must be a plain C
+/// struct, e.g. a raw frame.
+pub(crate) fn recv_with_timestamps<T>(
+    fd: RawFd,
+    buf: &mut T,
+    flags: libc::c_int,
+) -> io::Result<(usize, Timestamps)> {
+    let mut iov = libc::iovec {
+        iov_base: (buf as *mut T) as *mut libc::c_void,
+        iov_len: std::mem::size_of::<T>(),
+    };
+    // Room for both a timeval and three timespecs, aligned for cmsghdr
+    let mut control = [0u64; 16];
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = std::mem::size_of_val(&control) as _;
+
+    let ret = unsafe { libc::recvmsg(fd, &mut msg, flags) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut timestamps = Timestamps::default();
+    let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(&msg) };
+    while !cmsg.is_null() {
+        let (level, ty) = unsafe { ((*cmsg).cmsg_level, (*cmsg).cmsg_type) };
+        let data = unsafe { libc::CMSG_DATA(cmsg) };
+        match (level, ty) {
+            (libc::SOL_SOCKET, SCM_TIMESTAMP) => {
+                let tv = unsafe { (data as *const libc::timeval).read_unaligned() };
+                timestamps.software =
+                    to_duration(tv.tv_sec as i64, tv.tv_usec as i64 * 1000);
+            }
+            (libc::SOL_SOCKET, SCM_TIMESTAMPING) => {
+                // Software, deprecated and raw hardware timestamp
+                let ts =
+                    unsafe { (data as *const [libc::timespec; 3]).read_unaligned() };
+                timestamps.software =
+                    to_duration(ts[0].tv_sec as i64, ts[0].tv_nsec as i64);
+                timestamps.hardware =
+                    to_duration(ts[2].tv_sec as i64, ts[2].tv_nsec as i64);
+            }
+            _ => {}
+        }
+        cmsg = unsafe { libc::CMSG_NXTHDR(&msg, cmsg) };
+    }
+    Ok((ret as usize, timestamps))
+}
+
+/// `None` for the zero timestamps of timestamps that weren't taken
+fn to_duration(secs: i64, nanos: i64) -> Option<Duration> {
+    match (u64::try_from(secs), u32::try_from(nanos)) {
+        (Ok(0), Ok(0)) | (Err(_), _) | (_, Err(_)) => None,
+        (Ok(secs), Ok(nanos)) => Some(Duration::new(secs, nanos)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_timestamps_are_none() {
+        assert_eq!(to_duration(0, 0), None);
+        assert_eq!(to_duration(-1, 0), None);
+        assert_eq!(
+            to_duration(1_700_000_000, 5_000),
+            Some(Duration::new(1_700_000_000, 5_000))
+        );
+    }
+}
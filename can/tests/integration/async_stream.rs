@@ -0,0 +1,85 @@
+use std::time::Duration;
+
+use can_rs::async_stream::AsyncFrameStream;
+use can_rs::filter::Filter;
+use can_rs::stream::FrameStream;
+use can_rs::{Error, Frame, Id, CANFD_DATA_LEN, CAN_DATA_LEN};
+use futures::{SinkExt, StreamExt};
+
+use crate::{can_address, canfd_address, ID};
+
+#[tokio::test]
+#[ignore = "needs vcan interface"]
+async fn build_async_frame_stream() -> Result<(), Error> {
+    let stream = FrameStream::<CAN_DATA_LEN>::build().bind(can_address())?;
+    AsyncFrameStream::new(stream)?;
+    Ok(())
+}
+
+#[tokio::test]
+#[ignore = "needs vcan interface"]
+async fn send_and_receive_async_can_frame() -> Result<(), Error> {
+    let id = ID.with(|id| *id);
+    let rx = FrameStream::<CAN_DATA_LEN>::build()
+        .filters(vec![Filter {
+            id: Id::Standard(id),
+            mask: 0xFFFF,
+        }])
+        .bind(can_address())?;
+    let mut rx = AsyncFrameStream::new(rx)?;
+    let tx = AsyncFrameStream::new(FrameStream::<CAN_DATA_LEN>::new(can_address())?)?;
+
+    let frame = Frame {
+        id: Id::Standard(id),
+        flags: 0,
+        len: CAN_DATA_LEN as u8,
+        data: [15u8; CAN_DATA_LEN],
+    };
+    tx.send(&frame).await?;
+
+    let recv_frame = tokio::time::timeout(Duration::from_millis(100), rx.next())
+        .await
+        .expect("timed out waiting for frame")
+        .expect("stream ended")?;
+    assert_eq!(frame, recv_frame);
+    Ok(())
+}
+
+#[tokio::test]
+#[ignore = "needs vcan interface"]
+async fn sink_and_stream_canfd_frames() -> Result<(), Error> {
+    let id = ID.with(|id| *id);
+    let rx = FrameStream::<CANFD_DATA_LEN>::build()
+        .filters(vec![Filter {
+            id: Id::Standard(id),
+            mask: 0xFFFF,
+        }])
+        .bind(canfd_address())?;
+    let rx = AsyncFrameStream::new(rx)?;
+    let mut tx =
+        AsyncFrameStream::new(FrameStream::<CANFD_DATA_LEN>::new(canfd_address())?)?;
+
+    let frames: Vec<_> = (0..3u8)
+        .map(|i| Frame {
+            id: Id::Standard(id),
+            flags: 0,
+            len: CANFD_DATA_LEN as u8,
+            data: [i; CANFD_DATA_LEN],
+        })
+        .collect();
+    for frame in &frames {
+        tx.feed(*frame).await?;
+    }
+    tx.flush().await?;
+
+    let recv_frames: Vec<_> = tokio::time::timeout(
+        Duration::from_millis(100),
+        rx.take(frames.len()).collect::<Vec<_>>(),
+    )
+    .await
+    .expect("timed out waiting for frames")
+    .into_iter()
+    .collect::<Result<_, _>>()?;
+    assert_eq!(frames, recv_frames);
+    Ok(())
+}
@@ -0,0 +1,101 @@
+use std::time::{Duration, Instant};
+
+use can_rs::bcm::{BcmMessage, BcmSocket, RxSetup, TxSetup};
+use can_rs::filter::Filter;
+use can_rs::stream::FrameStream;
+use can_rs::{Error, Frame, Id, CAN_DATA_LEN};
+
+use crate::{can_address, ID};
+
+#[test]
+#[ignore = "needs vcan interface"]
+fn build_bcm_socket() -> Result<(), Error> {
+    BcmSocket::<CAN_DATA_LEN>::build()
+        .nonblocking(true)
+        .connect(can_address())?;
+    Ok(())
+}
+
+#[test]
+#[ignore = "needs vcan interface"]
+fn cyclic_transmission_is_received() -> Result<(), Error> {
+    let id = ID.with(|id| *id);
+    let stream = FrameStream::<CAN_DATA_LEN>::build()
+        .filters(vec![Filter {
+            id: Id::Standard(id),
+            mask: 0xFFFF,
+        }])
+        .bind(can_address())?;
+
+    let socket = BcmSocket::<CAN_DATA_LEN>::connect(can_address())?;
+    let frame = Frame {
+        id: Id::Standard(id),
+        flags: 0,
+        len: 2,
+        data: [7u8; CAN_DATA_LEN],
+    };
+    socket.tx_setup(&TxSetup::cyclic(
+        Id::Standard(id),
+        frame,
+        Duration::from_millis(5),
+    ))?;
+
+    for _ in 0..3 {
+        assert_eq!(frame, stream.recv_frame(0)?);
+    }
+    socket.tx_delete(Id::Standard(id))?;
+    Ok(())
+}
+
+#[test]
+#[ignore = "needs vcan interface"]
+fn update_keeps_the_timer() -> Result<(), Error> {
+    let id = ID.with(|id| *id);
+    let stream = FrameStream::<CAN_DATA_LEN>::build()
+        .filters(vec![Filter {
+            id: Id::Standard(id),
+            mask: 0xFFFF,
+        }])
+        .bind(can_address())?;
+
+    let socket = BcmSocket::<CAN_DATA_LEN>::connect(can_address())?;
+    let interval = Duration::from_millis(200);
+    let mut frame = Frame {
+        id: Id::Standard(id),
+        flags: 0,
+        len: 2,
+        data: [1u8; CAN_DATA_LEN],
+    };
+    socket.tx_setup(&TxSetup::cyclic(Id::Standard(id), frame, interval))?;
+    assert_eq!(frame, stream.recv_frame(0)?);
+    let sent = Instant::now();
+
+    // The update goes out with the next interval, not right away
+    frame.data = [2u8; CAN_DATA_LEN];
+    let mut update = TxSetup::cyclic(Id::Standard(id), frame, interval);
+    update.update_only(true);
+    socket.tx_setup(&update)?;
+    assert_eq!(frame, stream.recv_frame(0)?);
+    assert!(sent.elapsed() >= interval / 2);
+
+    socket.tx_delete(Id::Standard(id))?;
+    Ok(())
+}
+
+#[test]
+#[ignore = "needs vcan interface"]
+fn silent_peer_times_out() -> Result<(), Error> {
+    let id = ID.with(|id| *id);
+    let socket = BcmSocket::<CAN_DATA_LEN>::connect(can_address())?;
+    let mut rx = RxSetup::new(Id::Standard(id));
+    rx.timeout(Duration::from_millis(10));
+    socket.rx_setup(&rx)?;
+
+    assert_eq!(
+        BcmMessage::RxTimeout {
+            id: Id::Standard(id)
+        },
+        socket.recv()?
+    );
+    Ok(())
+}
@@ -0,0 +1,79 @@
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use can_rs::capture::{replay, Format, LogReader, LogWriter, Recorder, Timing};
+use can_rs::filter::Filter;
+use can_rs::stream::FrameStream;
+use can_rs::{Error, Id, CAN_DATA_LEN};
+
+use crate::{can_address, ID};
+
+#[test]
+#[ignore = "needs vcan interface"]
+fn replayed_log_is_recorded_with_original_timing() -> Result<(), Error> {
+    let id = ID.with(|id| *id);
+    let log = format!(
+        "(1436509052.000000) vcan9 {id:03X}#01\n\
+         (1436509052.050000) vcan9 {id:03X}#0202\n\
+         (1436509052.100000) vcan9 {id:03X}#030303\n"
+    );
+    let recorder = Recorder::new(
+        FrameStream::<CAN_DATA_LEN>::build()
+            .filters(vec![Filter {
+                id: Id::Standard(id),
+                mask: 0xFFFF,
+            }])
+            .bind(can_address())?,
+    )?;
+
+    let started = Instant::now();
+    let stream = FrameStream::<CAN_DATA_LEN>::new(can_address())?;
+    let sent = replay(
+        &stream,
+        LogReader::new(log.as_bytes(), Format::Candump),
+        Timing::Original,
+    )?;
+    assert_eq!(3, sent);
+    assert!(started.elapsed() >= Duration::from_millis(100));
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    let mut writer = LogWriter::new(Vec::new(), Format::Candump);
+    let mut records = Vec::new();
+    for len in 1..=3 {
+        let record = recorder.recv()?;
+        assert_eq!(len, record.frame.len);
+        assert_eq!(can_address().name, record.interface);
+        assert!(
+            record.timestamp <= now && now - record.timestamp < Duration::from_secs(1)
+        );
+        writer.write(&record)?;
+        records.push(record);
+    }
+    let gap = records[2].timestamp - records[0].timestamp;
+    assert!(gap >= Duration::from_millis(100));
+
+    let written = writer.finish()?;
+    let read = LogReader::new(written.as_slice(), Format::Candump)
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(records, read);
+    Ok(())
+}
+
+#[test]
+#[ignore = "needs vcan interface"]
+fn accelerated_replay() -> Result<(), Error> {
+    let id = ID.with(|id| *id);
+    let log = format!(
+        "(1436509052.000000) vcan9 {id:03X}#01\n\
+         (1436509054.000000) vcan9 {id:03X}#02\n"
+    );
+    let stream = FrameStream::<CAN_DATA_LEN>::new(can_address())?;
+    let started = Instant::now();
+    replay(
+        &stream,
+        LogReader::new(log.as_bytes(), Format::Candump),
+        Timing::Accelerated(20.0),
+    )?;
+    let elapsed = started.elapsed();
+    assert!(elapsed >= Duration::from_millis(100) && elapsed < Duration::from_secs(2));
+    Ok(())
+}
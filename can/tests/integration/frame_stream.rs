@@ -1,8 +1,16 @@
+use can_rs::error_frame::{CAN_ERR_FLAG, CAN_ERR_MASK, CAN_ERR_RESTARTED};
 use can_rs::filter::Filter;
-use can_rs::stream::FrameStream;
-use can_rs::{Error, Frame, Id, CANFD_DATA_LEN, CAN_DATA_LEN, MTU};
+use can_rs::stream::{FrameStream, Received};
+use can_rs::timestamp::{Timestamping, Timestamps};
+use can_rs::{Error, Frame, Id, CANFD_DATA_LEN, CAN_DATA_LEN, CAN_MTU, MTU};
 use core::time;
-use std::{sync::mpsc, thread};
+use std::{
+    io,
+    os::unix::prelude::AsRawFd,
+    sync::mpsc,
+    thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use crate::{can_address, canfd_address, ID};
 
@@ -138,3 +146,89 @@ fn set_too_many_sockets() {
         .bind(can_address())
         .unwrap();
 }
+
+#[test]
+#[ignore = "needs vcan interface"]
+fn receive_timestamped_frame() -> Result<(), Error> {
+    let id = ID.with(|id| *id);
+    let rx = FrameStream::<CAN_DATA_LEN>::build()
+        .filters(vec![Filter {
+            id: Id::Standard(id),
+            mask: 0xFFFF,
+        }])
+        .error_filter(CAN_ERR_MASK)
+        .timestamping(Timestamping::Software)
+        .bind(can_address())?;
+    let untimestamped = FrameStream::<CAN_DATA_LEN>::build()
+        .filters(vec![Filter {
+            id: Id::Standard(id),
+            mask: 0xFFFF,
+        }])
+        .bind(can_address())?;
+
+    let frame = Frame {
+        id: Id::Standard(id),
+        flags: 0,
+        len: 8,
+        data: [3u8; CAN_DATA_LEN],
+    };
+    FrameStream::<CAN_DATA_LEN>::new(can_address())?.send(&frame, 0)?;
+
+    let (received, timestamps) = rx.recv_timestamped(0)?;
+    assert_eq!(Received::Frame(frame), received);
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    let software = timestamps.software.expect("missing software timestamp");
+    assert!(software <= now && now - software < time::Duration::from_secs(1));
+    assert_eq!(None, timestamps.hardware);
+
+    let (received, timestamps) = untimestamped.recv_timestamped(0)?;
+    assert_eq!(Received::Frame(frame), received);
+    assert_eq!(Timestamps::default(), timestamps);
+    Ok(())
+}
+
+#[test]
+#[ignore = "needs vcan interface"]
+fn error_frames_are_skipped() -> Result<(), Error> {
+    let id = ID.with(|id| *id);
+    let rx = FrameStream::<CAN_DATA_LEN>::build()
+        .filters(vec![Filter {
+            id: Id::Standard(id),
+            mask: 0xFFFF,
+        }])
+        .error_filter(CAN_ERR_MASK)
+        .bind(canfd_address())?;
+    let tx = FrameStream::<CAN_DATA_LEN>::new(canfd_address())?;
+
+    // Frame can't carry the error flag, so the error frame is written raw
+    let mut error = [0u8; CAN_MTU];
+    error[..4].copy_from_slice(&(CAN_ERR_FLAG | CAN_ERR_RESTARTED).to_ne_bytes());
+    error[4] = CAN_DATA_LEN as u8;
+    let frame = Frame {
+        id: Id::Standard(id),
+        flags: 0,
+        len: 2,
+        data: [9u8; CAN_DATA_LEN],
+    };
+    for _ in 0..2 {
+        let ret = unsafe {
+            libc::write(
+                tx.as_raw_fd(),
+                error.as_ptr() as *const libc::c_void,
+                CAN_MTU,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        tx.send(&frame, 0)?;
+    }
+
+    // The peeked error frame is consumed, the peeked data frame isn't
+    assert_eq!(frame, rx.recv_frame(libc::MSG_PEEK)?);
+    assert_eq!(frame, rx.recv_frame(0)?);
+    let mut received = Frame::empty();
+    rx.recv_from(&mut received, 0, &mut canfd_address())?;
+    assert_eq!(frame, received);
+    Ok(())
+}
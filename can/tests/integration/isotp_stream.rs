@@ -20,3 +20,18 @@ fn write_isotp_stream() -> Result<(), Error> {
     assert_eq!(got, bytes.len());
     Ok(())
 }
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+#[ignore = "needs vcan interface"]
+async fn write_async_isotp_stream() -> Result<(), Error> {
+    use can_rs::isotp::async_stream::AsyncIsotpStream;
+    use tokio::io::AsyncWriteExt;
+
+    let stream = IsotpStream::<CAN_DATA_LEN>::build().bind(isotp_address())?;
+    let mut stream = AsyncIsotpStream::new(stream)?;
+    let bytes: Vec<u8> = vec![0, 1, 2, 4, 8, 16, 32, 64, 128, 255];
+    let got = stream.write(bytes.as_slice()).await?;
+    assert_eq!(got, bytes.len());
+    Ok(())
+}
@@ -0,0 +1,81 @@
+use can_rs::j1939::{
+    addr::CanJ1939Addr, stream::J1939Stream, J1939_MAX_TP_PACKET_SIZE, J1939_NO_ADDR,
+    J1939_NO_NAME, J1939_NO_PGN, J1939_PGN_ADDRESS_CLAIMED,
+};
+use can_rs::Error;
+
+const J1939_ADDRESS_RAW: &str = "vcan1";
+const PGN: u32 = 0x0EF00;
+
+#[test]
+#[ignore = "needs vcan interface"]
+fn build_j1939_stream() -> Result<(), Error> {
+    J1939Stream::build()
+        .nonblocking(true)
+        .broadcast(true)
+        .promiscuous(true)
+        .send_priority(3)
+        .bind(CanJ1939Addr::with_address(J1939_ADDRESS_RAW, 0x80)?)?;
+    Ok(())
+}
+
+#[test]
+#[ignore = "needs vcan interface"]
+fn multi_packet_message_is_reassembled() -> Result<(), Error> {
+    let tx = J1939Stream::new(CanJ1939Addr::with_address(J1939_ADDRESS_RAW, 0x81)?)?;
+    let rx = J1939Stream::new(CanJ1939Addr::new(J1939_ADDRESS_RAW, 0, PGN, 0x82)?)?;
+
+    let data: Vec<u8> = (0..J1939_MAX_TP_PACKET_SIZE).map(|i| i as u8).collect();
+    assert_eq!(data.len(), tx.send_to(&data, &tx.addr().peer(PGN, 0x82))?);
+
+    let mut buf = vec![0u8; J1939_MAX_TP_PACKET_SIZE];
+    let mut src = CanJ1939Addr::with_address(J1939_ADDRESS_RAW, 0)?;
+    let len = rx.recv_from(&mut buf, &mut src)?;
+    assert_eq!(&data[..], &buf[..len]);
+    assert_eq!(0x81, src.addr());
+    assert_eq!(PGN, src.pgn());
+    Ok(())
+}
+
+#[test]
+#[ignore = "needs vcan interface"]
+fn lower_name_wins_address_claim() -> Result<(), Error> {
+    let claimant = |name| {
+        J1939Stream::build().broadcast(true).bind(CanJ1939Addr::new(
+            J1939_ADDRESS_RAW,
+            name,
+            J1939_NO_PGN,
+            0x90,
+        )?)
+    };
+    let winner = claimant(0x1000)?;
+    winner.claim_address()?;
+
+    // Watch the bus, so that the winner only contends once the loser claimed.
+    let monitor = J1939Stream::build()
+        .broadcast(true)
+        .promiscuous(true)
+        .bind(CanJ1939Addr::new(
+            J1939_ADDRESS_RAW,
+            J1939_NO_NAME,
+            J1939_PGN_ADDRESS_CLAIMED,
+            J1939_NO_ADDR,
+        )?)?;
+    let loser = claimant(0x2000)?;
+    let claimer = std::thread::spawn(move || loser.claim_address());
+    let mut buf = [0u8; 8];
+    let mut src = monitor.addr().clone();
+    while u64::from_le_bytes(buf) != 0x2000 {
+        monitor.recv_from(&mut buf, &mut src)?;
+    }
+
+    winner.claim_address()?;
+    assert!(matches!(
+        claimer.join().unwrap(),
+        Err(Error::J1939AddressClaimLost {
+            addr: 0x90,
+            name: 0x2000
+        })
+    ));
+    Ok(())
+}
@@ -4,10 +4,17 @@ use can_rs::{addr::CanAddr, Error};
 #[cfg(feature = "isotp")]
 use can_rs::{isotp::addr::CanIsotpAddr, Id};
 
+#[cfg(feature = "tokio")]
+mod async_stream;
+#[cfg(feature = "bcm")]
+mod bcm;
+mod capture;
 mod filters;
 mod frame_stream;
 #[cfg(feature = "isotp")]
 mod isotp_stream;
+#[cfg(feature = "j1939")]
+mod j1939;
 
 /// Track the largest Thread ID (keeping it strictly incrementing)
 static LARGEST_ID: AtomicU32 = AtomicU32::new(1);
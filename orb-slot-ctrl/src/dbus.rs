@@ -0,0 +1,434 @@
+//! The `org.worldcoin.SlotCtrl1` dbus service, so that unprivileged daemons can
+//! observe the boot state and step through updates without running `slot-ctrl`
+//! as root.
+//!
+//! The service runs on the system bus. The dbus policy shipped in the package
+//! (`/usr/share/dbus-1/system.d/org.worldcoin.SlotCtrl1.conf`) lets anyone read the
+//! properties, but only root call the methods that change the boot state.
+//!
+//! ```sh
+//! # Get the next boot slot
+//! gdbus call --system -d org.worldcoin.SlotCtrl1 -o '/org/worldcoin/SlotCtrl1' -m \
+//!     org.freedesktop.DBus.Properties.Get org.worldcoin.SlotCtrl1 NextSlot
+//!
+//! # Begin an update of the inactive slot
+//! sudo gdbus call --system -d org.worldcoin.SlotCtrl1 -o '/org/worldcoin/SlotCtrl1' -m \
+//!     org.worldcoin.SlotCtrl1.BeginUpdate
+//!
+//! # Wait for the next boot slot to change
+//! dbus-monitor --system type='signal',sender='org.worldcoin.SlotCtrl1',member='NextBootSlotChanged'
+//! ```
+
+use std::{
+    sync::{Arc, Mutex, PoisonError},
+    time::Duration,
+};
+
+use zbus::{
+    fdo::DBusProxy, interface, message::Header, Connection, ConnectionBuilder,
+    SignalContext,
+};
+
+use crate::{
+    get_current_slot, get_next_boot_slot, get_rootfs_status,
+    journal::{Journal, JournaledStore},
+    update, EfiVarStore, Error, RootFsStatus, Slot,
+};
+
+/// Well-known name of the service.
+pub const SERVICE_NAME: &str = "org.worldcoin.SlotCtrl1";
+/// Path the interface is served at.
+pub const OBJECT_PATH: &str = "/org/worldcoin/SlotCtrl1";
+
+/// The parts of the boot state that are exposed as properties.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct BootState {
+    current_slot: Slot,
+    next_slot: Slot,
+    current_status: RootFsStatus,
+    inactive_status: RootFsStatus,
+}
+
+impl BootState {
+    fn read(store: &dyn EfiVarStore) -> Result<Self, Error> {
+        let current_slot = get_current_slot(store)?;
+        Ok(Self {
+            current_slot,
+            next_slot: get_next_boot_slot(store)?,
+            current_status: get_rootfs_status(store, current_slot)?,
+            inactive_status: get_rootfs_status(store, current_slot.other())?,
+        })
+    }
+}
+
+/// Implementation of `org.worldcoin.SlotCtrl1`.
+///
+/// Slots are named `a` and `b`, rootfs statuses like the variants of
+/// [`RootFsStatus`].
+pub struct Interface {
+    store: Arc<dyn EfiVarStore>,
+    // Mutations are journaled here, attributed to the caller of the method.
+    journal: Option<Journal>,
+    // Held while an update step runs, so that concurrent calls don't interleave.
+    step: Arc<Mutex<()>>,
+    // The state that was last signalled.
+    last: Mutex<Option<BootState>>,
+}
+
+impl Interface {
+    /// An interface to the efivars in `store`, journaling the changes made through
+    /// it to `journal`.
+    #[must_use]
+    pub fn new(store: Arc<dyn EfiVarStore>, journal: Option<Journal>) -> Self {
+        let last = BootState::read(store.as_ref()).ok();
+        Self {
+            store,
+            journal,
+            step: Arc::default(),
+            last: Mutex::new(last),
+        }
+    }
+
+    /// Re-read the boot state, and signal whatever changed since the last time.
+    /// Changes made through the interface are signalled right away, but others,
+    /// e.g. by `slot-ctrl`, only once this is called.
+    ///
+    /// # Panics
+    ///
+    /// If a previous call panicked.
+    pub async fn refresh(&self, ctxt: &SignalContext<'_>) -> zbus::Result<()> {
+        let Ok(state) = self.state().await else {
+            // Reported to callers through the properties.
+            return Ok(());
+        };
+        let Some(last) = self.last.lock().unwrap().replace(state) else {
+            return Ok(());
+        };
+
+        if state.current_slot != last.current_slot {
+            self.current_slot_changed(ctxt).await?;
+        }
+        if state.next_slot != last.next_slot {
+            self.next_slot_changed(ctxt).await?;
+            Self::next_boot_slot_changed(ctxt, &state.next_slot.to_string()).await?;
+        }
+        if state.current_status != last.current_status {
+            self.current_rootfs_status_changed(ctxt).await?;
+        }
+        if state.inactive_status != last.inactive_status {
+            self.inactive_rootfs_status_changed(ctxt).await?;
+        }
+        Ok(())
+    }
+
+    async fn state(&self) -> zbus::fdo::Result<BootState> {
+        blocking(Arc::clone(&self.store), BootState::read).await
+    }
+
+    /// Run one of the [`update`] steps for the caller of the method in `header`,
+    /// and signal its changes.
+    async fn transition(
+        &self,
+        ctxt: &SignalContext<'_>,
+        header: &Header<'_>,
+        step: fn(&dyn EfiVarStore) -> Result<Slot, Error>,
+    ) -> zbus::fdo::Result<String> {
+        let store = match &self.journal {
+            Some(journal) => {
+                let (command, uid) = caller(ctxt.connection(), header).await;
+                Arc::new(JournaledStore::new(
+                    Arc::clone(&self.store),
+                    journal.clone(),
+                    command,
+                    uid,
+                ))
+            }
+            None => Arc::clone(&self.store),
+        };
+        let lock = Arc::clone(&self.step);
+        let result = blocking(store, move |store| {
+            let _step = lock.lock().unwrap_or_else(PoisonError::into_inner);
+            step(store)
+        })
+        .await;
+        // The step already happened, the caller needs to know its result either way.
+        if let Err(err) = self.refresh(ctxt).await {
+            tracing::warn!("failed to signal boot state changes: {err}");
+        }
+        result.map(|slot| slot.to_string())
+    }
+}
+
+#[interface(name = "org.worldcoin.SlotCtrl1")]
+impl Interface {
+    /// The current active slot.
+    #[zbus(property)]
+    async fn current_slot(&self) -> zbus::fdo::Result<String> {
+        Ok(self.state().await?.current_slot.to_string())
+    }
+
+    /// The slot set for the next boot.
+    #[zbus(property)]
+    async fn next_slot(&self) -> zbus::fdo::Result<String> {
+        Ok(self.state().await?.next_slot.to_string())
+    }
+
+    /// The rootfs status of the current active slot.
+    #[zbus(property)]
+    async fn current_rootfs_status(&self) -> zbus::fdo::Result<String> {
+        Ok(format!("{:?}", self.state().await?.current_status))
+    }
+
+    /// The rootfs status of the inactive slot.
+    #[zbus(property)]
+    async fn inactive_rootfs_status(&self) -> zbus::fdo::Result<String> {
+        Ok(format!("{:?}", self.state().await?.inactive_status))
+    }
+
+    /// See [`update::begin`]. Returns the slot to write the update to.
+    async fn begin_update(
+        &self,
+        #[zbus(signal_context)] ctxt: SignalContext<'_>,
+        #[zbus(header)] header: Header<'_>,
+    ) -> zbus::fdo::Result<String> {
+        self.transition(&ctxt, &header, update::begin).await
+    }
+
+    /// See [`update::activate`]. Returns the slot that boots next.
+    async fn activate_update(
+        &self,
+        #[zbus(signal_context)] ctxt: SignalContext<'_>,
+        #[zbus(header)] header: Header<'_>,
+    ) -> zbus::fdo::Result<String> {
+        self.transition(&ctxt, &header, update::activate).await
+    }
+
+    /// See [`update::mark_successful`]. Returns the confirmed slot.
+    async fn mark_successful(
+        &self,
+        #[zbus(signal_context)] ctxt: SignalContext<'_>,
+        #[zbus(header)] header: Header<'_>,
+    ) -> zbus::fdo::Result<String> {
+        self.transition(&ctxt, &header, update::mark_successful)
+            .await
+    }
+
+    /// See [`update::rollback`]. Returns the slot that boots next.
+    async fn rollback(
+        &self,
+        #[zbus(signal_context)] ctxt: SignalContext<'_>,
+        #[zbus(header)] header: Header<'_>,
+    ) -> zbus::fdo::Result<String> {
+        self.transition(&ctxt, &header, update::rollback).await
+    }
+
+    /// Emitted with the new slot whenever the slot for the next boot changes.
+    #[zbus(signal)]
+    async fn next_boot_slot_changed(
+        ctxt: &SignalContext<'_>,
+        slot: &str,
+    ) -> zbus::Result<()>;
+}
+
+/// Client side of `org.worldcoin.SlotCtrl1`, see [`Interface`].
+#[zbus::proxy(
+    default_service = "org.worldcoin.SlotCtrl1",
+    default_path = "/org/worldcoin/SlotCtrl1",
+    interface = "org.worldcoin.SlotCtrl1"
+)]
+pub trait SlotCtrl {
+    /// The current active slot.
+    #[zbus(property)]
+    fn current_slot(&self) -> zbus::Result<String>;
+
+    /// The slot set for the next boot.
+    #[zbus(property)]
+    fn next_slot(&self) -> zbus::Result<String>;
+
+    /// The rootfs status of the current active slot.
+    #[zbus(property)]
+    fn current_rootfs_status(&self) -> zbus::Result<String>;
+
+    /// The rootfs status of the inactive slot.
+    #[zbus(property)]
+    fn inactive_rootfs_status(&self) -> zbus::Result<String>;
+
+    /// Start updating the inactive slot.
+    fn begin_update(&self) -> zbus::Result<String>;
+
+    /// Boot the updated inactive slot next.
+    fn activate_update(&self) -> zbus::Result<String>;
+
+    /// Confirm that the updated slot booted successfully.
+    fn mark_successful(&self) -> zbus::Result<String>;
+
+    /// Abandon the update and boot the previous slot next.
+    fn rollback(&self) -> zbus::Result<String>;
+
+    /// Emitted with the new slot whenever the slot for the next boot changes.
+    #[zbus(signal)]
+    fn next_boot_slot_changed(&self, slot: String) -> zbus::Result<()>;
+}
+
+/// Serve [`Interface`] on the system bus, and check the efivars for changes made
+/// by others every `poll_interval`. Runs until the connection fails.
+///
+/// Changes made through the interface are journaled to `journal`, attributed to the
+/// method, its sender and the uid of the sender.
+pub async fn serve(
+    store: Arc<dyn EfiVarStore>,
+    journal: Option<Journal>,
+    poll_interval: Duration,
+) -> zbus::Result<()> {
+    let conn = ConnectionBuilder::system()?
+        .name(SERVICE_NAME)?
+        .serve_at(OBJECT_PATH, Interface::new(store, journal))?
+        .build()
+        .await?;
+    let iface = conn
+        .object_server()
+        .interface::<_, Interface>(OBJECT_PATH)
+        .await?;
+    loop {
+        tokio::time::sleep(poll_interval).await;
+        iface.get().await.refresh(iface.signal_context()).await?;
+    }
+}
+
+/// Run `f` on `store` without blocking the executor, efivarfs is slow.
+async fn blocking<T: Send + 'static>(
+    store: Arc<dyn EfiVarStore>,
+    f: impl FnOnce(&dyn EfiVarStore) -> Result<T, Error> + Send + 'static,
+) -> zbus::fdo::Result<T> {
+    tokio::task::spawn_blocking(move || f(store.as_ref()))
+        .await
+        .map_err(|err| zbus::fdo::Error::Failed(err.to_string()))?
+        .map_err(to_fdo_error)
+}
+
+/// The method call in `header` and its sender, and the uid of the sender if the
+/// bus knows it, for the journal.
+async fn caller(conn: &Connection, header: &Header<'_>) -> (String, Option<u32>) {
+    let method = header.member().map_or("unknown method", |m| m.as_str());
+    let Some(sender) = header.sender() else {
+        return (format!("dbus {method}"), None);
+    };
+    let uid = match DBusProxy::new(conn).await {
+        Ok(bus) => bus.get_connection_unix_user(sender.as_ref().into()).await.ok(),
+        Err(_) => None,
+    };
+    (format!("dbus {method} from {sender}"), uid)
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn to_fdo_error(err: Error) -> zbus::fdo::Error {
+    zbus::fdo::Error::Failed(err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use eyre::Result;
+    use zbus::{proxy::CacheProperties, Guid};
+
+    use super::*;
+    use crate::{efivar::InMemoryStore, tests::booted_from_a};
+
+    /// A client connected to [`Interface`] serving `store`, without a bus.
+    async fn connect(
+        store: Arc<InMemoryStore>,
+        journal: Option<Journal>,
+    ) -> Result<(Connection, SlotCtrlProxy<'static>)> {
+        let (server, client) = tokio::net::UnixStream::pair()?;
+        let (server, client) = tokio::try_join!(
+            ConnectionBuilder::unix_stream(server)
+                .server(Guid::generate())?
+                .p2p()
+                .serve_at(OBJECT_PATH, Interface::new(store, journal))?
+                .build(),
+            ConnectionBuilder::unix_stream(client).p2p().build(),
+        )?;
+        let proxy = SlotCtrlProxy::builder(&client)
+            .cache_properties(CacheProperties::No)
+            .build()
+            .await?;
+        Ok((server, proxy))
+    }
+
+    #[tokio::test]
+    async fn test_update_methods() -> Result<()> {
+        let store = Arc::new(booted_from_a());
+        let (_server, proxy) = connect(Arc::clone(&store), None).await?;
+        assert_eq!(proxy.current_slot().await?, "a");
+        assert_eq!(proxy.next_slot().await?, "a");
+
+        assert_eq!(proxy.begin_update().await?, "b");
+        assert_eq!(proxy.inactive_rootfs_status().await?, "UpdateInProcess");
+        assert_eq!(proxy.activate_update().await?, "b");
+        assert_eq!(proxy.next_slot().await?, "b");
+        assert_eq!(proxy.inactive_rootfs_status().await?, "UpdateDone");
+        assert_eq!(get_next_boot_slot(store.as_ref())?, Slot::B);
+
+        assert_eq!(proxy.mark_successful().await?, "a");
+        assert_eq!(proxy.rollback().await?, "a");
+        assert_eq!(proxy.next_slot().await?, "a");
+        assert_eq!(proxy.current_rootfs_status().await?, "Normal");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_failed_step_is_reported() -> Result<()> {
+        let store = Arc::new(booted_from_a());
+        let (_server, proxy) = connect(Arc::clone(&store), None).await?;
+        let vars = store.vars();
+        match proxy.rollback().await {
+            Err(zbus::Error::MethodError(name, Some(message), _)) => {
+                assert_eq!(name.as_str(), "org.freedesktop.DBus.Error.Failed");
+                assert_eq!(message, Error::NothingToRollBack.to_string());
+            }
+            result => panic!("unexpected result {result:?}"),
+        }
+        assert_eq!(store.vars(), vars);
+        Ok(())
+    }
+
+    #[test]
+    fn test_boot_state() -> Result<()> {
+        let store = booted_from_a();
+        let state = BootState::read(&store)?;
+        assert_eq!(
+            state,
+            BootState {
+                current_slot: Slot::A,
+                next_slot: Slot::A,
+                current_status: RootFsStatus::Normal,
+                inactive_status: RootFsStatus::Normal,
+            }
+        );
+
+        update::begin(&store)?;
+        update::activate(&store)?;
+        let state = BootState::read(&store)?;
+        assert_eq!(state.next_slot, Slot::B);
+        assert_eq!(state.inactive_status, RootFsStatus::UpdateDone);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_mutations_are_journaled_with_the_method() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let journal = Journal::new(dir.path());
+        let store = Arc::new(booted_from_a());
+        let (_server, proxy) = connect(store, Some(journal.clone())).await?;
+        proxy.begin_update().await?;
+        proxy.current_slot().await?;
+
+        let entries = journal.entries()?;
+        assert!(!entries.is_empty());
+        assert!(
+            entries.iter().all(|e| e.command == "dbus BeginUpdate"),
+            "without a bus there is neither a sender nor a uid"
+        );
+        assert!(entries.iter().all(|e| e.uid.is_none()));
+        Ok(())
+    }
+}
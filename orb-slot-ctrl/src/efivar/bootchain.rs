@@ -2,19 +2,23 @@
 //!
 //! * `BootChainFwCurrent` - represents the current boot slot (readonly)
 //! * `BootChainFwNext` - represents the next boot slot
+//! * `BootChainFwStatus` - represents the status of the last bootloader chain switch
+//!   or update (readonly)
 //!
-//! Bits of interest are found in byte 4 for all efivars.
+//! Bits of interest are found in the first byte of the payload, after the attributes,
+//! for the slot efivars. The status is a 32 bit value.
 
-use super::{is_valid_buffer, EfiVar, SLOT_A, SLOT_B};
+use super::{
+    is_valid_buffer, EfiVar, EfiVarAttributes, EfiVarData, EfiVarStore, SLOT_A, SLOT_B,
+};
 use crate::Error;
 
 pub const PATH_CURRENT: &str =
     "BootChainFwCurrent-781e084c-a330-417c-b678-38e696380cb9";
 pub const PATH_NEXT: &str = "BootChainFwNext-781e084c-a330-417c-b678-38e696380cb9";
+pub const PATH_STATUS: &str = "BootChainFwStatus-781e084c-a330-417c-b678-38e696380cb9";
 
-const EXPECTED_LEN: usize = 8;
-const NEXT_BOOT_SLOT_NEW_BUFFER: [u8; 8] =
-    [0x07, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+const EXPECTED_LEN: usize = 4;
 
 /// Throws an `Error` if the given slot is invalid.
 fn is_valid_slot(slot: u8) -> Result<(), Error> {
@@ -24,53 +28,71 @@ fn is_valid_slot(slot: u8) -> Result<(), Error> {
     }
 }
 
-// Get the slot from a buffer.
-fn get_slot_from_buffer(buffer: &[u8]) -> Result<u8, Error> {
-    is_valid_buffer(buffer, EXPECTED_LEN)?;
-    Ok(buffer[4])
+// Get the slot from a payload.
+fn get_slot_from_payload(payload: &[u8]) -> Result<u8, Error> {
+    is_valid_buffer(payload, EXPECTED_LEN)?;
+    Ok(payload[0])
 }
 
-// Set the slot in given buffer.
-fn set_slot_in_buffer(buffer: &mut Vec<u8>, slot: u8) -> Result<(), Error> {
-    is_valid_buffer(&*buffer, EXPECTED_LEN)?;
-    // Next boot slot information can be found in byte 4.
-    buffer[4] = slot;
+// Set the slot in given payload.
+fn set_slot_in_payload(payload: &mut [u8], slot: u8) -> Result<(), Error> {
+    is_valid_buffer(payload, EXPECTED_LEN)?;
+    // Next boot slot information can be found in the first byte.
+    payload[0] = slot;
     Ok(())
 }
 
 /// Gets the raw current boot slot.
-pub fn get_current_boot_slot() -> Result<u8, Error> {
-    let efivar = EfiVar::from_path(PATH_CURRENT)?.read_fixed_len(EXPECTED_LEN)?;
-    get_slot_from_buffer(&efivar)
+pub fn get_current_boot_slot(store: &dyn EfiVarStore) -> Result<u8, Error> {
+    let efivar = EfiVar::new(store, PATH_CURRENT).read_fixed_len(EXPECTED_LEN)?;
+    get_slot_from_payload(&efivar.payload)
 }
 
 /// Gets the raw next boot slot.
-pub fn get_next_boot_slot() -> Result<u8, Error> {
-    match EfiVar::from_path(PATH_NEXT)?.read_fixed_len(EXPECTED_LEN) {
-        Ok(efivar) => Ok(get_slot_from_buffer(&efivar)?),
+pub fn get_next_boot_slot(store: &dyn EfiVarStore) -> Result<u8, Error> {
+    match EfiVar::new(store, PATH_NEXT).read_fixed_len(EXPECTED_LEN) {
+        Ok(efivar) => Ok(get_slot_from_payload(&efivar.payload)?),
         Err(Error::OpenFile { path: _, source: _ }) => {
             // in this case the efivar does not exist yet because it gets created on demand and
             // the next boot slot will be the same as the current
-            get_current_boot_slot()
+            get_current_boot_slot(store)
         }
         Err(err) => Err(err),
     }
 }
 
+/// Gets the raw bootloader chain status, `None` if it was never set.
+pub fn get_fw_status(store: &dyn EfiVarStore) -> Result<Option<u32>, Error> {
+    match EfiVar::new(store, PATH_STATUS).read_fixed_len(EXPECTED_LEN) {
+        Ok(efivar) => {
+            let [a, b, c, d] = efivar.payload[..] else {
+                unreachable!("length was validated");
+            };
+            Ok(Some(u32::from_le_bytes([a, b, c, d])))
+        }
+        Err(Error::OpenFile { .. }) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
 /// Set the next boot slot.
-pub fn set_next_boot_slot(slot: u8) -> Result<(), Error> {
+pub fn set_next_boot_slot(store: &dyn EfiVarStore, slot: u8) -> Result<(), Error> {
     is_valid_slot(slot)?;
-    let efivar = super::EfiVar::from_path(PATH_NEXT)?;
+    let efivar = EfiVar::new(store, PATH_NEXT);
     match efivar.read_fixed_len(EXPECTED_LEN) {
-        Ok(mut val) => {
-            set_slot_in_buffer(&mut val, slot)?;
-            efivar.write(&val)
+        Ok(mut data) => {
+            // keeps the attributes the efivar already has.
+            set_slot_in_payload(&mut data.payload, slot)?;
+            efivar.write_data(&data)
         }
         Err(Error::OpenFile { path: _, source: _ }) => {
             // in this case the efivar does not exist yet because and needs to be created.
-            let mut buffer = Vec::from(NEXT_BOOT_SLOT_NEW_BUFFER);
-            set_slot_in_buffer(&mut buffer, slot)?;
-            efivar.create_and_write(&buffer)
+            let mut payload = vec![0; EXPECTED_LEN];
+            set_slot_in_payload(&mut payload, slot)?;
+            efivar.create_and_write_data(&EfiVarData::new(
+                EfiVarAttributes::NV_BS_RT,
+                payload,
+            ))
         }
         Err(err) => Err(err),
     }
@@ -89,50 +111,54 @@ mod tests {
         [0x07, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00];
 
     #[test]
-    fn test_get_slot_from_buffer() -> Result<()> {
+    fn test_get_slot_from_payload() -> Result<()> {
         // Read Slot A from configured Slot A.
-        let buffer = Vec::from(EFIVAR_BUFFER_BOOT_SLOT_A);
-        let slot = get_slot_from_buffer(&buffer)?;
+        let data = EfiVarData::parse(&EFIVAR_BUFFER_BOOT_SLOT_A)?;
+        let slot = get_slot_from_payload(&data.payload)?;
         assert_eq!(slot, SLOT_A, "Read unexpected next boot slot");
 
         // Read Slot B from configured Slot B.
-        let buffer = Vec::from(EFIVAR_BUFFER_BOOT_SLOT_B);
-        let slot = get_slot_from_buffer(&buffer)?;
+        let data = EfiVarData::parse(&EFIVAR_BUFFER_BOOT_SLOT_B)?;
+        let slot = get_slot_from_payload(&data.payload)?;
         assert_eq!(slot, SLOT_B, "Read unexpected next boot slot");
         Ok(())
     }
 
     #[test]
-    fn test_set_slot_in_buffer() -> Result<()> {
+    fn test_set_slot_in_payload() -> Result<()> {
         // Set Slot A again on already configured Slot A.
-        let mut buffer = Vec::from(EFIVAR_BUFFER_BOOT_SLOT_A);
-        set_slot_in_buffer(&mut buffer, SLOT_A)?;
+        let mut data = EfiVarData::parse(&EFIVAR_BUFFER_BOOT_SLOT_A)?;
+        set_slot_in_payload(&mut data.payload, SLOT_A)?;
         assert_eq!(
-            buffer, EFIVAR_BUFFER_BOOT_SLOT_A,
+            data.to_bytes(),
+            EFIVAR_BUFFER_BOOT_SLOT_A,
             "Buffer was changed unexpectedly"
         );
 
         // Set Slot B again on already configured Slot B.
-        let mut buffer = Vec::from(EFIVAR_BUFFER_BOOT_SLOT_B);
-        set_slot_in_buffer(&mut buffer, SLOT_B)?;
+        let mut data = EfiVarData::parse(&EFIVAR_BUFFER_BOOT_SLOT_B)?;
+        set_slot_in_payload(&mut data.payload, SLOT_B)?;
         assert_eq!(
-            buffer, EFIVAR_BUFFER_BOOT_SLOT_B,
+            data.to_bytes(),
+            EFIVAR_BUFFER_BOOT_SLOT_B,
             "Buffer was changed unexpectedly"
         );
 
         // Set Slot B on configured Slot A.
-        let mut buffer = Vec::from(EFIVAR_BUFFER_BOOT_SLOT_A);
-        set_slot_in_buffer(&mut buffer, SLOT_B)?;
+        let mut data = EfiVarData::parse(&EFIVAR_BUFFER_BOOT_SLOT_A)?;
+        set_slot_in_payload(&mut data.payload, SLOT_B)?;
         assert_eq!(
-            buffer, EFIVAR_BUFFER_BOOT_SLOT_B,
+            data.to_bytes(),
+            EFIVAR_BUFFER_BOOT_SLOT_B,
             "Buffer wasn't changed accordingly"
         );
 
         // Set Slot A on configured Slot B.
-        let mut buffer = Vec::from(EFIVAR_BUFFER_BOOT_SLOT_B);
-        set_slot_in_buffer(&mut buffer, SLOT_A)?;
+        let mut data = EfiVarData::parse(&EFIVAR_BUFFER_BOOT_SLOT_B)?;
+        set_slot_in_payload(&mut data.payload, SLOT_A)?;
         assert_eq!(
-            buffer, EFIVAR_BUFFER_BOOT_SLOT_A,
+            data.to_bytes(),
+            EFIVAR_BUFFER_BOOT_SLOT_A,
             "Buffer wasn't changed accordingly"
         );
         Ok(())
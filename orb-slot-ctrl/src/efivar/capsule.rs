@@ -0,0 +1,178 @@
+//! Capsule update efivars, as defined by the UEFI specification.
+//!
+//! * `OsIndications` - requests from the OS to the firmware for the next boot, e.g.
+//!   to process capsules on disk
+//! * `OsIndicationsSupported` - the `OsIndications` the firmware understands
+//!   (readonly)
+//! * `CapsuleLast` - name of the report efivar of the last processed capsule
+//!   (readonly)
+//! * `CapsuleNNNN` - report of a processed capsule, `NNNN` being a hex number
+//!   (readonly)
+
+use super::{EfiVar, EfiVarAttributes, EfiVarData, EfiVarStore};
+use crate::Error;
+
+/// `EFI_CAPSULE_REPORT_GUID` vendor guid.
+pub const REPORT_GUID: &str = "39b68c46-f7fb-441b-b6ec-16b0f69821f3";
+
+pub const PATH_OS_INDICATIONS: &str =
+    "OsIndications-8be4df61-93ca-11d2-aa0d-00e098032b8c";
+pub const PATH_OS_INDICATIONS_SUPPORTED: &str =
+    "OsIndicationsSupported-8be4df61-93ca-11d2-aa0d-00e098032b8c";
+pub const PATH_CAPSULE_LAST: &str = "CapsuleLast-39b68c46-f7fb-441b-b6ec-16b0f69821f3";
+
+/// `EFI_OS_INDICATIONS_FILE_CAPSULE_DELIVERY_SUPPORTED`, set to have the firmware
+/// process the capsules in `\EFI\UpdateCapsule` of the ESP on the next boot.
+pub const OS_INDICATIONS_FILE_CAPSULE_DELIVERY: u64 = 0x0000_0000_0000_0004;
+
+const OS_INDICATIONS_LEN: usize = 8;
+
+/// Gets `OsIndications`, `None` if it was never set.
+pub fn get_os_indications(store: &dyn EfiVarStore) -> Result<Option<u64>, Error> {
+    match EfiVar::new(store, PATH_OS_INDICATIONS).read_fixed_len(OS_INDICATIONS_LEN) {
+        Ok(efivar) => Ok(Some(u64_from_payload(&efivar.payload))),
+        Err(Error::OpenFile { .. }) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Gets `OsIndicationsSupported`, `None` if the firmware doesn't provide it.
+pub fn get_os_indications_supported(
+    store: &dyn EfiVarStore,
+) -> Result<Option<u64>, Error> {
+    match EfiVar::new(store, PATH_OS_INDICATIONS_SUPPORTED)
+        .read_fixed_len(OS_INDICATIONS_LEN)
+    {
+        Ok(efivar) => Ok(Some(u64_from_payload(&efivar.payload))),
+        Err(Error::OpenFile { .. }) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Sets `OsIndications`, keeping the attributes it already has.
+pub fn set_os_indications(store: &dyn EfiVarStore, value: u64) -> Result<(), Error> {
+    let efivar = EfiVar::new(store, PATH_OS_INDICATIONS);
+    match efivar.read_fixed_len(OS_INDICATIONS_LEN) {
+        Ok(mut data) => {
+            data.payload = value.to_le_bytes().to_vec();
+            efivar.write_data(&data)
+        }
+        Err(Error::OpenFile { .. }) => efivar.create_and_write_data(&EfiVarData::new(
+            EfiVarAttributes::NV_BS_RT,
+            value.to_le_bytes().to_vec(),
+        )),
+        Err(err) => Err(err),
+    }
+}
+
+/// Gets the name of the report of the last processed capsule, e.g. `Capsule0001`,
+/// `None` if no capsule was processed yet.
+pub fn get_capsule_last(store: &dyn EfiVarStore) -> Result<Option<String>, Error> {
+    let data = match EfiVar::new(store, PATH_CAPSULE_LAST).read_data() {
+        Ok(data) => data,
+        Err(Error::OpenFile { .. }) => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    // A CHAR16 string, without a terminating null.
+    if data.payload.len() % 2 != 0 {
+        return Err(Error::InvalidEfiVarLen);
+    }
+    let chars: Vec<u16> = data
+        .payload
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .take_while(|c| *c != 0)
+        .collect();
+    let name = String::from_utf16(&chars)
+        .map_err(|_| Error::InvalidCapsuleReport("name is not utf-16".to_owned()))?;
+    Ok(Some(name))
+}
+
+/// Gets the payload of the capsule report `name`, e.g. `Capsule0001`, `None` if it
+/// doesn't exist.
+pub fn get_capsule_report(
+    store: &dyn EfiVarStore,
+    name: &str,
+) -> Result<Option<Vec<u8>>, Error> {
+    let is_report_name = name.len() == "CapsuleNNNN".len()
+        && name.starts_with("Capsule")
+        && name["Capsule".len()..]
+            .chars()
+            .all(|c| c.is_ascii_hexdigit());
+    if !is_report_name {
+        return Err(Error::InvalidCapsuleReport(format!(
+            "{name} is not the name of a capsule report"
+        )));
+    }
+    match EfiVar::new(store, &format!("{name}-{REPORT_GUID}")).read_data() {
+        Ok(data) => Ok(Some(data.payload)),
+        Err(Error::OpenFile { .. }) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+fn u64_from_payload(payload: &[u8]) -> u64 {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&payload[..8]);
+    u64::from_le_bytes(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use eyre::Result;
+
+    use super::*;
+    use crate::InMemoryStore;
+
+    #[test]
+    fn test_os_indications() -> Result<()> {
+        let store = InMemoryStore::new();
+        assert_eq!(get_os_indications(&store)?, None);
+
+        set_os_indications(&store, OS_INDICATIONS_FILE_CAPSULE_DELIVERY)?;
+        assert_eq!(
+            store.vars()[PATH_OS_INDICATIONS],
+            [0x07, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]
+        );
+        assert_eq!(
+            get_os_indications(&store)?,
+            Some(OS_INDICATIONS_FILE_CAPSULE_DELIVERY)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_os_indications_supported() -> Result<()> {
+        let store = InMemoryStore::new();
+        assert_eq!(get_os_indications_supported(&store)?, None);
+
+        let buffer = [0x06, 0x00, 0x00, 0x00, 0x05, 0x00, 0x00, 0x00, 0, 0, 0, 0];
+        store.create_and_write(PATH_OS_INDICATIONS_SUPPORTED, &buffer)?;
+        assert_eq!(get_os_indications_supported(&store)?, Some(0x5));
+        Ok(())
+    }
+
+    #[test]
+    fn test_capsule_last() -> Result<()> {
+        let mut buffer = vec![0x07, 0x00, 0x00, 0x00];
+        buffer.extend("Capsule000A".encode_utf16().flat_map(u16::to_le_bytes));
+        let report = [0x07, 0x00, 0x00, 0x00, 0x30, 0x00, 0x00, 0x00];
+        let store = InMemoryStore::with_vars([
+            (PATH_CAPSULE_LAST, &buffer[..]),
+            (
+                "Capsule000A-39b68c46-f7fb-441b-b6ec-16b0f69821f3",
+                &report[..],
+            ),
+        ]);
+
+        let name = get_capsule_last(&store)?.unwrap();
+        assert_eq!(name, "Capsule000A");
+        assert_eq!(
+            get_capsule_report(&store, &name)?.as_deref(),
+            Some(&report[4..])
+        );
+        assert_eq!(get_capsule_report(&store, "Capsule000B")?, None);
+        assert!(get_capsule_report(&store, "OsIndications").is_err());
+        Ok(())
+    }
+}
@@ -7,18 +7,17 @@
 //!
 //! [efivar Documentation](https://www.kernel.org/doc/html/latest/filesystems/efivarfs.html)
 
-use std::{
-    fs::File,
-    io::{Read, Write},
-    path::{Path, PathBuf},
-};
-
 pub mod bootchain;
+pub mod capsule;
 pub mod rootfs;
+pub mod store;
+
+use bitflags::bitflags;
 
-use crate::ioctl;
 use crate::Error;
 
+pub use self::store::{DirStore, EfiVarStore, Efivarfs, InMemoryStore};
+
 // Slots.
 pub const SLOT_A: u8 = 0;
 pub const SLOT_B: u8 = 1;
@@ -29,102 +28,151 @@ pub const ROOTFS_STATUS_UPD_IN_PROCESS: u8 = 1;
 pub const ROOTFS_STATUS_UPD_DONE: u8 = 2;
 pub const ROOTFS_STATUS_UNBOOTABLE: u8 = 3;
 
-const EFIVARS_PATH: &str = "/sys/firmware/efi/efivars/";
-/// Efivar representation.
-pub struct EfiVar {
-    // Path to efivar.
-    path: PathBuf,
-}
+/// Length of the attribute header that precedes the data of every efivar.
+pub const ATTRIBUTES_LEN: usize = 4;
 
-impl EfiVar {
-    /// Construct a new `EfiVar` from a `path`. If the path is relative, it is relative to the efivarfs.
+bitflags! {
+    /// Attribute flags of an efivar, as defined by the UEFI specification.
     ///
-    /// # Panics
+    /// Bits without a name are kept as they are, so that writing back the
+    /// attributes of a variable that was read never changes them.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct EfiVarAttributes: u32 {
+        /// `EFI_VARIABLE_NON_VOLATILE`
+        const NON_VOLATILE = 0x0000_0001;
+        /// `EFI_VARIABLE_BOOTSERVICE_ACCESS`
+        const BOOTSERVICE_ACCESS = 0x0000_0002;
+        /// `EFI_VARIABLE_RUNTIME_ACCESS`
+        const RUNTIME_ACCESS = 0x0000_0004;
+        /// `EFI_VARIABLE_HARDWARE_ERROR_RECORD`
+        const HARDWARE_ERROR_RECORD = 0x0000_0008;
+        /// `EFI_VARIABLE_TIME_BASED_AUTHENTICATED_WRITE_ACCESS`
+        const TIME_BASED_AUTHENTICATED_WRITE_ACCESS = 0x0000_0020;
+        /// `EFI_VARIABLE_APPEND_WRITE`
+        const APPEND_WRITE = 0x0000_0040;
+
+        const _ = !0;
+    }
+}
+
+impl EfiVarAttributes {
+    /// Non-volatile, and accessible both during boot and from the OS. All boot
+    /// state efivars have these attributes.
+    pub const NV_BS_RT: Self = Self::NON_VOLATILE
+        .union(Self::BOOTSERVICE_ACCESS)
+        .union(Self::RUNTIME_ACCESS);
+}
+
+/// The contents of an efivar, split into the attribute header and the data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EfiVarData {
+    /// The attributes of the efivar.
+    pub attributes: EfiVarAttributes,
+    /// The data following the attributes.
+    pub payload: Vec<u8>,
+}
+
+impl EfiVarData {
+    /// Contents with the given `attributes` and `payload`.
+    #[must_use]
+    pub fn new(attributes: EfiVarAttributes, payload: Vec<u8>) -> Self {
+        Self {
+            attributes,
+            payload,
+        }
+    }
+
+    /// Parse the contents of an efivar as read from the efivarfs.
     ///
-    /// If the `path` is an absolute path outside of the efivarfs.
-    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, Error> {
-        let path = PathBuf::from(EFIVARS_PATH).join(path);
-        assert!(
-            path.starts_with(EFIVARS_PATH),
-            "Absolute path must be inside efivarfs"
-        );
+    /// Errors: `InvalidEfiVarLen` if the buffer is too short to hold the attributes.
+    pub fn parse(buffer: &[u8]) -> Result<Self, Error> {
+        let [a, b, c, d, payload @ ..] = buffer else {
+            return Err(Error::InvalidEfiVarLen);
+        };
+        let attributes = u32::from_le_bytes([*a, *b, *c, *d]);
+        Ok(Self {
+            attributes: EfiVarAttributes::from_bits_retain(attributes),
+            payload: payload.to_vec(),
+        })
+    }
+
+    /// The contents as written to the efivarfs, attributes first.
+    #[must_use]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buffer = Vec::with_capacity(ATTRIBUTES_LEN + self.payload.len());
+        buffer.extend_from_slice(&self.attributes.bits().to_le_bytes());
+        buffer.extend_from_slice(&self.payload);
+        buffer
+    }
+}
+
+/// Efivar representation.
+pub struct EfiVar<'a> {
+    store: &'a dyn EfiVarStore,
+    // Name of the efivar, including the vendor guid.
+    name: &'a str,
+}
 
-        Ok(EfiVar { path })
+impl<'a> EfiVar<'a> {
+    /// Construct a new `EfiVar` for the variable `name` in `store`.
+    pub fn new(store: &'a dyn EfiVarStore, name: &'a str) -> Self {
+        EfiVar { store, name }
     }
 
-    /// Read the efivar data from a `path`.
+    /// Read the raw efivar, attribute header included.
     ///
-    /// Errors: i/o specific on file operations and `InvalidEfiVarLen` if the data length is invalid.
+    /// Errors: i/o specific on file operations.
     pub fn read(&self) -> Result<Vec<u8>, Error> {
-        let mut file =
-            File::open(&self.path).map_err(|e| Error::open_file(&self.path, e))?;
-        let mut buffer: Vec<u8> = vec![];
-        file.read_to_end(&mut buffer)
-            .map_err(|e| Error::read_file(&self.path, e))?;
-        Ok(buffer)
+        self.store.read(self.name)
     }
 
-    /// Read the efivar data from a `path`.
-    /// Validates the expected data length and saves the data to a `buffer`.
+    /// Read the efivar and split it into attributes and payload.
     ///
-    pub fn read_fixed_len(&self, expected_length: usize) -> Result<Vec<u8>, Error> {
-        let buf = self.read()?;
-        is_valid_buffer(&buf, expected_length)?;
-        Ok(buf)
+    /// Errors: i/o specific on file operations and `InvalidEfiVarLen` if the data length is invalid.
+    pub fn read_data(&self) -> Result<EfiVarData, Error> {
+        EfiVarData::parse(&self.read()?)
+    }
+
+    /// Read the efivar and split it into attributes and payload.
+    /// Validates the expected length of the payload.
+    pub fn read_fixed_len(&self, payload_length: usize) -> Result<EfiVarData, Error> {
+        let data = self.read_data()?;
+        is_valid_buffer(&data.payload, payload_length)?;
+        Ok(data)
     }
-    /// Validates the expected data length and writes the current buffer.
+
+    /// Writes the raw `buffer`, attribute header included, to an existing efivar.
     ///
-    /// Errors: i/o specific `Error`s on file operations and `InvalidEfiVarLen` if the data length is invalid.
-    pub fn write(self, buffer: &[u8]) -> Result<(), Error> {
-        let file_read =
-            File::open(&self.path).map_err(|e| Error::open_file(&self.path, e))?;
-
-        let original_attributes: libc::c_int =
-            ioctl::read_file_attributes(&file_read).map_err(Error::GetAttributes)?;
-
-        // Make file mutable.
-        let new_attributes = original_attributes & !ioctl::IMMUTABLE_MASK;
-        ioctl::write_file_attributes(&file_read, new_attributes)
-            .map_err(Error::MakeMutable)?;
-
-        // Open file for writing and write buffer.
-        let file_write = File::options()
-            .write(true)
-            .open(&self.path)
-            .map_err(|e| Error::open_write_file(&self.path, e))?;
-        (&file_write)
-            .write_all(buffer)
-            .map_err(|e| Error::write_file(&self.path, e))?;
-        (&file_write)
-            .flush()
-            .map_err(|e| Error::flush_file(&self.path, e))?;
-
-        // Make file immutable again.
-        ioctl::write_file_attributes(&file_read, original_attributes)
-            .map_err(Error::MakeImmutable)?;
+    /// Errors: i/o specific `Error`s on file operations.
+    pub fn write(&self, buffer: &[u8]) -> Result<(), Error> {
+        self.store.write(self.name, buffer)
+    }
 
-        Ok(())
+    /// Writes `data` to an existing efivar. Pass the attributes it was read with to
+    /// preserve them.
+    ///
+    /// Errors: i/o specific `Error`s on file operations.
+    pub fn write_data(&self, data: &EfiVarData) -> Result<(), Error> {
+        self.write(&data.to_bytes())
     }
 
-    /// Create a new efivar and write the `buffer`.
+    /// Create a new efivar and write the raw `buffer`, attribute header included.
     ///
-    /// Errors: i/o specific `Error`s on file operations and `InvalidEfiVarLen` if the data length is invalid.
+    /// Errors: i/o specific `Error`s on file operations.
     pub fn create_and_write(&self, buffer: &[u8]) -> Result<(), Error> {
-        let inner_file =
-            File::create(&self.path).map_err(|e| Error::create_file(&self.path, e))?;
-        (&inner_file)
-            .write_all(buffer)
-            .map_err(|e| Error::write_file(&self.path, e))?;
-        (&inner_file)
-            .flush()
-            .map_err(|e| Error::flush_file(&self.path, e))?;
-        Ok(())
+        self.store.create_and_write(self.name, buffer)
+    }
+
+    /// Create a new efivar with the attributes and payload of `data`.
+    ///
+    /// Errors: i/o specific `Error`s on file operations.
+    pub fn create_and_write_data(&self, data: &EfiVarData) -> Result<(), Error> {
+        self.create_and_write(&data.to_bytes())
     }
 
     /// Remove UEFI variable
     pub fn remove(&self) -> Result<(), Error> {
-        std::fs::remove_file(&self.path)
-            .map_err(|e| Error::remove_efi_var(&self.path, e))
+        self.store.remove(self.name)
     }
 }
 
@@ -135,3 +183,37 @@ fn is_valid_buffer(buffer: &[u8], expected_length: usize) -> Result<(), Error> {
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use eyre::Result;
+
+    use super::*;
+
+    #[test]
+    fn test_efivar_data() -> Result<()> {
+        let data =
+            EfiVarData::parse(&[0x07, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00])?;
+        assert_eq!(data.attributes, EfiVarAttributes::NV_BS_RT);
+        assert_eq!(data.payload, [0x01, 0x00, 0x00, 0x00]);
+        assert_eq!(
+            data.to_bytes(),
+            [0x07, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00]
+        );
+
+        let buffer = [0x27, 0x00, 0x00, 0x80];
+        let data = EfiVarData::parse(&buffer)?;
+        assert!(data.attributes.contains(
+            EfiVarAttributes::NV_BS_RT
+                | EfiVarAttributes::TIME_BASED_AUTHENTICATED_WRITE_ACCESS
+        ));
+        assert!(data.payload.is_empty());
+        assert_eq!(data.to_bytes(), buffer, "unknown bits are preserved");
+
+        assert!(matches!(
+            EfiVarData::parse(&[0x07, 0x00, 0x00]),
+            Err(Error::InvalidEfiVarLen)
+        ));
+        Ok(())
+    }
+}
@@ -6,11 +6,12 @@
 //! * `RootfsRetryCountB` - represents the boot retry count of the rootfs in slot B
 //! * `RootfsRetryCountMax` - represents the maximum boot retry count
 //!
-//! Bits of interest are found in byte 4 for all efivars.
+//! Bits of interest are found in the first byte of the payload, after the attributes,
+//! for all efivars.
 
 use super::{
-    is_valid_buffer, EfiVar, ROOTFS_STATUS_NORMAL, ROOTFS_STATUS_UNBOOTABLE,
-    ROOTFS_STATUS_UPD_DONE, ROOTFS_STATUS_UPD_IN_PROCESS,
+    is_valid_buffer, EfiVar, EfiVarStore, ROOTFS_STATUS_NORMAL,
+    ROOTFS_STATUS_UNBOOTABLE, ROOTFS_STATUS_UPD_DONE, ROOTFS_STATUS_UPD_IN_PROCESS,
 };
 use super::{SLOT_A, SLOT_B};
 use crate::Error;
@@ -26,7 +27,7 @@ pub const PATH_RETRY_COUNT_B: &str =
 pub const PATH_RETRY_COUNT_MAX: &str =
     "RootfsRetryCountMax-781e084c-a330-417c-b678-38e696380cb9";
 
-const EXPECTED_LEN: usize = 8;
+const EXPECTED_LEN: usize = 4;
 
 /// Throws an `Error` if the given rootfs status is invalid.
 fn is_valid_rootfs_status(status: u8) -> Result<(), Error> {
@@ -40,8 +41,8 @@ fn is_valid_rootfs_status(status: u8) -> Result<(), Error> {
 }
 
 /// Throws an `Error` if the given retry count is exceeding the maximum.
-fn is_valid_retry_count(count: u8) -> Result<(), Error> {
-    let max_count = get_max_retry_count()?;
+fn is_valid_retry_count(store: &dyn EfiVarStore, count: u8) -> Result<(), Error> {
+    let max_count = get_max_retry_count(store)?;
     if count > max_count {
         return Err(Error::ExceedingRetryCount {
             counter: count,
@@ -51,73 +52,81 @@ fn is_valid_retry_count(count: u8) -> Result<(), Error> {
     Ok(())
 }
 
-// Get the information of interest from a `buffer`s byte 4.
-fn parse_buffer(buffer: &[u8]) -> Result<u8, Error> {
-    is_valid_buffer(buffer, EXPECTED_LEN)?;
-    Ok(buffer[4])
+// Get the information of interest from a `payload`s first byte.
+fn parse_payload(payload: &[u8]) -> Result<u8, Error> {
+    is_valid_buffer(payload, EXPECTED_LEN)?;
+    Ok(payload[0])
 }
 
-// Set the value in a `buffer`s byte 4.
-fn set_value_in_buffer(buffer: &mut Vec<u8>, value: u8) -> Result<(), Error> {
-    is_valid_buffer(&*buffer, EXPECTED_LEN)?;
-    buffer[4] = value;
+// Set the value in a `payload`s first byte.
+fn set_value_in_payload(payload: &mut [u8], value: u8) -> Result<(), Error> {
+    is_valid_buffer(payload, EXPECTED_LEN)?;
+    payload[0] = value;
     Ok(())
 }
 
 /// Get the raw rootfs status for a certain `slot`.
-pub fn get_rootfs_status(slot: u8) -> Result<u8, Error> {
+pub fn get_rootfs_status(store: &dyn EfiVarStore, slot: u8) -> Result<u8, Error> {
     let efivar = match slot {
-        SLOT_A => EfiVar::from_path(PATH_STATUS_A)?,
-        SLOT_B => EfiVar::from_path(PATH_STATUS_B)?,
+        SLOT_A => EfiVar::new(store, PATH_STATUS_A),
+        SLOT_B => EfiVar::new(store, PATH_STATUS_B),
         _ => return Err(Error::InvalidSlotData),
     };
-    let status = parse_buffer(&efivar.read_fixed_len(EXPECTED_LEN)?)?;
+    let status = parse_payload(&efivar.read_fixed_len(EXPECTED_LEN)?.payload)?;
     is_valid_rootfs_status(status)?;
     Ok(status)
 }
 
 /// Get the retry count for a certain `slot`.
-pub fn get_retry_count(slot: u8) -> Result<u8, Error> {
+pub fn get_retry_count(store: &dyn EfiVarStore, slot: u8) -> Result<u8, Error> {
     let efivar = match slot {
-        SLOT_A => EfiVar::from_path(PATH_RETRY_COUNT_A)?,
-        SLOT_B => EfiVar::from_path(PATH_RETRY_COUNT_B)?,
+        SLOT_A => EfiVar::new(store, PATH_RETRY_COUNT_A),
+        SLOT_B => EfiVar::new(store, PATH_RETRY_COUNT_B),
         _ => return Err(Error::InvalidSlotData),
     };
-    let retry_count = parse_buffer(&efivar.read_fixed_len(EXPECTED_LEN)?)?;
-    is_valid_retry_count(retry_count)?;
+    let retry_count = parse_payload(&efivar.read_fixed_len(EXPECTED_LEN)?.payload)?;
+    is_valid_retry_count(store, retry_count)?;
     Ok(retry_count)
 }
 
 /// Get the maximum retry count.
-pub fn get_max_retry_count() -> Result<u8, Error> {
-    let efivar = EfiVar::from_path(PATH_RETRY_COUNT_MAX)?;
-    parse_buffer(&efivar.read_fixed_len(EXPECTED_LEN)?)
+pub fn get_max_retry_count(store: &dyn EfiVarStore) -> Result<u8, Error> {
+    let efivar = EfiVar::new(store, PATH_RETRY_COUNT_MAX);
+    parse_payload(&efivar.read_fixed_len(EXPECTED_LEN)?.payload)
 }
 
 /// Set raw rootfs `status` for a certain `slot`.
-pub fn set_rootfs_status(status: u8, slot: u8) -> Result<(), Error> {
+pub fn set_rootfs_status(
+    store: &dyn EfiVarStore,
+    status: u8,
+    slot: u8,
+) -> Result<(), Error> {
     is_valid_rootfs_status(status)?;
     let efivar = match slot {
-        SLOT_A => EfiVar::from_path(PATH_STATUS_A)?,
-        SLOT_B => EfiVar::from_path(PATH_STATUS_B)?,
+        SLOT_A => EfiVar::new(store, PATH_STATUS_A),
+        SLOT_B => EfiVar::new(store, PATH_STATUS_B),
         _ => return Err(Error::InvalidSlotData),
     };
-    let mut buf = efivar.read_fixed_len(EXPECTED_LEN)?;
-    set_value_in_buffer(&mut buf, status)?;
-    efivar.write(&buf)
+    let mut data = efivar.read_fixed_len(EXPECTED_LEN)?;
+    set_value_in_payload(&mut data.payload, status)?;
+    efivar.write_data(&data)
 }
 
 /// Set the retry `counter` for a certain `slot`.
-pub fn set_retry_count(counter: u8, slot: u8) -> Result<(), Error> {
-    is_valid_retry_count(counter)?;
+pub fn set_retry_count(
+    store: &dyn EfiVarStore,
+    counter: u8,
+    slot: u8,
+) -> Result<(), Error> {
+    is_valid_retry_count(store, counter)?;
     let efivar = match slot {
-        SLOT_A => EfiVar::from_path(PATH_RETRY_COUNT_A)?,
-        SLOT_B => EfiVar::from_path(PATH_RETRY_COUNT_B)?,
+        SLOT_A => EfiVar::new(store, PATH_RETRY_COUNT_A),
+        SLOT_B => EfiVar::new(store, PATH_RETRY_COUNT_B),
         _ => return Err(Error::InvalidSlotData),
     };
-    let mut buf = efivar.read_fixed_len(EXPECTED_LEN)?;
-    set_value_in_buffer(&mut buf, counter)?;
-    efivar.write(&buf)
+    let mut data = efivar.read_fixed_len(EXPECTED_LEN)?;
+    set_value_in_payload(&mut data.payload, counter)?;
+    efivar.write_data(&data)
 }
 
 #[cfg(test)]
@@ -125,6 +134,7 @@ mod tests {
     // Unit testing only buffer based operations.
     use eyre::Result;
 
+    use super::super::EfiVarData;
     use super::*;
 
     const ROOTFS_STATUS_NORMAL_DATA: [u8; 8] =
@@ -137,8 +147,8 @@ mod tests {
         [0x07, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00];
 
     fn assert_rootfs_status(status: u8, data: [u8; 8]) -> Result<()> {
-        let buffer = Vec::from(data);
-        let read_status = parse_buffer(&buffer)?;
+        let data = EfiVarData::parse(&data)?;
+        let read_status = parse_payload(&data.payload)?;
         assert_eq!(read_status, status, "Read unexpected rootfs status");
         Ok(())
     }
@@ -166,8 +176,8 @@ mod tests {
         [0x07, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00];
 
     fn assert_retry_count(count: u8, data: [u8; 8]) -> Result<()> {
-        let buffer = Vec::from(data);
-        let current_count = parse_buffer(&buffer)?;
+        let data = EfiVarData::parse(&data)?;
+        let current_count = parse_payload(&data.payload)?;
         assert_eq!(current_count, count, "Read unexpected retry count");
         Ok(())
     }
@@ -196,9 +206,9 @@ mod tests {
         ];
         for (_, original_data) in test_data {
             for (new_status, _) in test_data {
-                let mut buffer = Vec::from(original_data);
-                set_value_in_buffer(&mut buffer, new_status)?;
-                let data_status = parse_buffer(&buffer)?;
+                let mut data = EfiVarData::parse(&original_data)?;
+                set_value_in_payload(&mut data.payload, new_status)?;
+                let data_status = parse_payload(&data.payload)?;
                 assert_eq!(
                     new_status, data_status,
                     "Rootfs status unexpected after set"
@@ -219,9 +229,9 @@ mod tests {
         ];
         for original_data in test_data {
             for new_retry in 0..3_u8 {
-                let mut buffer = Vec::from(original_data);
-                set_value_in_buffer(&mut buffer, new_retry)?;
-                let data_counter = parse_buffer(&buffer)?;
+                let mut data = EfiVarData::parse(&original_data)?;
+                set_value_in_payload(&mut data.payload, new_retry)?;
+                let data_counter = parse_payload(&data.payload)?;
                 assert_eq!(new_retry, data_counter, "Retry count unexpected after set");
             }
         }
@@ -0,0 +1,303 @@
+//! Backends that efivars are read from and written to.
+//!
+//! * [`Efivarfs`] - the efivarfs of the running system
+//! * [`DirStore`] - a plain directory, e.g. an efivarfs dump pulled from a device
+//! * [`InMemoryStore`] - variables held in memory, for tests
+
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use crate::ioctl;
+use crate::Error;
+
+/// Default mount point of the efivarfs.
+pub const EFIVARS_PATH: &str = "/sys/firmware/efi/efivars/";
+
+/// Storage for efivars, addressed by their name including the vendor guid, e.g.
+/// `BootChainFwCurrent-781e084c-a330-417c-b678-38e696380cb9`.
+///
+/// Missing variables are reported as [`Error::OpenFile`].
+pub trait EfiVarStore: Send + Sync {
+    /// Read the full contents of the efivar `name`.
+    fn read(&self, name: &str) -> Result<Vec<u8>, Error>;
+
+    /// Overwrite the contents of the existing efivar `name`.
+    fn write(&self, name: &str, buffer: &[u8]) -> Result<(), Error>;
+
+    /// Create the efivar `name` with the contents `buffer`.
+    fn create_and_write(&self, name: &str, buffer: &[u8]) -> Result<(), Error>;
+
+    /// Remove the efivar `name`.
+    fn remove(&self, name: &str) -> Result<(), Error>;
+}
+
+impl<S: EfiVarStore + ?Sized> EfiVarStore for Arc<S> {
+    fn read(&self, name: &str) -> Result<Vec<u8>, Error> {
+        self.as_ref().read(name)
+    }
+
+    fn write(&self, name: &str, buffer: &[u8]) -> Result<(), Error> {
+        self.as_ref().write(name, buffer)
+    }
+
+    fn create_and_write(&self, name: &str, buffer: &[u8]) -> Result<(), Error> {
+        self.as_ref().create_and_write(name, buffer)
+    }
+
+    fn remove(&self, name: &str) -> Result<(), Error> {
+        self.as_ref().remove(name)
+    }
+}
+
+/// The efivarfs of the running system.
+///
+/// Efivars are frequently marked immutable, so the flag is cleared for the
+/// duration of a write.
+#[derive(Debug, Clone)]
+pub struct Efivarfs {
+    dir: PathBuf,
+}
+
+impl Efivarfs {
+    /// The efivarfs mounted at `/sys/firmware/efi/efivars/`.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::at(EFIVARS_PATH)
+    }
+
+    /// An efivarfs mounted at `dir`.
+    pub fn at(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+}
+
+impl Default for Efivarfs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EfiVarStore for Efivarfs {
+    fn read(&self, name: &str) -> Result<Vec<u8>, Error> {
+        read_file(&self.dir.join(name))
+    }
+
+    fn write(&self, name: &str, buffer: &[u8]) -> Result<(), Error> {
+        let path = self.dir.join(name);
+        let file_read = File::open(&path).map_err(|e| Error::open_file(&path, e))?;
+
+        let original_attributes: libc::c_int =
+            ioctl::read_file_attributes(&file_read).map_err(Error::GetAttributes)?;
+
+        // Make file mutable.
+        let new_attributes = original_attributes & !ioctl::IMMUTABLE_MASK;
+        ioctl::write_file_attributes(&file_read, new_attributes)
+            .map_err(Error::MakeMutable)?;
+
+        // Open file for writing and write buffer.
+        let file_write = File::options()
+            .write(true)
+            .open(&path)
+            .map_err(|e| Error::open_write_file(&path, e))?;
+        write_all(&path, &file_write, buffer)?;
+
+        // Make file immutable again.
+        ioctl::write_file_attributes(&file_read, original_attributes)
+            .map_err(Error::MakeImmutable)?;
+
+        Ok(())
+    }
+
+    fn create_and_write(&self, name: &str, buffer: &[u8]) -> Result<(), Error> {
+        create_file(&self.dir.join(name), buffer)
+    }
+
+    fn remove(&self, name: &str) -> Result<(), Error> {
+        remove_file(&self.dir.join(name))
+    }
+}
+
+/// A plain directory with one file per efivar, laid out like the efivarfs. Used
+/// to inspect efivar dumps off-device.
+#[derive(Debug, Clone)]
+pub struct DirStore {
+    dir: PathBuf,
+}
+
+impl DirStore {
+    /// Efivars in `dir`.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+}
+
+impl EfiVarStore for DirStore {
+    fn read(&self, name: &str) -> Result<Vec<u8>, Error> {
+        read_file(&self.dir.join(name))
+    }
+
+    fn write(&self, name: &str, buffer: &[u8]) -> Result<(), Error> {
+        let path = self.dir.join(name);
+        let file = File::options()
+            .write(true)
+            .truncate(true)
+            .open(&path)
+            .map_err(|e| Error::open_file(&path, e))?;
+        write_all(&path, &file, buffer)
+    }
+
+    fn create_and_write(&self, name: &str, buffer: &[u8]) -> Result<(), Error> {
+        create_file(&self.dir.join(name), buffer)
+    }
+
+    fn remove(&self, name: &str) -> Result<(), Error> {
+        remove_file(&self.dir.join(name))
+    }
+}
+
+/// Efivars held in memory.
+#[derive(Debug, Default)]
+pub struct InMemoryStore {
+    vars: Mutex<BTreeMap<String, Vec<u8>>>,
+}
+
+impl InMemoryStore {
+    /// An empty store.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A store containing `vars`, given as pairs of name and contents.
+    pub fn with_vars<'a>(vars: impl IntoIterator<Item = (&'a str, &'a [u8])>) -> Self {
+        let vars = vars
+            .into_iter()
+            .map(|(name, buffer)| (name.to_owned(), buffer.to_vec()))
+            .collect();
+        Self {
+            vars: Mutex::new(vars),
+        }
+    }
+
+    /// A copy of all efivars, by name.
+    ///
+    /// # Panics
+    ///
+    /// If another user of the store panicked.
+    #[must_use]
+    pub fn vars(&self) -> BTreeMap<String, Vec<u8>> {
+        self.vars.lock().unwrap().clone()
+    }
+}
+
+impl EfiVarStore for InMemoryStore {
+    fn read(&self, name: &str) -> Result<Vec<u8>, Error> {
+        self.vars
+            .lock()
+            .unwrap()
+            .get(name)
+            .cloned()
+            .ok_or_else(|| Error::open_file(name, io::ErrorKind::NotFound.into()))
+    }
+
+    fn write(&self, name: &str, buffer: &[u8]) -> Result<(), Error> {
+        let mut vars = self.vars.lock().unwrap();
+        let var = vars
+            .get_mut(name)
+            .ok_or_else(|| Error::open_file(name, io::ErrorKind::NotFound.into()))?;
+        *var = buffer.to_vec();
+        Ok(())
+    }
+
+    fn create_and_write(&self, name: &str, buffer: &[u8]) -> Result<(), Error> {
+        self.vars
+            .lock()
+            .unwrap()
+            .insert(name.to_owned(), buffer.to_vec());
+        Ok(())
+    }
+
+    fn remove(&self, name: &str) -> Result<(), Error> {
+        self.vars
+            .lock()
+            .unwrap()
+            .remove(name)
+            .map(|_| ())
+            .ok_or_else(|| Error::remove_efi_var(name, io::ErrorKind::NotFound.into()))
+    }
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>, Error> {
+    let mut file = File::open(path).map_err(|e| Error::open_file(path, e))?;
+    let mut buffer: Vec<u8> = vec![];
+    file.read_to_end(&mut buffer)
+        .map_err(|e| Error::read_file(path, e))?;
+    Ok(buffer)
+}
+
+fn create_file(path: &Path, buffer: &[u8]) -> Result<(), Error> {
+    let file = File::create(path).map_err(|e| Error::create_file(path, e))?;
+    write_all(path, &file, buffer)
+}
+
+fn write_all(path: &Path, mut file: &File, buffer: &[u8]) -> Result<(), Error> {
+    file.write_all(buffer)
+        .map_err(|e| Error::write_file(path, e))?;
+    file.flush().map_err(|e| Error::flush_file(path, e))
+}
+
+fn remove_file(path: &Path) -> Result<(), Error> {
+    std::fs::remove_file(path).map_err(|e| Error::remove_efi_var(path, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use eyre::Result;
+
+    use super::*;
+
+    const NAME: &str = "RootfsStatusSlotA-781e084c-a330-417c-b678-38e696380cb9";
+    const DATA: [u8; 8] = [0x07, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00];
+
+    // Exercise a store through the whole lifecycle of an efivar.
+    fn check_store(store: &dyn EfiVarStore) -> Result<()> {
+        assert!(matches!(store.read(NAME), Err(Error::OpenFile { .. })));
+        assert!(
+            store.write(NAME, &DATA).is_err(),
+            "writing requires an existing efivar"
+        );
+
+        store.create_and_write(NAME, &DATA)?;
+        assert_eq!(store.read(NAME)?, DATA);
+        store.write(NAME, &DATA[..4])?;
+        assert_eq!(
+            store.read(NAME)?,
+            DATA[..4],
+            "write should replace contents"
+        );
+
+        store.remove(NAME)?;
+        assert!(matches!(store.read(NAME), Err(Error::OpenFile { .. })));
+        Ok(())
+    }
+
+    #[test]
+    fn test_in_memory_store() -> Result<()> {
+        check_store(&InMemoryStore::new())
+    }
+
+    #[test]
+    fn test_dir_store() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        check_store(&DirStore::new(dir.path()))?;
+
+        std::fs::write(dir.path().join(NAME), DATA)?;
+        assert_eq!(DirStore::new(dir.path()).read(NAME)?, DATA);
+        Ok(())
+    }
+}
@@ -0,0 +1,229 @@
+//! Bootloader state beyond the slots: the status of the NVIDIA bootloader chain
+//! and UEFI capsule updates.
+//!
+//! Useful to diagnose cases where the bootloader chain and the rootfs slot diverge,
+//! e.g. because a capsule update of the bootloader failed.
+
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::efivar::{bootchain, capsule};
+use crate::{to_hex, EfiVarStore, Error};
+
+/// Status of the last bootloader chain switch or update, set by the `BootChainDxe`
+/// driver of the NVIDIA edk2.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum FwStatus {
+    /// The last switch or update succeeded.
+    Success,
+    /// A switch or update is in progress.
+    InProgress,
+    /// The last switch or update failed, with one of the driver's
+    /// `STATUS_ERROR_*` codes.
+    Error(u32),
+}
+
+impl From<u32> for FwStatus {
+    fn from(value: u32) -> Self {
+        match value {
+            0 => FwStatus::Success,
+            1 => FwStatus::InProgress,
+            code => FwStatus::Error(code),
+        }
+    }
+}
+
+impl fmt::Display for FwStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FwStatus::Success => write!(f, "success"),
+            FwStatus::InProgress => write!(f, "in progress"),
+            FwStatus::Error(code) => write!(f, "error {code}"),
+        }
+    }
+}
+
+/// Result of a processed capsule, from an `EFI_CAPSULE_RESULT_VARIABLE_HEADER`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CapsuleResult {
+    /// Name of the report efivar, e.g. `Capsule0001`.
+    pub name: String,
+    /// Guid of the processed capsule.
+    pub capsule_guid: String,
+    /// When the capsule was processed, as `YYYY-MM-DDTHH:MM:SS` in firmware time.
+    pub processed: String,
+    /// The `EFI_STATUS` of processing the capsule, 0 on success.
+    pub status: u64,
+}
+
+impl CapsuleResult {
+    // `VariableTotalSize`, `Reserved`, `CapsuleGuid`, `CapsuleProcessed` and
+    // `CapsuleStatus`, the latter being 64 bit wide on the orb.
+    const HEADER_LEN: usize = 4 + 4 + 16 + 16 + 8;
+
+    /// Parse the payload of the capsule report efivar `name`.
+    pub fn parse(name: &str, payload: &[u8]) -> Result<Self, Error> {
+        if payload.len() < Self::HEADER_LEN {
+            return Err(Error::InvalidCapsuleReport(format!(
+                "{name} is too short ({} bytes)",
+                payload.len()
+            )));
+        }
+        let u16_at = |i: usize| u16::from_le_bytes([payload[i], payload[i + 1]]);
+        let guid = &payload[8..24];
+        let time = &payload[24..40];
+        let mut status = [0; 8];
+        status.copy_from_slice(&payload[40..48]);
+
+        Ok(Self {
+            name: name.to_owned(),
+            capsule_guid: format!(
+                "{:08x}-{:04x}-{:04x}-{}-{}",
+                u32::from_le_bytes([guid[0], guid[1], guid[2], guid[3]]),
+                u16_at(12),
+                u16_at(14),
+                to_hex(&guid[8..10]),
+                to_hex(&guid[10..16]),
+            ),
+            processed: format!(
+                "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+                u16_at(24),
+                time[2],
+                time[3],
+                time[4],
+                time[5],
+                time[6]
+            ),
+            status: u64::from_le_bytes(status),
+        })
+    }
+
+    /// Whether the capsule was processed successfully.
+    #[must_use]
+    pub fn succeeded(&self) -> bool {
+        self.status == 0
+    }
+}
+
+/// Get the status of the last bootloader chain switch or update, `None` if there
+/// was none yet.
+pub fn get_fw_status(store: &dyn EfiVarStore) -> Result<Option<FwStatus>, Error> {
+    Ok(bootchain::get_fw_status(store)?.map(FwStatus::from))
+}
+
+/// Get the result of the last processed capsule, `None` if there was none yet.
+pub fn get_last_capsule_result(
+    store: &dyn EfiVarStore,
+) -> Result<Option<CapsuleResult>, Error> {
+    let Some(name) = capsule::get_capsule_last(store)? else {
+        return Ok(None);
+    };
+    let Some(payload) = capsule::get_capsule_report(store, &name)? else {
+        return Ok(None);
+    };
+    CapsuleResult::parse(&name, &payload).map(Some)
+}
+
+/// Checks if the capsules on the ESP will be processed on the next boot.
+pub fn is_capsule_update_requested(store: &dyn EfiVarStore) -> Result<bool, Error> {
+    Ok(capsule::get_os_indications(store)?.is_some_and(|value| {
+        value & capsule::OS_INDICATIONS_FILE_CAPSULE_DELIVERY != 0
+    }))
+}
+
+/// Have the firmware process the capsules on the ESP on the next boot. Other
+/// `OsIndications` are kept.
+///
+/// Fails with [`Error::CapsuleDeliveryUnsupported`] if `OsIndicationsSupported`
+/// says the firmware wouldn't act on the request.
+pub fn request_capsule_update(store: &dyn EfiVarStore) -> Result<(), Error> {
+    let supported = capsule::get_os_indications_supported(store)?.unwrap_or(0);
+    if supported & capsule::OS_INDICATIONS_FILE_CAPSULE_DELIVERY == 0 {
+        return Err(Error::CapsuleDeliveryUnsupported);
+    }
+    let value = capsule::get_os_indications(store)?.unwrap_or(0);
+    capsule::set_os_indications(
+        store,
+        value | capsule::OS_INDICATIONS_FILE_CAPSULE_DELIVERY,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use eyre::Result;
+
+    use super::*;
+    use crate::tests::booted_from_a;
+
+    #[test]
+    fn test_fw_status() -> Result<()> {
+        let store = booted_from_a();
+        assert_eq!(get_fw_status(&store)?, None);
+
+        for (value, status) in [
+            (0, FwStatus::Success),
+            (1, FwStatus::InProgress),
+            (5, FwStatus::Error(5)),
+        ] {
+            store.create_and_write(
+                bootchain::PATH_STATUS,
+                &[0x07, 0x00, 0x00, 0x00, value, 0x00, 0x00, 0x00],
+            )?;
+            assert_eq!(get_fw_status(&store)?, Some(status));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_capsule_result() -> Result<()> {
+        #[rustfmt::skip]
+        let payload = [
+            // VariableTotalSize, Reserved
+            0x30, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            // CapsuleGuid
+            0x78, 0x56, 0x34, 0x12, 0x34, 0x12, 0x78, 0x56,
+            0x9a, 0xbc, 0xde, 0xf0, 0x12, 0x34, 0x56, 0x78,
+            // CapsuleProcessed: 2024-03-07 13:05:09
+            0xe8, 0x07, 0x03, 0x07, 0x0d, 0x05, 0x09, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            // CapsuleStatus: EFI_ABORTED
+            0x15, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x80,
+        ];
+        let result = CapsuleResult::parse("Capsule0000", &payload)?;
+        assert_eq!(
+            result,
+            CapsuleResult {
+                name: "Capsule0000".to_owned(),
+                capsule_guid: "12345678-1234-5678-9abc-def012345678".to_owned(),
+                processed: "2024-03-07T13:05:09".to_owned(),
+                status: 0x8000_0000_0000_0015,
+            }
+        );
+        assert!(!result.succeeded());
+        assert!(CapsuleResult::parse("Capsule0000", &payload[..40]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_request_capsule_update() -> Result<()> {
+        let store = booted_from_a();
+        assert!(!is_capsule_update_requested(&store)?);
+        capsule::set_os_indications(&store, 0x1)?;
+        assert!(matches!(
+            request_capsule_update(&store),
+            Err(Error::CapsuleDeliveryUnsupported)
+        ));
+        assert_eq!(capsule::get_os_indications(&store)?, Some(0x1));
+
+        store.create_and_write(
+            capsule::PATH_OS_INDICATIONS_SUPPORTED,
+            &[0x06, 0x00, 0x00, 0x00, 0x05, 0x00, 0x00, 0x00, 0, 0, 0, 0],
+        )?;
+
+        request_capsule_update(&store)?;
+        assert!(is_capsule_update_requested(&store)?);
+        assert_eq!(capsule::get_os_indications(&store)?, Some(0x5));
+        Ok(())
+    }
+}
@@ -0,0 +1,369 @@
+//! An append-only journal of all efivar mutations, to find out who flipped what
+//! when a device ends up in a boot loop.
+//!
+//! [`JournaledStore`] wraps the [`EfiVarStore`] that is actually written to, and
+//! records every successful write, creation and removal to a [`Journal`] as well
+//! as a `tracing` event.
+
+use std::{
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{to_hex, EfiVarStore, Error};
+
+/// Name of the journal file in the journal directory.
+pub const JOURNAL_FILE: &str = "efivars-journal.jsonl";
+
+/// Default directory of the journal, on the partition that survives updates.
+pub const DEFAULT_JOURNAL_DIR: &str = "/usr/persistent/slot-ctrl";
+
+/// How an efivar was mutated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Operation {
+    /// An existing efivar was overwritten.
+    Write,
+    /// An efivar was created.
+    Create,
+    /// An efivar was removed.
+    Remove,
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operation::Write => write!(f, "write"),
+            Operation::Create => write!(f, "create"),
+            Operation::Remove => write!(f, "remove"),
+        }
+    }
+}
+
+/// A single efivar mutation.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    /// When the efivar was mutated.
+    pub timestamp: DateTime<Utc>,
+    /// Name of the efivar, including the vendor guid.
+    pub variable: String,
+    /// How the efivar was mutated.
+    pub operation: Operation,
+    /// All bytes of the efivar before, attributes included, as hex. `None` if it
+    /// didn't exist or couldn't be read.
+    pub old: Option<String>,
+    /// All bytes of the efivar after, attributes included, as hex. `None` if it
+    /// was removed.
+    pub new: Option<String>,
+    /// The command that mutated the efivar, e.g. `slot-ctrl set b`, or the dbus
+    /// method and its sender.
+    pub command: String,
+    /// The user that mutated the efivar, `None` if unknown.
+    #[serde(default)]
+    pub uid: Option<u32>,
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {}: {} -> {} by `{}`",
+            self.timestamp.to_rfc3339_opts(SecondsFormat::Millis, true),
+            self.operation,
+            self.variable,
+            self.old.as_deref().unwrap_or("missing"),
+            self.new.as_deref().unwrap_or("missing"),
+            self.command
+        )?;
+        if let Some(uid) = self.uid {
+            write!(f, " (uid {uid})")?;
+        }
+        Ok(())
+    }
+}
+
+/// The journal file, one json encoded [`Entry`] per line.
+#[derive(Debug, Clone)]
+pub struct Journal {
+    path: PathBuf,
+}
+
+impl Journal {
+    /// The journal in `dir`, which is created on the first append.
+    pub fn new(dir: impl AsRef<Path>) -> Self {
+        Self {
+            path: dir.as_ref().join(JOURNAL_FILE),
+        }
+    }
+
+    /// Path of the journal file.
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append `entry` to the journal, and wait until it is on disk.
+    pub fn append(&self, entry: &Entry) -> Result<(), Error> {
+        let mut line = serde_json::to_vec(entry).map_err(Error::EncodeJournalEntry)?;
+        line.push(b'\n');
+        let append_error = |source| Error::AppendJournal {
+            path: self.path.clone(),
+            source,
+        };
+
+        let dir = self.path.parent().unwrap_or(Path::new("."));
+        fs::create_dir_all(dir).map_err(|source| Error::CreateJournalDir {
+            path: dir.to_owned(),
+            source,
+        })?;
+        let (mut file, created) = match OpenOptions::new()
+            .append(true)
+            .create_new(true)
+            .open(&self.path)
+        {
+            Ok(file) => (file, true),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => (
+                OpenOptions::new()
+                    .append(true)
+                    .open(&self.path)
+                    .map_err(append_error)?,
+                false,
+            ),
+            Err(e) => return Err(append_error(e)),
+        };
+        // A single write, so that concurrent writers don't interleave lines.
+        file.write_all(&line).map_err(append_error)?;
+        file.sync_data().map_err(append_error)?;
+        if created {
+            // Otherwise the new file itself may be lost on a power cut.
+            File::open(dir)
+                .and_then(|dir| dir.sync_all())
+                .map_err(append_error)?;
+        }
+        Ok(())
+    }
+
+    /// All entries of the journal, oldest first. Empty if nothing was journaled
+    /// yet.
+    pub fn entries(&self) -> Result<Vec<Entry>, Error> {
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(Error::open_file(&self.path, e)),
+        };
+        let mut entries = Vec::new();
+        for (i, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.map_err(|e| Error::read_file(&self.path, e))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry = serde_json::from_str(&line).map_err(|source| {
+                Error::InvalidJournalEntry {
+                    line: i + 1,
+                    source,
+                }
+            })?;
+            entries.push(entry);
+        }
+        Ok(entries)
+    }
+}
+
+/// An [`EfiVarStore`] that records all mutations of `inner` to a [`Journal`].
+///
+/// Failing to journal a mutation is logged, but doesn't fail the mutation, which
+/// has already happened at that point.
+pub struct JournaledStore<S> {
+    inner: S,
+    journal: Journal,
+    command: String,
+    uid: Option<u32>,
+}
+
+impl<S: EfiVarStore> JournaledStore<S> {
+    /// Journal the mutations of `inner` to `journal`, attributing them to
+    /// `command`, run by `uid`.
+    pub fn new(
+        inner: S,
+        journal: Journal,
+        command: impl Into<String>,
+        uid: Option<u32>,
+    ) -> Self {
+        Self {
+            inner,
+            journal,
+            command: command.into(),
+            uid,
+        }
+    }
+
+    /// The journal mutations are recorded to.
+    pub fn journal(&self) -> &Journal {
+        &self.journal
+    }
+
+    fn record(
+        &self,
+        operation: Operation,
+        name: &str,
+        old: Option<&[u8]>,
+        new: Option<&[u8]>,
+    ) {
+        let entry = Entry {
+            timestamp: Utc::now(),
+            variable: name.to_owned(),
+            operation,
+            old: old.map(to_hex),
+            new: new.map(to_hex),
+            command: self.command.clone(),
+            uid: self.uid,
+        };
+        tracing::info!(
+            variable = %entry.variable,
+            %operation,
+            old = entry.old.as_deref().unwrap_or("missing"),
+            new = entry.new.as_deref().unwrap_or("missing"),
+            command = %entry.command,
+            uid = entry.uid,
+            "efivar mutated"
+        );
+        if let Err(err) = self.journal.append(&entry) {
+            tracing::warn!(
+                journal = %self.journal.path.display(),
+                "failed to journal efivar mutation: {err}"
+            );
+        }
+    }
+}
+
+impl<S: EfiVarStore> EfiVarStore for JournaledStore<S> {
+    fn read(&self, name: &str) -> Result<Vec<u8>, Error> {
+        self.inner.read(name)
+    }
+
+    fn write(&self, name: &str, buffer: &[u8]) -> Result<(), Error> {
+        let old = self.inner.read(name).ok();
+        self.inner.write(name, buffer)?;
+        self.record(Operation::Write, name, old.as_deref(), Some(buffer));
+        Ok(())
+    }
+
+    fn create_and_write(&self, name: &str, buffer: &[u8]) -> Result<(), Error> {
+        let old = self.inner.read(name).ok();
+        self.inner.create_and_write(name, buffer)?;
+        self.record(Operation::Create, name, old.as_deref(), Some(buffer));
+        Ok(())
+    }
+
+    fn remove(&self, name: &str) -> Result<(), Error> {
+        let old = self.inner.read(name).ok();
+        self.inner.remove(name)?;
+        self.record(Operation::Remove, name, old.as_deref(), None);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use eyre::Result;
+
+    use super::*;
+    use crate::{
+        efivar::bootchain, set_next_boot_slot, tests::booted_from_a, InMemoryStore,
+        Slot,
+    };
+
+    fn journaled(dir: &Path) -> JournaledStore<InMemoryStore> {
+        JournaledStore::new(
+            booted_from_a(),
+            Journal::new(dir),
+            "slot-ctrl set b",
+            Some(1000),
+        )
+    }
+
+    #[test]
+    fn test_journaled_mutations() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let store = journaled(dir.path());
+        assert!(store.journal().entries()?.is_empty());
+
+        set_next_boot_slot(&store, Slot::B)?;
+        store.write(bootchain::PATH_NEXT, &[0x07, 0x00, 0x00, 0x00, 0x00])?;
+        store.remove(bootchain::PATH_NEXT)?;
+
+        let entries = Journal::new(dir.path()).entries()?;
+        let summary: Vec<_> = entries
+            .iter()
+            .map(|e| (e.operation, e.old.as_deref(), e.new.as_deref()))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (Operation::Create, None, Some("0700000001000000")),
+                (
+                    Operation::Write,
+                    Some("0700000001000000"),
+                    Some("0700000000")
+                ),
+                (Operation::Remove, Some("0700000000"), None),
+            ]
+        );
+        assert!(entries.iter().all(|e| e.variable == bootchain::PATH_NEXT
+            && e.command == "slot-ctrl set b"
+            && e.uid == Some(1000)));
+        assert!(entries.windows(2).all(|w| w[0].timestamp <= w[1].timestamp));
+        Ok(())
+    }
+
+    #[test]
+    fn test_reads_and_failures_are_not_journaled() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let store = journaled(dir.path());
+        store.read(bootchain::PATH_CURRENT)?;
+        assert!(store.remove(bootchain::PATH_NEXT).is_err());
+        assert!(store.journal().entries()?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_unwritable_journal() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let file = dir.path().join("file");
+        fs::write(&file, "")?;
+        let store = journaled(&file);
+
+        set_next_boot_slot(&store, Slot::B)?;
+        assert_eq!(crate::get_next_boot_slot(&store)?, Slot::B);
+        Ok(())
+    }
+
+    #[test]
+    fn test_entries_without_uid() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let journal = Journal::new(dir.path());
+        fs::write(
+            journal.path(),
+            r#"{"timestamp":"2024-03-07T13:05:09Z","variable":"BootChainFwNext","operation":"create","old":null,"new":"0700000001000000","command":"slot-ctrl set b"}"#,
+        )?;
+        assert_eq!(journal.entries()?[0].uid, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_journal() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let journal = Journal::new(dir.path());
+        fs::write(journal.path(), "\nnot json\n")?;
+        assert!(matches!(
+            journal.entries(),
+            Err(Error::InvalidJournalEntry { line: 2, .. })
+        ));
+        Ok(())
+    }
+}
@@ -4,19 +4,33 @@
 #![allow(clippy::missing_errors_doc)]
 
 use std::{
-    fmt, io,
+    fmt::{self, Write},
+    io,
     path::{Path, PathBuf},
 };
 
+use serde::Serialize;
+
+// The code generated by zbus is not documented.
+#[allow(missing_docs)]
+pub mod dbus;
 mod efivar;
+pub mod firmware;
 mod ioctl;
+pub mod journal;
+pub mod report;
+pub mod update;
+pub mod watch;
 
 use efivar::{
     ROOTFS_STATUS_NORMAL, ROOTFS_STATUS_UNBOOTABLE, ROOTFS_STATUS_UPD_DONE,
     ROOTFS_STATUS_UPD_IN_PROCESS, SLOT_A, SLOT_B,
 };
 
-pub use crate::efivar::EfiVar;
+pub use crate::efivar::{
+    DirStore, EfiVar, EfiVarAttributes, EfiVarData, EfiVarStore, Efivarfs,
+    InMemoryStore,
+};
 
 /// Error definition for library.
 #[allow(missing_docs)]
@@ -52,6 +66,31 @@ pub enum Error {
     InvalidRootFsStatusData,
     #[error("invalid retry counter({counter}), exceeding the maximum ({max})")]
     ExceedingRetryCount { counter: u8, max: u8 },
+    #[error("illegal rootfs status transition of slot {slot} from {from:?} to {to:?}")]
+    InvalidRootFsTransition {
+        slot: Slot,
+        from: RootFsStatus,
+        to: RootFsStatus,
+    },
+    #[error("slot {0} was not confirmed as booting successfully yet")]
+    BootNotConfirmed(Slot),
+    #[error("no update to roll back")]
+    NothingToRollBack,
+    #[error("the firmware doesn't support processing capsules on disk")]
+    CapsuleDeliveryUnsupported,
+    #[error("invalid capsule report: {0}")]
+    InvalidCapsuleReport(String),
+    #[error("failed creating journal directory {path}: {source}")]
+    CreateJournalDir { path: PathBuf, source: io::Error },
+    #[error("failed encoding journal entry: {0}")]
+    EncodeJournalEntry(serde_json::Error),
+    #[error("failed appending to journal {path}: {source}")]
+    AppendJournal { path: PathBuf, source: io::Error },
+    #[error("invalid journal entry on line {line}: {source}")]
+    InvalidJournalEntry {
+        line: usize,
+        source: serde_json::Error,
+    },
 }
 
 #[allow(missing_docs)]
@@ -101,6 +140,8 @@ impl Error {
 }
 
 /// Representation of the slot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
 #[repr(u8)]
 pub enum Slot {
     /// The Slot A is represented as 0.
@@ -109,6 +150,17 @@ pub enum Slot {
     B = SLOT_B,
 }
 
+impl Slot {
+    /// The slot that isn't `self`.
+    #[must_use]
+    pub fn other(self) -> Self {
+        match self {
+            Slot::A => Slot::B,
+            Slot::B => Slot::A,
+        }
+    }
+}
+
 /// Format slot as lowercase to match Nvidia standard in file system.
 impl fmt::Display for Slot {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -120,7 +172,7 @@ impl fmt::Display for Slot {
 }
 
 /// Representation of the rootfs status.
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
 #[repr(u8)]
 pub enum RootFsStatus {
     /// Default status of the rootfs.
@@ -157,6 +209,28 @@ impl RootFsStatus {
     pub fn is_unbootable(self) -> bool {
         matches!(self, Self::Unbootable)
     }
+
+    /// Checks if a slot may go from this status to `next` during an update. Staying
+    /// in the same status is always allowed, so that interrupted steps can be
+    /// repeated.
+    ///
+    /// * `Normal` or `Unbootable` -> `UpdateInProcess` - an update starts writing
+    ///   the slot
+    /// * `UpdateInProcess` -> `UpdateDone` - the slot was written and is booted next
+    /// * `UpdateInProcess` -> `Unbootable` - the update was aborted
+    /// * `UpdateDone` -> `Normal` - the slot booted successfully
+    /// * `UpdateDone` -> `Unbootable` - the slot failed to boot or was rolled back
+    #[must_use]
+    pub fn can_transition_to(self, next: Self) -> bool {
+        use RootFsStatus::{Normal, Unbootable, UpdateDone, UpdateInProcess};
+        self == next
+            || matches!(
+                (self, next),
+                (Normal | Unbootable, UpdateInProcess)
+                    | (UpdateInProcess, UpdateDone | Unbootable)
+                    | (UpdateDone, Normal | Unbootable)
+            )
+    }
 }
 
 impl TryFrom<u8> for RootFsStatus {
@@ -174,8 +248,8 @@ impl TryFrom<u8> for RootFsStatus {
 }
 
 /// Get the current active slot.
-pub fn get_current_slot() -> Result<Slot, Error> {
-    match efivar::bootchain::get_current_boot_slot()? {
+pub fn get_current_slot(store: &dyn EfiVarStore) -> Result<Slot, Error> {
+    match efivar::bootchain::get_current_boot_slot(store)? {
         SLOT_A => Ok(Slot::A),
         SLOT_B => Ok(Slot::B),
         _ => Err(Error::InvalidSlotData),
@@ -183,17 +257,14 @@ pub fn get_current_slot() -> Result<Slot, Error> {
 }
 
 /// Get the inactive slot.
-pub fn get_inactive_slot() -> Result<Slot, Error> {
+pub fn get_inactive_slot(store: &dyn EfiVarStore) -> Result<Slot, Error> {
     // inverts the output of `get_current_slot()`
-    match get_current_slot()? {
-        Slot::A => Ok(Slot::B),
-        Slot::B => Ok(Slot::A),
-    }
+    Ok(get_current_slot(store)?.other())
 }
 
 /// Get the slot set for the next boot.
-pub fn get_next_boot_slot() -> Result<Slot, Error> {
-    match efivar::bootchain::get_next_boot_slot()? {
+pub fn get_next_boot_slot(store: &dyn EfiVarStore) -> Result<Slot, Error> {
+    match efivar::bootchain::get_next_boot_slot(store)? {
         SLOT_A => Ok(Slot::A),
         SLOT_B => Ok(Slot::B),
         _ => Err(Error::InvalidSlotData),
@@ -201,61 +272,195 @@ pub fn get_next_boot_slot() -> Result<Slot, Error> {
 }
 
 /// Set the slot for the next boot.
-pub fn set_next_boot_slot(slot: Slot) -> Result<(), Error> {
-    efivar::bootchain::set_next_boot_slot(slot as u8)
+pub fn set_next_boot_slot(store: &dyn EfiVarStore, slot: Slot) -> Result<(), Error> {
+    efivar::bootchain::set_next_boot_slot(store, slot as u8)
 }
 
 /// Get the rootfs status for the current active slot.
-pub fn get_current_rootfs_status() -> Result<RootFsStatus, Error> {
+pub fn get_current_rootfs_status(
+    store: &dyn EfiVarStore,
+) -> Result<RootFsStatus, Error> {
     RootFsStatus::try_from(efivar::rootfs::get_rootfs_status(
-        efivar::bootchain::get_current_boot_slot()?,
+        store,
+        efivar::bootchain::get_current_boot_slot(store)?,
     )?)
 }
 
 /// Get the rootfs status for a certain `slot`.
-pub fn get_rootfs_status(slot: Slot) -> Result<RootFsStatus, Error> {
-    RootFsStatus::try_from(efivar::rootfs::get_rootfs_status(slot as u8)?)
+pub fn get_rootfs_status(
+    store: &dyn EfiVarStore,
+    slot: Slot,
+) -> Result<RootFsStatus, Error> {
+    RootFsStatus::try_from(efivar::rootfs::get_rootfs_status(store, slot as u8)?)
 }
 
 /// Set a rootfs status for the current active slot.
-pub fn set_current_rootfs_status(status: RootFsStatus) -> Result<(), Error> {
+pub fn set_current_rootfs_status(
+    store: &dyn EfiVarStore,
+    status: RootFsStatus,
+) -> Result<(), Error> {
     efivar::rootfs::set_rootfs_status(
+        store,
         status as u8,
-        efivar::bootchain::get_current_boot_slot()?,
+        efivar::bootchain::get_current_boot_slot(store)?,
     )
 }
 
 /// Set a rootfs status for a certain `slot`.
-pub fn set_rootfs_status(status: RootFsStatus, slot: Slot) -> Result<(), Error> {
-    efivar::rootfs::set_rootfs_status(status as u8, slot as u8)
+pub fn set_rootfs_status(
+    store: &dyn EfiVarStore,
+    status: RootFsStatus,
+    slot: Slot,
+) -> Result<(), Error> {
+    efivar::rootfs::set_rootfs_status(store, status as u8, slot as u8)
 }
 
 /// Get the retry count for the current active slot.
-pub fn get_current_retry_count() -> Result<u8, Error> {
-    efivar::rootfs::get_retry_count(efivar::bootchain::get_current_boot_slot()?)
+pub fn get_current_retry_count(store: &dyn EfiVarStore) -> Result<u8, Error> {
+    efivar::rootfs::get_retry_count(
+        store,
+        efivar::bootchain::get_current_boot_slot(store)?,
+    )
 }
 
 /// Get the retry count for a certain `slot`.
-pub fn get_retry_count(slot: Slot) -> Result<u8, Error> {
-    efivar::rootfs::get_retry_count(slot as u8)
+pub fn get_retry_count(store: &dyn EfiVarStore, slot: Slot) -> Result<u8, Error> {
+    efivar::rootfs::get_retry_count(store, slot as u8)
 }
 
 /// Get the maximum retry count before fallback.
-pub fn get_max_retry_count() -> Result<u8, Error> {
-    efivar::rootfs::get_max_retry_count()
+pub fn get_max_retry_count(store: &dyn EfiVarStore) -> Result<u8, Error> {
+    efivar::rootfs::get_max_retry_count(store)
 }
 
 /// Reset the retry counter to the maximum for the current active slot.
-pub fn reset_current_retry_count_to_max() -> Result<(), Error> {
-    let max_count = efivar::rootfs::get_max_retry_count()?;
+pub fn reset_current_retry_count_to_max(store: &dyn EfiVarStore) -> Result<(), Error> {
+    let max_count = efivar::rootfs::get_max_retry_count(store)?;
     efivar::rootfs::set_retry_count(
+        store,
         max_count,
-        efivar::bootchain::get_current_boot_slot()?,
+        efivar::bootchain::get_current_boot_slot(store)?,
     )
 }
 
 /// Reset the retry counter to the maximum for the a certain `slot`.
-pub fn reset_retry_count_to_max(slot: Slot) -> Result<(), Error> {
-    let max_count = efivar::rootfs::get_max_retry_count()?;
-    efivar::rootfs::set_retry_count(max_count, slot as u8)
+pub fn reset_retry_count_to_max(
+    store: &dyn EfiVarStore,
+    slot: Slot,
+) -> Result<(), Error> {
+    let max_count = efivar::rootfs::get_max_retry_count(store)?;
+    efivar::rootfs::set_retry_count(store, max_count, slot as u8)
+}
+
+/// Format `bytes` as lowercase hex.
+fn to_hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        write!(hex, "{byte:02x}").expect("writing to a string can't fail");
+    }
+    hex
+}
+
+#[cfg(test)]
+mod tests {
+    use eyre::Result;
+
+    use super::*;
+    use crate::efivar::{bootchain, rootfs};
+
+    /// A store of an orb booted from slot A, with both rootfs in `Normal`
+    /// status and a maximum of 3 retries.
+    pub(crate) fn booted_from_a() -> InMemoryStore {
+        let var = |value: u8| [0x07, 0x00, 0x00, 0x00, value, 0x00, 0x00, 0x00];
+        let vars = [
+            (bootchain::PATH_CURRENT, var(SLOT_A)),
+            (rootfs::PATH_STATUS_A, var(ROOTFS_STATUS_NORMAL)),
+            (rootfs::PATH_STATUS_B, var(ROOTFS_STATUS_NORMAL)),
+            (rootfs::PATH_RETRY_COUNT_A, var(3)),
+            (rootfs::PATH_RETRY_COUNT_B, var(1)),
+            (rootfs::PATH_RETRY_COUNT_MAX, var(3)),
+        ];
+        InMemoryStore::with_vars(vars.iter().map(|(name, buf)| (*name, &buf[..])))
+    }
+
+    #[test]
+    fn test_slots() -> Result<()> {
+        let store = booted_from_a();
+        assert!(matches!(get_current_slot(&store)?, Slot::A));
+        assert!(matches!(get_inactive_slot(&store)?, Slot::B));
+        assert!(
+            matches!(get_next_boot_slot(&store)?, Slot::A),
+            "next boot slot defaults to the current one"
+        );
+
+        set_next_boot_slot(&store, Slot::B)?;
+        assert!(matches!(get_next_boot_slot(&store)?, Slot::B));
+        assert!(matches!(get_current_slot(&store)?, Slot::A));
+        assert_eq!(
+            store.vars()[bootchain::PATH_NEXT],
+            [0x07, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_writes_preserve_attributes() -> Result<()> {
+        let store = booted_from_a();
+        store.write(
+            rootfs::PATH_STATUS_B,
+            &[
+                0x27,
+                0x00,
+                0x00,
+                0x00,
+                ROOTFS_STATUS_NORMAL,
+                0x00,
+                0x00,
+                0x00,
+            ],
+        )?;
+        set_rootfs_status(&store, RootFsStatus::Unbootable, Slot::B)?;
+        assert_eq!(
+            store.vars()[rootfs::PATH_STATUS_B],
+            [
+                0x27,
+                0x00,
+                0x00,
+                0x00,
+                ROOTFS_STATUS_UNBOOTABLE,
+                0x00,
+                0x00,
+                0x00
+            ]
+        );
+
+        set_next_boot_slot(&store, Slot::B)?;
+        let next = EfiVar::new(&store, bootchain::PATH_NEXT).read_data()?;
+        assert_eq!(
+            next.attributes,
+            EfiVarAttributes::NV_BS_RT,
+            "new efivars get explicit attributes"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_rootfs_status_and_retries() -> Result<()> {
+        let store = booted_from_a();
+        set_rootfs_status(&store, RootFsStatus::UpdateInProcess, Slot::B)?;
+        assert!(get_rootfs_status(&store, Slot::B)?.is_update_in_progress());
+        assert!(get_current_rootfs_status(&store)?.is_normal());
+
+        set_current_rootfs_status(&store, RootFsStatus::UpdateDone)?;
+        assert!(get_rootfs_status(&store, Slot::A)?.is_update_done());
+
+        assert_eq!(get_retry_count(&store, Slot::B)?, 1);
+        reset_retry_count_to_max(&store, Slot::B)?;
+        assert_eq!(get_retry_count(&store, Slot::B)?, 3);
+        assert_eq!(
+            get_current_retry_count(&store)?,
+            get_max_retry_count(&store)?
+        );
+        Ok(())
+    }
 }
@@ -1,5 +1,20 @@
 use clap::{Parser, Subcommand};
-use std::{env, path::Path, process::exit};
+use orb_slot_ctrl::{
+    dbus, firmware,
+    journal::{Journal, JournaledStore, DEFAULT_JOURNAL_DIR},
+    report::BootReport,
+    update,
+    watch::{HealthCheck, Outcome, Watchdog},
+    DirStore, EfiVarStore, Efivarfs, Error,
+};
+use std::{
+    env, io,
+    path::{Path, PathBuf},
+    process::exit,
+    sync::Arc,
+    time::Duration,
+};
+use tracing::Level;
 
 #[derive(Parser)]
 #[command(
@@ -8,6 +23,15 @@ use std::{env, path::Path, process::exit};
     long_about = "This tool is designed to read and write the slot and rootfs state of the Orb."
 )]
 struct Cli {
+    /// Read and write efivars in this directory instead of the efivarfs, e.g. to
+    /// inspect a dump from another device.
+    #[arg(long, global = true, value_name = "DIR")]
+    efivars_dir: Option<PathBuf>,
+    /// Directory of the journal that all efivar mutations are recorded to. Defaults
+    /// to the --efivars-dir if given, so that changes to a dump aren't journaled as
+    /// changes to this device, and to /usr/persistent/slot-ctrl otherwise.
+    #[arg(long, global = true, value_name = "DIR")]
+    journal_dir: Option<PathBuf>,
     #[command(subcommand)]
     subcmd: Commands,
 }
@@ -31,6 +55,54 @@ enum Commands {
         #[command(subcommand)]
         subcmd: StatusCommands,
     },
+    /// Get the full boot state of both slots, including the raw efivars.
+    #[command(name = "report", short_flag = 'r')]
+    Report {
+        /// Print the report as json.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Bootloader chain and capsule update controls.
+    Firmware {
+        #[command(subcommand)]
+        subcmd: FirmwareCommands,
+    },
+    /// A/B update controls.
+    Update {
+        #[command(subcommand)]
+        subcmd: UpdateCommands,
+    },
+    /// Confirm the current slot once healthy, if it is on trial after an update,
+    /// or roll it back.
+    Watch {
+        /// Systemd unit that has to be active. Can be repeated.
+        #[arg(long = "unit", value_name = "UNIT")]
+        units: Vec<String>,
+        /// Shell command that has to exit successfully. Can be repeated.
+        #[arg(long = "check", value_name = "COMMAND")]
+        commands: Vec<String>,
+        /// Seconds within which all checks have to pass.
+        #[arg(long, default_value_t = 300)]
+        deadline: u64,
+        /// Seconds to wait before running failed checks again.
+        #[arg(long, default_value_t = 5)]
+        interval: u64,
+    },
+    /// Serve the slot state and update controls on dbus as org.worldcoin.SlotCtrl1.
+    Serve {
+        /// Seconds between checks of the efivars for changes made by others.
+        #[arg(long, default_value_t = 5)]
+        poll_interval: u64,
+    },
+    /// Print the journal of efivar mutations, oldest first.
+    History {
+        /// Only print the last N mutations.
+        #[arg(long, short = 'n', value_name = "N")]
+        last: Option<usize>,
+        /// Print the mutations as json, one per line.
+        #[arg(long)]
+        json: bool,
+    },
     /// Get the git commit used for this build.
     #[command(name = "git", short_flag = 'g')]
     GitCommit,
@@ -58,6 +130,31 @@ enum StatusCommands {
     ListStatusVariants,
 }
 
+#[derive(Subcommand)]
+enum FirmwareCommands {
+    /// Get the status of the last bootloader chain switch or update.
+    Status,
+    /// Get the result of the last processed capsule.
+    Capsule,
+    /// Process the capsules on the ESP on the next boot.
+    RequestCapsuleUpdate,
+}
+
+#[derive(Subcommand)]
+enum UpdateCommands {
+    /// Get the state of the update.
+    State,
+    /// Start updating the inactive slot.
+    Begin,
+    /// Boot the updated inactive slot next.
+    Activate,
+    /// Confirm that the updated slot booted successfully.
+    #[command(name = "mark-success")]
+    MarkSuccessful,
+    /// Abandon the update and boot the previous slot next.
+    Rollback,
+}
+
 fn check_running_as_root(error: orb_slot_ctrl::Error) {
     let uid = unsafe { libc::getuid() };
     let euid = unsafe { libc::geteuid() };
@@ -75,7 +172,7 @@ fn main() -> eyre::Result<()> {
             match executable_name.to_str() {
                 Some("get-slot") => {
                     // print current slot if called by get-slot and exit
-                    println!("{}", orb_slot_ctrl::get_current_slot()?);
+                    println!("{}", orb_slot_ctrl::get_current_slot(&Efivarfs::new())?);
                     return Ok(());
                 }
                 None => {
@@ -86,12 +183,33 @@ fn main() -> eyre::Result<()> {
         };
     };
     let cli = Cli::parse();
+    tracing_subscriber::fmt()
+        .with_writer(io::stderr)
+        .with_max_level(Level::INFO)
+        .init();
+    let journal = Journal::new(
+        cli.journal_dir
+            .as_deref()
+            .or(cli.efivars_dir.as_deref())
+            .unwrap_or(Path::new(DEFAULT_JOURNAL_DIR)),
+    );
+    let unjournaled_store: Arc<dyn EfiVarStore> = match cli.efivars_dir {
+        Some(dir) => Arc::new(DirStore::new(dir)),
+        None => Arc::new(Efivarfs::new()),
+    };
+    let shared_store: Arc<dyn EfiVarStore> = Arc::new(JournaledStore::new(
+        Arc::clone(&unjournaled_store),
+        journal.clone(),
+        env::args().collect::<Vec<_>>().join(" "),
+        Some(unsafe { libc::getuid() }),
+    ));
+    let store = shared_store.as_ref();
     match cli.subcmd {
         Commands::GetSlot => {
-            println!("{}", orb_slot_ctrl::get_current_slot()?);
+            println!("{}", orb_slot_ctrl::get_current_slot(store)?);
         }
         Commands::GetNextSlot => {
-            println!("{}", orb_slot_ctrl::get_next_boot_slot()?);
+            println!("{}", orb_slot_ctrl::get_next_boot_slot(store)?);
         }
         Commands::SetNextSlot { slot } => {
             let slot = match slot.as_str() {
@@ -110,7 +228,7 @@ fn main() -> eyre::Result<()> {
                     exit(1)
                 }
             };
-            if let Err(e) = orb_slot_ctrl::set_next_boot_slot(slot) {
+            if let Err(e) = orb_slot_ctrl::set_next_boot_slot(store, slot) {
                 check_running_as_root(e);
             };
         }
@@ -121,11 +239,15 @@ fn main() -> eyre::Result<()> {
                         println!(
                             "{:?}",
                             orb_slot_ctrl::get_rootfs_status(
-                                orb_slot_ctrl::get_inactive_slot()?
+                                store,
+                                orb_slot_ctrl::get_inactive_slot(store)?
                             )?
                         );
                     } else {
-                        println!("{:?}", orb_slot_ctrl::get_current_rootfs_status()?);
+                        println!(
+                            "{:?}",
+                            orb_slot_ctrl::get_current_rootfs_status(store)?
+                        );
                     }
                 }
                 StatusCommands::SetRootfsStatus { status } => {
@@ -160,13 +282,14 @@ fn main() -> eyre::Result<()> {
                     };
                     if inactive {
                         if let Err(e) = orb_slot_ctrl::set_rootfs_status(
+                            store,
                             status,
-                            orb_slot_ctrl::get_inactive_slot()?,
+                            orb_slot_ctrl::get_inactive_slot(store)?,
                         ) {
                             check_running_as_root(e);
                         }
                     } else if let Err(e) =
-                        orb_slot_ctrl::set_current_rootfs_status(status)
+                        orb_slot_ctrl::set_current_rootfs_status(store, status)
                     {
                         check_running_as_root(e);
                     }
@@ -176,25 +299,27 @@ fn main() -> eyre::Result<()> {
                         println!(
                             "{}",
                             orb_slot_ctrl::get_retry_count(
-                                orb_slot_ctrl::get_inactive_slot()?
+                                store,
+                                orb_slot_ctrl::get_inactive_slot(store)?
                             )?
                         );
                     } else {
-                        println!("{}", orb_slot_ctrl::get_current_retry_count()?);
+                        println!("{}", orb_slot_ctrl::get_current_retry_count(store)?);
                     }
                 }
                 StatusCommands::GetMaxRetryCounter => {
-                    println!("{}", orb_slot_ctrl::get_max_retry_count()?);
+                    println!("{}", orb_slot_ctrl::get_max_retry_count(store)?);
                 }
                 StatusCommands::ResetRetryCounter => {
                     if inactive {
                         if let Err(e) = orb_slot_ctrl::reset_retry_count_to_max(
-                            orb_slot_ctrl::get_inactive_slot()?,
+                            store,
+                            orb_slot_ctrl::get_inactive_slot(store)?,
                         ) {
                             check_running_as_root(e)
                         }
                     } else if let Err(e) =
-                        orb_slot_ctrl::reset_current_retry_count_to_max()
+                        orb_slot_ctrl::reset_current_retry_count_to_max(store)
                     {
                         check_running_as_root(e)
                     }
@@ -208,6 +333,120 @@ fn main() -> eyre::Result<()> {
                 }
             }
         }
+        Commands::Report { json } => {
+            let report = BootReport::collect(store);
+            if json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                print!("{report}");
+            }
+        }
+        Commands::Firmware { subcmd } => match subcmd {
+            FirmwareCommands::Status => match firmware::get_fw_status(store)? {
+                Some(status) => println!("{status}"),
+                None => println!("none"),
+            },
+            FirmwareCommands::Capsule => {
+                match firmware::get_last_capsule_result(store)? {
+                    Some(capsule) => println!(
+                        "{} {} at {}, status {:#x}",
+                        capsule.name,
+                        capsule.capsule_guid,
+                        capsule.processed,
+                        capsule.status
+                    ),
+                    None => println!("none"),
+                }
+            }
+            FirmwareCommands::RequestCapsuleUpdate => {
+                match firmware::request_capsule_update(store) {
+                    Ok(()) => {}
+                    Err(e @ Error::CapsuleDeliveryUnsupported) => return Err(e.into()),
+                    Err(e) => check_running_as_root(e),
+                }
+            }
+        },
+        Commands::Update { subcmd } => {
+            let result = match subcmd {
+                UpdateCommands::State => {
+                    println!("{:?}", update::state(store)?);
+                    return Ok(());
+                }
+                UpdateCommands::Begin => update::begin(store),
+                UpdateCommands::Activate => update::activate(store),
+                UpdateCommands::MarkSuccessful => update::mark_successful(store),
+                UpdateCommands::Rollback => update::rollback(store),
+            };
+            match result {
+                Ok(slot) => println!("{slot}"),
+                Err(
+                    e @ (orb_slot_ctrl::Error::InvalidRootFsTransition { .. }
+                    | orb_slot_ctrl::Error::BootNotConfirmed(_)
+                    | orb_slot_ctrl::Error::NothingToRollBack),
+                ) => return Err(e.into()),
+                Err(e) => check_running_as_root(e),
+            }
+        }
+        Commands::Watch {
+            units,
+            commands,
+            deadline,
+            interval,
+        } => {
+            let watchdog = Watchdog {
+                checks: units
+                    .into_iter()
+                    .map(HealthCheck::Unit)
+                    .chain(commands.into_iter().map(HealthCheck::Command))
+                    .collect(),
+                deadline: Duration::from_secs(deadline),
+                interval: Duration::from_secs(interval),
+            };
+            match watchdog.run(store) {
+                Ok(Outcome::NotOnTrial(status)) => {
+                    println!("Current slot is {status:?}, nothing to confirm.");
+                }
+                Ok(Outcome::Confirmed(slot)) => {
+                    println!("Slot {slot} booted successfully and was marked Normal.");
+                }
+                Ok(Outcome::RolledBack {
+                    failed,
+                    fallback,
+                    unhealthy,
+                }) => {
+                    for check in unhealthy {
+                        println!("Health check failed: {check}");
+                    }
+                    println!(
+                        "Slot {failed} was marked Unbootable, slot {fallback} boots next."
+                    );
+                    exit(1)
+                }
+                Err(e) => check_running_as_root(e),
+            }
+        }
+        Commands::Serve { poll_interval } => {
+            tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()?
+                // Mutations are journaled with the dbus caller, not as `serve`.
+                .block_on(dbus::serve(
+                    unjournaled_store.clone(),
+                    Some(journal.clone()),
+                    Duration::from_secs(poll_interval),
+                ))?;
+        }
+        Commands::History { last, json } => {
+            let entries = journal.entries()?;
+            let skip = last.map_or(0, |last| entries.len().saturating_sub(last));
+            for entry in &entries[skip..] {
+                if json {
+                    println!("{}", serde_json::to_string(entry)?);
+                } else {
+                    println!("{entry}");
+                }
+            }
+        }
         Commands::GitCommit => {
             println!("{}", env!("GIT_COMMIT"));
         }
@@ -0,0 +1,328 @@
+//! A snapshot of the whole boot state, for diagnostics.
+
+use std::fmt;
+
+use serde::{Serialize, Serializer};
+
+use crate::efivar::{bootchain, capsule, rootfs};
+use crate::firmware::{self, CapsuleResult, FwStatus};
+use crate::{
+    get_current_slot, get_max_retry_count, get_next_boot_slot, get_retry_count,
+    get_rootfs_status, to_hex, EfiVarData, EfiVarStore, Error, RootFsStatus, Slot,
+};
+
+/// The efivars that make up the boot state, in the order they are reported.
+const EFIVARS: [&str; 11] = [
+    bootchain::PATH_CURRENT,
+    bootchain::PATH_NEXT,
+    bootchain::PATH_STATUS,
+    rootfs::PATH_STATUS_A,
+    rootfs::PATH_STATUS_B,
+    rootfs::PATH_RETRY_COUNT_A,
+    rootfs::PATH_RETRY_COUNT_B,
+    rootfs::PATH_RETRY_COUNT_MAX,
+    capsule::PATH_OS_INDICATIONS,
+    capsule::PATH_OS_INDICATIONS_SUPPORTED,
+    capsule::PATH_CAPSULE_LAST,
+];
+
+/// A value of the report, or why it couldn't be read.
+///
+/// Serialized as the value itself, or as `{"error": "..."}`.
+pub type Field<T> = Result<T, String>;
+
+/// The boot state, as read from the efivars in one go.
+///
+/// A broken efivar only fails the fields read from it, the rest of the report and
+/// the raw bytes of all efivars are still collected.
+#[derive(Debug, Serialize)]
+pub struct BootReport {
+    /// The current active slot.
+    #[serde(serialize_with = "field")]
+    pub current_slot: Field<Slot>,
+    /// The slot set for the next boot.
+    #[serde(serialize_with = "field")]
+    pub next_slot: Field<Slot>,
+    /// The maximum retry count before fallback.
+    #[serde(serialize_with = "field")]
+    pub max_retry_count: Field<u8>,
+    /// The state of slot A.
+    pub slot_a: SlotReport,
+    /// The state of slot B.
+    pub slot_b: SlotReport,
+    /// The state of the bootloader.
+    pub bootloader: BootloaderReport,
+    /// The efivars the above was read from.
+    pub efivars: Vec<RawEfiVar>,
+}
+
+/// The state of a single slot.
+#[derive(Debug, Serialize)]
+pub struct SlotReport {
+    /// The rootfs status.
+    #[serde(serialize_with = "field")]
+    pub rootfs_status: Field<RootFsStatus>,
+    /// The boot retry count.
+    #[serde(serialize_with = "field")]
+    pub retry_count: Field<u8>,
+}
+
+/// The state of the bootloader, see [`firmware`].
+#[derive(Debug, Serialize)]
+pub struct BootloaderReport {
+    /// The status of the last bootloader chain switch or update.
+    #[serde(serialize_with = "field")]
+    pub fw_status: Field<Option<FwStatus>>,
+    /// Whether capsules will be processed on the next boot.
+    #[serde(serialize_with = "field")]
+    pub capsule_update_requested: Field<bool>,
+    /// The result of the last processed capsule.
+    #[serde(serialize_with = "field")]
+    pub last_capsule: Field<Option<CapsuleResult>>,
+}
+
+/// The contents of an efivar.
+#[derive(Debug, Serialize)]
+pub struct RawEfiVar {
+    /// Name of the efivar, including the vendor guid.
+    pub name: String,
+    /// The attribute flags in the first 4 bytes, or `None` if the efivar doesn't
+    /// exist or is too short.
+    pub attributes: Option<u32>,
+    /// All bytes of the efivar, attributes included, as hex. `None` if the efivar
+    /// doesn't exist or couldn't be read.
+    pub bytes: Option<String>,
+    /// Why the efivar couldn't be read, if it exists.
+    pub error: Option<String>,
+}
+
+impl BootReport {
+    /// Read the boot state from `store`.
+    pub fn collect(store: &dyn EfiVarStore) -> Self {
+        let slot = |slot| SlotReport {
+            rootfs_status: get_rootfs_status(store, slot).map_err(|e| e.to_string()),
+            retry_count: get_retry_count(store, slot).map_err(|e| e.to_string()),
+        };
+        Self {
+            current_slot: get_current_slot(store).map_err(|e| e.to_string()),
+            next_slot: get_next_boot_slot(store).map_err(|e| e.to_string()),
+            max_retry_count: get_max_retry_count(store).map_err(|e| e.to_string()),
+            slot_a: slot(Slot::A),
+            slot_b: slot(Slot::B),
+            bootloader: BootloaderReport {
+                fw_status: firmware::get_fw_status(store).map_err(|e| e.to_string()),
+                capsule_update_requested: firmware::is_capsule_update_requested(store)
+                    .map_err(|e| e.to_string()),
+                last_capsule: firmware::get_last_capsule_result(store)
+                    .map_err(|e| e.to_string()),
+            },
+            efivars: EFIVARS
+                .iter()
+                .map(|name| RawEfiVar::read(store, name))
+                .collect(),
+        }
+    }
+}
+
+impl RawEfiVar {
+    fn read(store: &dyn EfiVarStore, name: &str) -> Self {
+        let (buffer, error) = match store.read(name) {
+            Ok(buffer) => (Some(buffer), None),
+            // `BootChainFwNext` only exists once a next boot slot was set.
+            Err(Error::OpenFile { .. }) => (None, None),
+            Err(err) => (None, Some(err.to_string())),
+        };
+        Self {
+            name: name.to_owned(),
+            attributes: buffer
+                .as_deref()
+                .and_then(|b| EfiVarData::parse(b).ok())
+                .map(|data| data.attributes.bits()),
+            bytes: buffer.as_deref().map(to_hex),
+            error,
+        }
+    }
+}
+
+fn field<T: Serialize, S: Serializer>(
+    field: &Field<T>,
+    s: S,
+) -> Result<S::Ok, S::Error> {
+    #[derive(Serialize)]
+    struct FieldError<'a> {
+        error: &'a str,
+    }
+    match field {
+        Ok(value) => value.serialize(s),
+        Err(error) => FieldError { error }.serialize(s),
+    }
+}
+
+/// Writes `field` with `f`, or its error.
+fn write_field<T>(
+    f: &mut fmt::Formatter<'_>,
+    field: &Field<T>,
+    write: impl FnOnce(&mut fmt::Formatter<'_>, &T) -> fmt::Result,
+) -> fmt::Result {
+    match field {
+        Ok(value) => write(f, value),
+        Err(error) => writeln!(f, "error: {error}"),
+    }
+}
+
+impl fmt::Display for BootReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "current slot:    ")?;
+        write_field(f, &self.current_slot, |f, slot| writeln!(f, "{slot}"))?;
+        write!(f, "next slot:       ")?;
+        write_field(f, &self.next_slot, |f, slot| writeln!(f, "{slot}"))?;
+        write!(f, "max retry count: ")?;
+        write_field(f, &self.max_retry_count, |f, count| writeln!(f, "{count}"))?;
+        for (slot, report) in [(Slot::A, &self.slot_a), (Slot::B, &self.slot_b)] {
+            match (&report.rootfs_status, &report.retry_count) {
+                (Ok(status), Ok(count)) => writeln!(
+                    f,
+                    "slot {slot}:          {status:?}, {count} retries left"
+                )?,
+                (Err(error), _) | (_, Err(error)) => {
+                    writeln!(f, "slot {slot}:          error: {error}")?;
+                }
+            }
+        }
+        let bootloader = &self.bootloader;
+        write!(f, "fw status:       ")?;
+        write_field(f, &bootloader.fw_status, |f, status| match status {
+            Some(status) => writeln!(f, "{status}"),
+            None => writeln!(f, "none"),
+        })?;
+        write!(f, "capsule update:  ")?;
+        write_field(f, &bootloader.capsule_update_requested, |f, requested| {
+            writeln!(
+                f,
+                "{}",
+                if *requested {
+                    "requested"
+                } else {
+                    "not requested"
+                }
+            )
+        })?;
+        match &bootloader.last_capsule {
+            Ok(Some(capsule)) => writeln!(
+                f,
+                "last capsule:    {} {} at {}, status {:#x}",
+                capsule.name, capsule.capsule_guid, capsule.processed, capsule.status
+            )?,
+            Ok(None) => {}
+            Err(error) => writeln!(f, "last capsule:    error: {error}")?,
+        }
+        writeln!(f, "efivars:")?;
+        for var in &self.efivars {
+            match (&var.bytes, var.attributes) {
+                (Some(bytes), Some(attributes)) => {
+                    writeln!(
+                        f,
+                        "  {}: {bytes} (attributes {attributes:#x})",
+                        var.name
+                    )?;
+                }
+                (Some(bytes), None) => writeln!(f, "  {}: {bytes}", var.name)?,
+                (None, _) => match &var.error {
+                    Some(error) => writeln!(f, "  {}: error: {error}", var.name)?,
+                    None => writeln!(f, "  {}: missing", var.name)?,
+                },
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use eyre::Result;
+
+    use super::*;
+    use crate::tests::booted_from_a;
+
+    #[test]
+    fn test_report() -> Result<()> {
+        let store = booted_from_a();
+        let report = BootReport::collect(&store);
+        assert_eq!(report.current_slot, Ok(Slot::A));
+        assert_eq!(report.next_slot, Ok(Slot::A));
+        assert_eq!(report.slot_b.retry_count, Ok(1));
+
+        let json = serde_json::to_value(&report)?;
+        assert_eq!(json["current_slot"], "a");
+        assert_eq!(json["slot_a"]["rootfs_status"], "Normal");
+        assert_eq!(json["max_retry_count"], 3);
+        assert_eq!(
+            json["bootloader"],
+            serde_json::json!({
+                "fw_status": null,
+                "capsule_update_requested": false,
+                "last_capsule": null,
+            })
+        );
+        assert_eq!(json["efivars"][0]["name"], bootchain::PATH_CURRENT);
+        assert_eq!(json["efivars"][0]["attributes"], 7);
+        assert_eq!(json["efivars"][0]["bytes"], "0700000000000000");
+        assert_eq!(
+            json["efivars"][1],
+            serde_json::json!({
+                "name": bootchain::PATH_NEXT,
+                "attributes": null,
+                "bytes": null,
+                "error": null,
+            }),
+            "missing efivars are reported as such"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_report_with_invalid_capsule_report() -> Result<()> {
+        let store = booted_from_a();
+        let mut name = vec![0x07, 0x00, 0x00, 0x00];
+        name.extend("Capsule0000".encode_utf16().flat_map(u16::to_le_bytes));
+        store.create_and_write(capsule::PATH_CAPSULE_LAST, &name)?;
+        store.create_and_write(
+            &format!("Capsule0000-{}", capsule::REPORT_GUID),
+            &[0x07, 0x00, 0x00, 0x00, 0x30],
+        )?;
+        let report = BootReport::collect(&store);
+        assert!(report.bootloader.last_capsule.is_err());
+        assert_eq!(report.bootloader.fw_status, Ok(None));
+        assert_eq!(report.bootloader.capsule_update_requested, Ok(false));
+
+        let json = serde_json::to_value(&report)?;
+        assert!(json["bootloader"]["last_capsule"]["error"].is_string());
+        assert_eq!(json["bootloader"]["capsule_update_requested"], false);
+        Ok(())
+    }
+
+    #[test]
+    fn test_report_with_invalid_efivar() -> Result<()> {
+        let store = booted_from_a();
+        store.write(
+            rootfs::PATH_STATUS_A,
+            &[0x07, 0x00, 0x00, 0x00, 0xff, 0x00, 0x00, 0x00],
+        )?;
+        let report = BootReport::collect(&store);
+        assert!(report.slot_a.rootfs_status.is_err());
+        assert_eq!(report.slot_a.retry_count, Ok(3));
+        assert_eq!(report.current_slot, Ok(Slot::A));
+
+        let json = serde_json::to_value(&report)?;
+        assert_eq!(
+            json["slot_a"]["rootfs_status"]["error"],
+            Error::InvalidRootFsStatusData.to_string()
+        );
+        assert_eq!(json["slot_b"]["rootfs_status"], "Normal");
+        assert_eq!(
+            json["efivars"][3]["bytes"], "07000000ff000000",
+            "the raw bytes of invalid efivars are still reported"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,249 @@
+//! A/B updates as a sequence of validated rootfs status transitions.
+//!
+//! An update goes through these steps:
+//!
+//! 1. [`begin`] - marks the inactive slot `UpdateInProcess`, before it is written.
+//! 2. [`activate`] - once it is written, marks it `UpdateDone` and boots it next.
+//! 3. [`mark_successful`] - after rebooting into it, marks it `Normal`.
+//!
+//! [`rollback`] abandons the update at any point. Every step only touches the
+//! efivars it needs in an order that never makes a half-written slot bootable,
+//! and can simply be run again if it was interrupted. [`state`] tells which step
+//! to resume from.
+
+use crate::{
+    get_current_rootfs_status, get_current_slot, get_next_boot_slot, get_rootfs_status,
+    reset_retry_count_to_max, set_next_boot_slot, set_rootfs_status, EfiVarStore,
+    Error, RootFsStatus, Slot,
+};
+
+/// How far an update has progressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpdateState {
+    /// No update is in progress.
+    Idle,
+    /// `slot` is being written, see [`begin`].
+    Writing {
+        /// The inactive slot.
+        slot: Slot,
+    },
+    /// `slot` was written and waits to be booted, see [`activate`].
+    PendingReboot {
+        /// The inactive slot.
+        slot: Slot,
+    },
+    /// The updated `slot` was booted, but not yet confirmed as working, see
+    /// [`mark_successful`].
+    Trial {
+        /// The current slot.
+        slot: Slot,
+    },
+}
+
+/// Get the state of the update, derived from the rootfs status of both slots.
+pub fn state(store: &dyn EfiVarStore) -> Result<UpdateState, Error> {
+    let current = get_current_slot(store)?;
+    if get_rootfs_status(store, current)?.is_update_done() {
+        return Ok(UpdateState::Trial { slot: current });
+    }
+    let inactive = current.other();
+    Ok(match get_rootfs_status(store, inactive)? {
+        RootFsStatus::UpdateInProcess => UpdateState::Writing { slot: inactive },
+        RootFsStatus::UpdateDone => UpdateState::PendingReboot { slot: inactive },
+        RootFsStatus::Normal | RootFsStatus::Unbootable => UpdateState::Idle,
+    })
+}
+
+/// Start an update of the inactive slot, returning it.
+///
+/// The slot is taken out of the next boot first, so that a half-written slot is
+/// never booted. Starting over an aborted or previous update is allowed, but the
+/// current slot has to be confirmed with [`mark_successful`] or rolled back first.
+pub fn begin(store: &dyn EfiVarStore) -> Result<Slot, Error> {
+    let current = get_current_slot(store)?;
+    if get_current_rootfs_status(store)?.is_update_done() {
+        return Err(Error::BootNotConfirmed(current));
+    }
+    let target = current.other();
+    check_transition(store, target, RootFsStatus::UpdateInProcess)?;
+
+    if get_next_boot_slot(store)? == target {
+        set_next_boot_slot(store, current)?;
+    }
+    set_rootfs_status(store, RootFsStatus::UpdateInProcess, target)?;
+    Ok(target)
+}
+
+/// Make the written inactive slot boot next, returning it.
+///
+/// Its retry counter is reset before it is marked `UpdateDone`, and the next boot
+/// slot switched last.
+pub fn activate(store: &dyn EfiVarStore) -> Result<Slot, Error> {
+    let target = get_current_slot(store)?.other();
+    check_transition(store, target, RootFsStatus::UpdateDone)?;
+
+    reset_retry_count_to_max(store, target)?;
+    set_rootfs_status(store, RootFsStatus::UpdateDone, target)?;
+    set_next_boot_slot(store, target)?;
+    Ok(target)
+}
+
+/// Confirm that the current slot booted successfully, returning it.
+pub fn mark_successful(store: &dyn EfiVarStore) -> Result<Slot, Error> {
+    let current = get_current_slot(store)?;
+    check_transition(store, current, RootFsStatus::Normal)?;
+
+    reset_retry_count_to_max(store, current)?;
+    set_rootfs_status(store, RootFsStatus::Normal, current)?;
+    Ok(current)
+}
+
+/// Abandon the update, returning the slot that boots next.
+///
+/// * on trial - the current slot is marked `Unbootable`, and the previous slot
+///   boots next
+/// * writing or pending reboot - the inactive slot is marked `Unbootable`, and the
+///   current slot boots next
+pub fn rollback(store: &dyn EfiVarStore) -> Result<Slot, Error> {
+    let (failed, fallback) = match state(store)? {
+        UpdateState::Idle => return Err(Error::NothingToRollBack),
+        UpdateState::Writing { slot }
+        | UpdateState::PendingReboot { slot }
+        | UpdateState::Trial { slot } => (slot, slot.other()),
+    };
+
+    set_next_boot_slot(store, fallback)?;
+    set_rootfs_status(store, RootFsStatus::Unbootable, failed)?;
+    Ok(fallback)
+}
+
+/// Fails if `slot` may not go from its current status to `to`.
+fn check_transition(
+    store: &dyn EfiVarStore,
+    slot: Slot,
+    to: RootFsStatus,
+) -> Result<(), Error> {
+    let from = get_rootfs_status(store, slot)?;
+    if from.can_transition_to(to) {
+        Ok(())
+    } else {
+        Err(Error::InvalidRootFsTransition { slot, from, to })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use eyre::Result;
+
+    use super::*;
+    use crate::efivar::bootchain;
+    use crate::{get_retry_count, tests::booted_from_a};
+
+    /// Boots into the next boot slot, like the bootloader would.
+    fn reboot(store: &dyn EfiVarStore) -> Result<()> {
+        let next = get_next_boot_slot(store)?;
+        store.write(
+            bootchain::PATH_CURRENT,
+            &[0x07, 0x00, 0x00, 0x00, next as u8, 0x00, 0x00, 0x00],
+        )?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_update() -> Result<()> {
+        let store = booted_from_a();
+        assert_eq!(state(&store)?, UpdateState::Idle);
+
+        assert_eq!(begin(&store)?, Slot::B);
+        assert_eq!(state(&store)?, UpdateState::Writing { slot: Slot::B });
+        assert_eq!(get_next_boot_slot(&store)?, Slot::A);
+
+        assert_eq!(activate(&store)?, Slot::B);
+        assert_eq!(state(&store)?, UpdateState::PendingReboot { slot: Slot::B });
+        assert_eq!(get_next_boot_slot(&store)?, Slot::B);
+        assert_eq!(get_retry_count(&store, Slot::B)?, 3);
+
+        reboot(&store)?;
+        assert_eq!(state(&store)?, UpdateState::Trial { slot: Slot::B });
+        assert!(matches!(
+            begin(&store),
+            Err(Error::BootNotConfirmed(Slot::B))
+        ));
+
+        assert_eq!(mark_successful(&store)?, Slot::B);
+        assert_eq!(state(&store)?, UpdateState::Idle);
+        assert!(get_rootfs_status(&store, Slot::B)?.is_normal());
+        Ok(())
+    }
+
+    #[test]
+    fn test_resume_after_interruption() -> Result<()> {
+        let store = booted_from_a();
+        begin(&store)?;
+        // Interrupted after switching the status, but before the next boot slot.
+        set_rootfs_status(&store, RootFsStatus::UpdateDone, Slot::B)?;
+        assert_eq!(state(&store)?, UpdateState::PendingReboot { slot: Slot::B });
+        assert_eq!(get_next_boot_slot(&store)?, Slot::A);
+
+        activate(&store)?;
+        let vars = store.vars();
+        activate(&store)?;
+        assert_eq!(store.vars(), vars, "repeating a step changes nothing");
+        assert_eq!(get_next_boot_slot(&store)?, Slot::B);
+
+        begin(&store).unwrap_err();
+        assert_eq!(store.vars(), vars, "rejected steps change nothing");
+        Ok(())
+    }
+
+    #[test]
+    fn test_rollback() -> Result<()> {
+        let store = booted_from_a();
+        assert!(matches!(rollback(&store), Err(Error::NothingToRollBack)));
+
+        begin(&store)?;
+        activate(&store)?;
+        assert_eq!(rollback(&store)?, Slot::A);
+        assert_eq!(get_next_boot_slot(&store)?, Slot::A);
+        assert!(get_rootfs_status(&store, Slot::B)?.is_unbootable());
+        assert_eq!(state(&store)?, UpdateState::Idle);
+
+        // Retrying the update after an aborted one, this time until it is booted.
+        begin(&store)?;
+        activate(&store)?;
+        reboot(&store)?;
+        assert_eq!(rollback(&store)?, Slot::A);
+        assert!(get_current_rootfs_status(&store)?.is_unbootable());
+        reboot(&store)?;
+        assert_eq!(get_current_slot(&store)?, Slot::A);
+        assert_eq!(state(&store)?, UpdateState::Idle);
+        Ok(())
+    }
+
+    #[test]
+    fn test_illegal_transitions() -> Result<()> {
+        let store = booted_from_a();
+        assert!(matches!(
+            activate(&store),
+            Err(Error::InvalidRootFsTransition {
+                slot: Slot::B,
+                from: RootFsStatus::Normal,
+                to: RootFsStatus::UpdateDone,
+            })
+        ));
+
+        begin(&store)?;
+        reboot(&store)?;
+        assert_eq!(get_current_slot(&store)?, Slot::A, "B is not booted yet");
+        set_rootfs_status(&store, RootFsStatus::UpdateInProcess, Slot::A)?;
+        assert!(matches!(
+            mark_successful(&store),
+            Err(Error::InvalidRootFsTransition {
+                from: RootFsStatus::UpdateInProcess,
+                to: RootFsStatus::Normal,
+                ..
+            })
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,275 @@
+//! Confirming or rolling back an updated slot after it booted.
+//!
+//! Meant to run once at boot: if the current slot is on trial, i.e. its rootfs
+//! status is `UpdateDone`, the [`Watchdog`] waits for its health checks to pass and
+//! then marks the slot `Normal`, or rolls it back once the deadline is exceeded.
+
+use std::{
+    fmt, io,
+    os::unix::process::CommandExt,
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    get_current_rootfs_status, update, EfiVarStore, Error, RootFsStatus, Slot,
+};
+
+/// How often to look whether a running check has finished.
+const PROBE_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// A check that has to pass before the current slot is considered booted
+/// successfully.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HealthCheck {
+    /// The systemd unit is active.
+    Unit(String),
+    /// The shell command exits successfully.
+    Command(String),
+}
+
+impl HealthCheck {
+    /// Run the check once. A check that doesn't finish within `timeout` is killed,
+    /// together with any processes it started, and fails.
+    pub fn probe(&self, timeout: Duration) -> io::Result<bool> {
+        let mut cmd = match self {
+            HealthCheck::Unit(unit) => {
+                let mut cmd = Command::new("systemctl");
+                cmd.args(["is-active", "--quiet", unit]);
+                cmd
+            }
+            HealthCheck::Command(command) => {
+                let mut cmd = Command::new("sh");
+                cmd.args(["-c", command]);
+                cmd
+            }
+        };
+        let mut child = cmd
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .process_group(0)
+            .spawn()?;
+        let start = Instant::now();
+        loop {
+            if let Some(status) = child.try_wait()? {
+                return Ok(status.success());
+            }
+            let remaining = timeout.saturating_sub(start.elapsed());
+            if remaining.is_zero() {
+                // The child leads its own process group, so this also kills whatever
+                // the shell started.
+                let pgid =
+                    libc::pid_t::try_from(child.id()).map_err(io::Error::other)?;
+                unsafe { libc::kill(-pgid, libc::SIGKILL) };
+                child.wait()?;
+                return Ok(false);
+            }
+            thread::sleep(PROBE_POLL_INTERVAL.min(remaining));
+        }
+    }
+}
+
+impl fmt::Display for HealthCheck {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HealthCheck::Unit(unit) => write!(f, "unit {unit}"),
+            HealthCheck::Command(command) => write!(f, "command `{command}`"),
+        }
+    }
+}
+
+/// What the [`Watchdog`] did.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// The current slot was not on trial, nothing was changed.
+    NotOnTrial(RootFsStatus),
+    /// All health checks passed and the current slot was marked `Normal`.
+    Confirmed(Slot),
+    /// Some health checks didn't pass within the deadline. The current slot was
+    /// marked `Unbootable`, and the other slot boots next.
+    RolledBack {
+        /// The slot that was rolled back.
+        failed: Slot,
+        /// The slot that boots next.
+        fallback: Slot,
+        /// The checks that didn't pass.
+        unhealthy: Vec<HealthCheck>,
+    },
+}
+
+/// Decides whether the current slot booted successfully.
+#[derive(Debug, Clone)]
+pub struct Watchdog {
+    /// Checks that all have to pass in the same round.
+    pub checks: Vec<HealthCheck>,
+    /// How long the checks have to pass within, counted from the start of
+    /// [`Watchdog::run`].
+    pub deadline: Duration,
+    /// How long to wait before running the checks again after a round in which
+    /// some failed.
+    pub interval: Duration,
+}
+
+impl Watchdog {
+    /// Wait for the health checks of a slot on trial, and confirm or roll it back.
+    /// Returns immediately if the slot isn't on trial.
+    ///
+    /// Checks that fail to run or are still running at the deadline count as
+    /// failed.
+    pub fn run(&self, store: &dyn EfiVarStore) -> Result<Outcome, Error> {
+        self.run_with(store, |check, timeout| {
+            check.probe(timeout).unwrap_or(false)
+        })
+    }
+
+    fn run_with(
+        &self,
+        store: &dyn EfiVarStore,
+        mut probe: impl FnMut(&HealthCheck, Duration) -> bool,
+    ) -> Result<Outcome, Error> {
+        let status = get_current_rootfs_status(store)?;
+        if !status.is_update_done() {
+            return Ok(Outcome::NotOnTrial(status));
+        }
+
+        let start = Instant::now();
+        // A check that passed once may fail again, e.g. a unit that crashes after
+        // starting, so every round runs all of them.
+        let unhealthy = loop {
+            let unhealthy: Vec<_> = self
+                .checks
+                .iter()
+                .filter(|check| {
+                    !probe(check, self.deadline.saturating_sub(start.elapsed()))
+                })
+                .cloned()
+                .collect();
+            if unhealthy.is_empty() {
+                return Ok(Outcome::Confirmed(update::mark_successful(store)?));
+            }
+            let elapsed = start.elapsed();
+            if elapsed >= self.deadline {
+                break unhealthy;
+            }
+            thread::sleep(self.interval.min(self.deadline.saturating_sub(elapsed)));
+        };
+
+        let fallback = update::rollback(store)?;
+        Ok(Outcome::RolledBack {
+            failed: fallback.other(),
+            fallback,
+            unhealthy,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use eyre::Result;
+
+    use super::*;
+    use crate::{
+        get_current_slot, get_next_boot_slot, get_retry_count,
+        set_current_rootfs_status, tests::booted_from_a,
+    };
+
+    fn watchdog(deadline: Duration) -> Watchdog {
+        Watchdog {
+            checks: vec![
+                HealthCheck::Unit("worldcoin-core.service".to_owned()),
+                HealthCheck::Command("true".to_owned()),
+            ],
+            deadline,
+            interval: Duration::ZERO,
+        }
+    }
+
+    #[test]
+    fn test_not_on_trial() -> Result<()> {
+        let store = booted_from_a();
+        let vars = store.vars();
+        let outcome = watchdog(Duration::ZERO).run_with(&store, |_, _| false)?;
+        assert_eq!(outcome, Outcome::NotOnTrial(RootFsStatus::Normal));
+        assert_eq!(store.vars(), vars);
+        Ok(())
+    }
+
+    #[test]
+    fn test_confirmed() -> Result<()> {
+        let store = booted_from_a();
+        set_current_rootfs_status(&store, RootFsStatus::UpdateDone)?;
+
+        // The unit takes a few attempts to come up.
+        let mut attempts = 0;
+        let outcome =
+            watchdog(Duration::from_secs(60)).run_with(&store, |check, _| {
+                attempts += 1;
+                matches!(check, HealthCheck::Command(_)) || attempts > 5
+            })?;
+        assert_eq!(outcome, Outcome::Confirmed(Slot::A));
+        assert!(get_current_rootfs_status(&store)?.is_normal());
+        assert_eq!(get_retry_count(&store, Slot::A)?, 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_rolled_back() -> Result<()> {
+        let store = booted_from_a();
+        set_current_rootfs_status(&store, RootFsStatus::UpdateDone)?;
+
+        let outcome = watchdog(Duration::ZERO)
+            .run_with(&store, |check, _| matches!(check, HealthCheck::Command(_)))?;
+        assert_eq!(
+            outcome,
+            Outcome::RolledBack {
+                failed: Slot::A,
+                fallback: Slot::B,
+                unhealthy: vec![HealthCheck::Unit("worldcoin-core.service".to_owned())],
+            }
+        );
+        assert!(get_current_rootfs_status(&store)?.is_unbootable());
+        assert_eq!(get_current_slot(&store)?, Slot::A);
+        assert_eq!(get_next_boot_slot(&store)?, Slot::B);
+        Ok(())
+    }
+
+    #[test]
+    fn test_checks_pass_in_the_same_round() -> Result<()> {
+        let store = booted_from_a();
+        set_current_rootfs_status(&store, RootFsStatus::UpdateDone)?;
+
+        // Each check passes every other round, but never both in the same one.
+        let mut attempts = 0;
+        let outcome =
+            watchdog(Duration::from_millis(50)).run_with(&store, |check, _| {
+                attempts += 1;
+                let round = (attempts - 1) / 2;
+                matches!(check, HealthCheck::Command(_)) == (round % 2 == 0)
+            })?;
+        assert!(matches!(outcome, Outcome::RolledBack { .. }));
+        assert!(get_current_rootfs_status(&store)?.is_unbootable());
+        Ok(())
+    }
+
+    #[test]
+    fn test_probe_command() -> Result<()> {
+        let timeout = Duration::from_secs(10);
+        assert!(HealthCheck::Command("exit 0".to_owned()).probe(timeout)?);
+        assert!(!HealthCheck::Command("exit 1".to_owned()).probe(timeout)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_probe_timeout() -> Result<()> {
+        let start = Instant::now();
+        let check = HealthCheck::Command("sleep 10; true".to_owned());
+        assert!(!check.probe(Duration::from_millis(50))?);
+        assert!(
+            start.elapsed() < Duration::from_secs(5),
+            "the check wasn't killed"
+        );
+        Ok(())
+    }
+}